@@ -3,27 +3,169 @@
 export type Procedures = {
     queries: 
         { key: "activities", input: CourseIdentifier, result: Activity[] } | 
+        { key: "common-free-slots", input: CommonFreeSlotsQuery, result: CommonFreeSlot[] } | 
+        { key: "course-snapshots", input: CourseIdentifier, result: SnapshotSummary[] } | 
         { key: "courses", input: CoursesQuery, result: { [key: string]: Course } } | 
+        { key: "decode-calendar-query", input: string, result: CalendarSubscription[] } | 
+        { key: "diff-snapshots", input: SnapshotDiffQuery, result: ActivityChanges | null } | 
         { key: "encode-calendar-query", input: CalendarQuery[], result: string } | 
-        { key: "semesters", input: never, result: SemestersWithCurrent },
-    mutations: never,
-    subscriptions: never
+        { key: "encode-calendar-subscriptions", input: CalendarSubscription[], result: string } | 
+        { key: "exams", input: ExamIdentifier, result: Exam[] } | 
+        { key: "free-busy", input: FreeBusyQuery, result: FreeBusy } | 
+        { key: "free-rooms", input: FreeRoomsQuery, result: FreeRoom[] } | 
+        { key: "optimize-groups", input: GroupOptimizerQuery, result: GroupOptimization } | 
+        { key: "programme-activities", input: ProgrammeActivitiesQuery, result: Activity[] } | 
+        { key: "programme-timetable", input: ProgrammeIdentifier, result: Activity[] } | 
+        { key: "room-activities", input: RoomActivitiesQuery, result: Activity[] } | 
+        { key: "search-courses", input: CourseSearchQuery, result: CourseSearchResult[] } | 
+        { key: "search-staff", input: StaffSearchQuery, result: StaffMember[] } | 
+        { key: "semesters", input: never, result: SemestersWithCurrent } | 
+        { key: "staff-activities", input: StaffIdentifier, result: Activity[] } | 
+        { key: "student-group-hierarchy", input: CourseIdentifier, result: ProgrammeGroups[] } | 
+        { key: "student-groups", input: CourseIdentifier, result: StudentGroupSummary[] } | 
+        { key: "timetable-conflicts", input: CalendarQuery[], result: TimetableConflicts } | 
+        { key: "week-view", input: WeekViewQuery, result: WeekView[] },
+    mutations: 
+        { key: "register-email-notifications", input: EmailNotificationRequest, result: null } | 
+        { key: "register-webhook", input: WebhookSubscriptionRequest, result: WebhookRegistration } | 
+        { key: "unregister-webhook", input: WebhookUnsubscribeRequest, result: boolean },
+    subscriptions: 
+        { key: "selection-changes", input: string, result: CourseChanges }
 };
 
+export type GroupOptimization = { selections: GroupSelection[]; exhaustive: boolean }
+
+export type ConflictingActivity = { course: CourseIdentifier; activityId: string; title: string; start: string; end: string }
+
+export type CommonFreeSlot = { weekday: number; start: string; end: string; durationMinutes: number; freeDates: string[]; totalWeeks: number }
+
+export type FreeRoom = { room: Room; freeSlots: TimeSlot[] }
+
+export type CourseSearchResult = { code: string; course: Course }
+
+export type WebhookSubscriptionRequest = { identifier: CourseIdentifier; studentGroups?: string[]; url: string }
+
+export type ExamIdentifier = { courseCode: string; semester: string }
+
+/**
+ * One entry in an encoded calendar query
+ */
+export type CalendarSubscription = { course: CalendarQuery } | { programme: ProgrammeCalendarQuery } | { room: RoomIdentifier } | { staff: StaffIdentifier } | { custom: CustomEvent }
+
 export type CoursesQuery = { semester: string }
 
+export type MovedActivity = { before: Activity; after: Activity }
+
+/**
+ * Difference between two fetches of the same timetable
+ */
+export type ActivityChanges = { added: Activity[]; removed: Activity[]; moved: MovedActivity[] }
+
+export type WeekView = { year: number; week: number; days: DayView[] }
+
+/**
+ * When a custom event takes place, with times in Norwegian local time
+ */
+export type CustomEventSchedule = { weekly: { weekday: number; first_date: string; last_date: string; interval_weeks: number } } | { dates: { dates: string[] } }
+
+/**
+ * A distinct version of a course timetable, kept from when it was first fetched
+ */
+export type SnapshotSummary = { id: number; fetchedAt: string; activityCount: number }
+
+export type GroupOptimizerQuery = { courses: OptimizerCourse[]; minimizeDaysOnCampus: boolean; earliestStartHour: number | null; maxAlternatives: number | null }
+
+export type CourseChanges = { identifier: CourseIdentifier; detectedAt: string; changes: ActivityChanges }
+
+export type WeekViewQuery = { queries: CalendarQuery[]; year: number; week: number; weekCount: number | null }
+
+export type ProgrammeCalendarQuery = { identifier: ProgrammeIdentifier; customName: string | null }
+
+export type ProgrammeYearGroups = { year: number | null; groups: StudentGroupSummary[] }
+
+export type OptimizerCourse = { identifier: CourseIdentifier; customName: string | null; baseGroups: string[] }
+
+export type ProgrammeGroups = { programme: string; years: ProgrammeYearGroups[] }
+
+export type SnapshotDiffQuery = { identifier: CourseIdentifier; from: number; to: number }
+
+/**
+ * A student group string like `BPROG_2` split into its parts
+ */
+export type StudentGroup = { raw: string; programme: string; year: number | null; parallel: string | null }
+
+export type TimeSlot = { start: string; end: string }
+
+export type PlacedActivity = { name: string; activity: Activity; column: number; columnCount: number }
+
+export type StaffMember = { id: string | null; firstName: string; lastName: string }
+
+export type StaffIdentifier = { staffId: string; semester: string }
+
+export type DayView = { date: string; activities: PlacedActivity[]; hours: number; firstStart: string | null; lastEnd: string | null }
+
+export type CourseConflictShare = { course: CourseIdentifier; totalMinutes: number; conflictingMinutes: number; share: number; weeks: number[] }
+
+export type Exam = { id: string; courseCode: string; assessmentForm: string; date: string; start: string | null; end: string | null; location: string | null }
+
 export type CourseIdentifier = { courseCode: string; courseTerm: number; semester: string }
 
-export type CalendarQuery = { identifier: CourseIdentifier; studentGroups: string[]; customName: string | null }
+export type CourseSearchQuery = { semester: string; query: string; limit: number | null }
 
-export type Course = { name: string; amountOfTerms: number }
+export type Semester = { name: string }
 
-export type StaffMember = { firstName: string; lastName: string }
+export type WebhookRegistration = { id: string; secret: string }
 
 export type Activity = { id: string; courseCode: string; week: number; start: string; end: string; title: string; summary: string; staffMembers: StaffMember[]; studentGroups: string[]; rooms: Room[] }
 
-export type Semester = { name: string }
+export type RoomActivitiesQuery = { identifier: RoomIdentifier; from: string | null; to: string | null }
+
+export type StaffSearchQuery = { semester: string; query: string; limit: number | null }
+
+export type CommonFreeSlotsQuery = { queries: string[]; from: string; to: string; weekdays?: number[]; earliestHour: number; latestHour: number; minDurationMinutes: number; limit: number | null }
+
+export type BusyInterval = { start: string; end: string; busyCalendars: number[] }
+
+export type CalendarQuery = { identifier: CourseIdentifier; studentGroups: string[]; customName: string | null; includeExams?: boolean }
+
+/**
+ * A study programme and year, e.g. BPROG year 2, which has a timetable across all its courses
+ */
+export type ProgrammeIdentifier = { programme: string; year: number; semester: string }
+
+export type Course = { name: string; amountOfTerms: number }
+
+export type RoomIdentifier = { roomId: string; semester: string }
+
+export type TimetableConflicts = { conflicts: ActivityConflict[]; courses: CourseConflictShare[] }
+
+export type FreeBusy = { from: string | null; to: string | null; calendars: string[]; busy: BusyInterval[] }
+
+export type StudentGroupSummary = { group: StudentGroup; activityCount: number; activityKinds: string[]; weeks: number[] }
+
+export type FreeBusyQuery = { queries: string[]; from: string | null; to: string | null; anonymise?: boolean }
+
+export type ActivityConflict = { first: ConflictingActivity; second: ConflictingActivity; week: number; overlap: TimeSlot }
+
+export type WebhookUnsubscribeRequest = { id: string; secret: string }
+
+/**
+ * A user-defined event, like a study group session, kept in the encoded query
+ */
+export type CustomEvent = { id: string; title: string; location: string | null; description: string | null; startTime: string; endTime: string; schedule: CustomEventSchedule }
+
+export type EmailNotificationRequest = { email: string; query: string }
+
+/**
+ * There is no upstream list of rooms, so only rooms seen in timetables cached for the semester
+ * are considered
+ */
+export type FreeRoomsQuery = { semester: string; building: string; from: string; to: string; minDurationMinutes: number | null }
+
+export type ProgrammeActivitiesQuery = { identifier: CourseIdentifier; programme: string; year: number | null }
 
 export type SemestersWithCurrent = { semesters: { [key: string]: Semester }; currentSemester: string }
 
-export type Room = { name: string; buildingName: string; url: string }
+export type Room = { id: string | null; name: string; buildingName: string; url: string }
+
+export type GroupSelection = { queries: CalendarQuery[]; conflictingMinutes: number; daysOnCampus: number; earlyActivities: number }
@@ -1,64 +1,147 @@
 use crate::error::AppResult;
-use crate::{
-    fetch::activities::fetch_activities,
-    shared_types::{Activity, CourseIdentifier},
-};
+use crate::fetch::http_client::{backoff_delay, HttpClient};
+use crate::fetch::timetable::{fetch_timetable, TimetableQuery};
+use crate::shared_types::{Activity, CourseIdentifier};
+use chrono::{DateTime, Utc};
 use mini_moka::sync::Cache;
-use reqwest::Client;
 use std::sync::Arc;
 use std::time::Duration;
 use time::ext::NumericalStdDuration;
+use tokio::sync::broadcast;
 use tokio::time::sleep;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
-pub struct ActivitiesCache {
-    client: Client,
-    cache: Cache<CourseIdentifier, Arc<Vec<Activity>>>,
+/// How long fetched timetables are kept before being fetched again
+pub fn activities_time_to_live() -> Duration {
+    2.std_hours()
 }
 
-impl ActivitiesCache {
-    pub fn new(client: Client) -> Self {
-        let cache = Cache::builder().time_to_live(2.std_hours()).build();
+/// Fetches that can be waiting for listeners before the oldest are dropped
+const FETCH_CHANNEL_CAPACITY: usize = 64;
 
-        Self { client, cache }
+/// Caches fetched timetables, by default the activities of courses
+pub struct ActivitiesCache<Identifier: TimetableQuery = CourseIdentifier> {
+    client: HttpClient,
+    cache: Cache<Identifier, (Arc<Vec<Activity>>, DateTime<Utc>)>,
+    fetches: broadcast::Sender<(Identifier, Arc<Vec<Activity>>)>,
+}
+
+impl<Identifier: TimetableQuery> ActivitiesCache<Identifier> {
+    pub fn new(client: HttpClient) -> Self {
+        let cache = Cache::builder()
+            .time_to_live(activities_time_to_live())
+            .build();
+
+        let (fetches, _) = broadcast::channel(FETCH_CHANNEL_CAPACITY);
+
+        Self {
+            client,
+            cache,
+            fetches,
+        }
+    }
+
+    /// Receives every timetable successfully fetched from upstream from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<(Identifier, Arc<Vec<Activity>>)> {
+        self.fetches.subscribe()
+    }
+
+    /// Every timetable currently in the cache
+    pub fn cached_timetables(&self) -> Vec<(Identifier, Arc<Vec<Activity>>)> {
+        self.cache
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().0.clone()))
+            .collect()
+    }
+
+    /// When the cached timetable was fetched, if it is still cached
+    pub fn fetched_at(&self, identifier: &Identifier) -> Option<DateTime<Utc>> {
+        self.cache.get(identifier).map(|(_, fetched_at)| fetched_at)
+    }
+
+    /// Caches the timetable as if it was just fetched, for tests that can't reach upstream
+    #[cfg(test)]
+    pub fn insert(&self, identifier: Identifier, activities: Vec<Activity>) {
+        self.cache
+            .insert(identifier, (Arc::new(activities), Utc::now()));
+    }
+
+    pub async fn get_or_fetch(&self, identifier: Identifier) -> AppResult<Arc<Vec<Activity>>> {
+        let (activities, _) = self.get_or_fetch_with_time(identifier).await?;
+
+        Ok(activities)
     }
 
-    pub async fn get_or_fetch(
+    /// Activities along with when they were fetched, which is now if fetching failed
+    pub async fn get_or_fetch_with_time(
         &self,
-        course_identifier: CourseIdentifier,
-    ) -> AppResult<Arc<Vec<Activity>>> {
-        if let Some(cache_result) = self.cache.get(&course_identifier) {
+        identifier: Identifier,
+    ) -> AppResult<(Arc<Vec<Activity>>, DateTime<Utc>)> {
+        if let Some(cache_result) = self.cache.get(&identifier) {
             return Ok(cache_result);
         }
 
-        info!("Fetching activities for {:?}", &course_identifier);
+        self.refresh(identifier).await
+    }
+
+    /// Fetches the timetable, retrying while upstream answers with nothing
+    ///
+    /// `None` if it is still empty after every retry, for timetables that are never empty
+    async fn fetch(&self, identifier: &Identifier) -> AppResult<Option<Vec<Activity>>> {
+        info!("Fetching activities for {:?}", identifier);
 
         const MAX_RETRIES: usize = 5;
 
         for retry in 1..=MAX_RETRIES {
-            let activities = fetch_activities(&course_identifier, &self.client).await?;
-
-            // Insert to cache and return if successful
-            if !activities.is_empty() {
-                let activities = Arc::new(activities);
-
-                self.cache.insert(course_identifier, activities.clone());
+            let activities = fetch_timetable(identifier, &self.client).await?;
 
-                return Ok(activities);
+            if !activities.is_empty() || Identifier::MAY_BE_EMPTY {
+                return Ok(Some(activities));
             }
 
             if retry != MAX_RETRIES {
-                // Sleep and retry
-                sleep(Duration::from_millis(1000)).await;
-                info!("Retrying to fetch activities for {:?}", &course_identifier);
+                // Back off before retrying, longer for every attempt
+                sleep(backoff_delay(retry as u32)).await;
+                info!("Retrying to fetch activities for {:?}", identifier);
             }
         }
 
         error!(
             "Failed to fetch activities for {:?} after {MAX_RETRIES} retries",
-            course_identifier
+            identifier
         );
 
-        Ok(Arc::new(Vec::new()))
+        Ok(None)
+    }
+
+    /// Fetches the timetable even if it is cached, keeping and returning the cached one if
+    /// fetching fails
+    pub async fn refresh(
+        &self,
+        identifier: Identifier,
+    ) -> AppResult<(Arc<Vec<Activity>>, DateTime<Utc>)> {
+        let failed = match self.fetch(&identifier).await {
+            Ok(Some(activities)) => {
+                let fetched = (Arc::new(activities), Utc::now());
+
+                self.cache.insert(identifier.clone(), fetched.clone());
+
+                // Sending only fails when nobody is listening
+                let _ = self.fetches.send((identifier, fetched.0.clone()));
+
+                return Ok(fetched);
+            }
+            Ok(None) => Ok((Arc::new(Vec::new()), Utc::now())),
+            Err(fetch_error) => Err(fetch_error),
+        };
+
+        match self.cache.get(&identifier) {
+            Some(cached) => {
+                warn!("Keeping the cached activities for {:?}", &identifier);
+
+                Ok(cached)
+            }
+            None => failed,
+        }
     }
 }
@@ -1,27 +1,46 @@
 use crate::error::AppResult;
 use crate::fetch::courses::fetch_courses;
+use crate::fetch::http_client::HttpClient;
+use crate::search::course_index::CourseIndex;
 use crate::shared_types::Course;
 use mini_moka::sync::Cache;
-use reqwest::Client;
 use std::collections::HashMap;
 use std::sync::Arc;
 use time::ext::NumericalStdDuration;
 use tracing::info;
 
+#[derive(Debug, Clone)]
+pub struct CachedCourses {
+    pub courses: Arc<HashMap<String, Course>>,
+    pub index: Arc<CourseIndex>,
+}
+
 #[derive(Debug)]
 pub struct CoursesCache {
-    client: Client,
-    cache: Cache<String, Arc<HashMap<String, Course>>>,
+    client: HttpClient,
+    cache: Cache<String, CachedCourses>,
 }
 
 impl CoursesCache {
-    pub async fn new(client: Client) -> Self {
+    pub async fn new(client: HttpClient) -> Self {
         let cache = Cache::builder().time_to_live(2.std_weeks()).build();
 
         Self { client, cache }
     }
 
     pub async fn get_or_fetch(&self, semester: String) -> AppResult<Arc<HashMap<String, Course>>> {
+        let cached_courses = self.get_or_fetch_with_index(semester).await?;
+
+        Ok(cached_courses.courses)
+    }
+
+    pub async fn get_or_fetch_index(&self, semester: String) -> AppResult<Arc<CourseIndex>> {
+        let cached_courses = self.get_or_fetch_with_index(semester).await?;
+
+        Ok(cached_courses.index)
+    }
+
+    async fn get_or_fetch_with_index(&self, semester: String) -> AppResult<CachedCourses> {
         if let Some(cache_result) = self.cache.get(&semester) {
             return Ok(cache_result);
         }
@@ -29,9 +48,14 @@ impl CoursesCache {
         info!("Fetching courses for {semester}");
 
         let courses = fetch_courses(&semester, &self.client).await?;
-        let courses = Arc::new(courses);
+        let index = CourseIndex::new(&courses);
+
+        let cached_courses = CachedCourses {
+            courses: Arc::new(courses),
+            index: Arc::new(index),
+        };
 
-        self.cache.insert(semester.clone(), courses.clone());
-        Ok(courses)
+        self.cache.insert(semester.clone(), cached_courses.clone());
+        Ok(cached_courses)
     }
 }
@@ -0,0 +1,52 @@
+use crate::error::AppResult;
+use crate::fetch::exams::fetch_exams;
+use crate::fetch::http_client::HttpClient;
+use crate::shared_types::{Exam, ExamIdentifier};
+use chrono::{DateTime, Utc};
+use mini_moka::sync::Cache;
+use std::sync::Arc;
+use std::time::Duration;
+use time::ext::NumericalStdDuration;
+use tracing::info;
+
+/// Exam dates change rarely, so they are kept longer than timetables
+pub fn exams_time_to_live() -> Duration {
+    1.std_days()
+}
+
+pub struct ExamsCache {
+    client: HttpClient,
+    cache: Cache<ExamIdentifier, (Arc<Vec<Exam>>, DateTime<Utc>)>,
+}
+
+impl ExamsCache {
+    pub fn new(client: HttpClient) -> Self {
+        let cache = Cache::builder().time_to_live(exams_time_to_live()).build();
+
+        Self { client, cache }
+    }
+
+    pub async fn get_or_fetch(&self, identifier: ExamIdentifier) -> AppResult<Arc<Vec<Exam>>> {
+        let (exams, _) = self.get_or_fetch_with_time(identifier).await?;
+
+        Ok(exams)
+    }
+
+    /// Exams along with when they were fetched
+    pub async fn get_or_fetch_with_time(
+        &self,
+        identifier: ExamIdentifier,
+    ) -> AppResult<(Arc<Vec<Exam>>, DateTime<Utc>)> {
+        if let Some(cache_result) = self.cache.get(&identifier) {
+            return Ok(cache_result);
+        }
+
+        info!("Fetching exams for {:?}", &identifier);
+
+        let exams = fetch_exams(&identifier, &self.client).await?;
+        let fetched = (Arc::new(exams), Utc::now());
+
+        self.cache.insert(identifier, fetched.clone());
+        Ok(fetched)
+    }
+}
@@ -1,4 +1,5 @@
 pub mod activities_cache;
 pub mod courses_cache;
+pub mod exams_cache;
+pub mod prefetch;
 pub mod semesters_cache;
-
@@ -0,0 +1,191 @@
+use crate::caching::activities_cache::activities_time_to_live;
+use crate::shared_types::CourseIdentifier;
+use crate::AppState;
+use chrono::{DateTime, Duration, Utc};
+use futures_util::{stream, StreamExt};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use time::ext::NumericalStdDuration;
+use tracing::{error, info};
+
+/// How often the scheduler looks for timetables about to expire
+fn check_interval() -> std::time::Duration {
+    1.std_minutes()
+}
+
+/// Courses not requested for this long are left to expire
+fn recent_window() -> Duration {
+    Duration::days(1)
+}
+
+/// How long before expiry timetables are fetched again, at the latest
+fn refresh_margin() -> Duration {
+    Duration::minutes(10)
+}
+
+/// Upper bound of the random extra time before expiry, so refreshes don't all happen at once
+const MAX_JITTER_SECONDS: u32 = 20 * 60;
+
+/// Upstream fetches the scheduler runs at the same time
+const MAX_CONCURRENT_REFRESHES: usize = 4;
+
+fn random_jitter() -> Duration {
+    let mut bytes = [0; 4];
+
+    // Without randomness, every course just gets the same jitter
+    let _ = SystemRandom::new().fill(&mut bytes);
+
+    Duration::seconds((u32::from_le_bytes(bytes) % MAX_JITTER_SECONDS).into())
+}
+
+struct RequestedCourse {
+    last_requested: DateTime<Utc>,
+    jitter: Duration,
+}
+
+/// Keeps recently requested course timetables cached, so subscribers don't wait for upstream
+#[derive(Default)]
+pub struct PrefetchScheduler {
+    requested: Mutex<HashMap<CourseIdentifier, RequestedCourse>>,
+}
+
+impl PrefetchScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks a course as requested, which should only happen for courses that exist
+    pub fn note_request(&self, identifier: &CourseIdentifier) {
+        let mut requested = self
+            .requested
+            .lock()
+            .expect("Prefetch scheduler lock should not be poisoned");
+
+        let now = Utc::now();
+
+        requested
+            .entry(identifier.clone())
+            .and_modify(|course| course.last_requested = now)
+            .or_insert_with(|| RequestedCourse {
+                last_requested: now,
+                jitter: random_jitter(),
+            });
+    }
+
+    pub fn forget(&self, identifier: &CourseIdentifier) {
+        self.requested
+            .lock()
+            .expect("Prefetch scheduler lock should not be poisoned")
+            .remove(identifier);
+    }
+
+    /// Recently requested courses that expire soon, given when each was fetched
+    ///
+    /// Courses no longer requested recently are forgotten
+    pub fn due(
+        &self,
+        now: DateTime<Utc>,
+        fetched_at: impl Fn(&CourseIdentifier) -> Option<DateTime<Utc>>,
+    ) -> Vec<CourseIdentifier> {
+        let mut requested = self
+            .requested
+            .lock()
+            .expect("Prefetch scheduler lock should not be poisoned");
+
+        requested.retain(|_, course| now - course.last_requested < recent_window());
+
+        let time_to_live = Duration::from_std(activities_time_to_live())
+            .expect("Time to live should fit in a chrono duration");
+
+        requested
+            .iter()
+            .filter(|(identifier, course)| match fetched_at(identifier) {
+                Some(fetched_at) => {
+                    now >= fetched_at + time_to_live - refresh_margin() - course.jitter
+                }
+                None => true,
+            })
+            .map(|(identifier, _)| identifier.clone())
+            .collect()
+    }
+}
+
+/// Warms the courses of the current semester, then refreshes requested timetables before expiry
+pub fn spawn_prefetch(app_state: AppState) {
+    tokio::spawn(async move {
+        match app_state.semesters_cache.get_or_fetch().await {
+            Ok(semesters) => {
+                let semester = semesters.current_semester.clone();
+
+                if let Err(fetch_error) = app_state.courses_cache.get_or_fetch(semester).await {
+                    error!("Failed to warm courses cache: {fetch_error:?}");
+                }
+            }
+            Err(fetch_error) => error!("Failed to get current semester: {fetch_error:?}"),
+        }
+
+        let mut interval = tokio::time::interval(check_interval());
+
+        loop {
+            interval.tick().await;
+
+            let activities_cache = &app_state.activities_cache;
+            let scheduler = &app_state.prefetch_scheduler;
+
+            let due = scheduler.due(Utc::now(), |identifier| {
+                activities_cache.fetched_at(identifier)
+            });
+
+            if due.is_empty() {
+                continue;
+            }
+
+            info!("Prefetching {} course timetables", due.len());
+
+            stream::iter(due)
+                .for_each_concurrent(MAX_CONCURRENT_REFRESHES, |identifier| async move {
+                    match activities_cache.refresh(identifier.clone()).await {
+                        // Nothing to keep warm if the course is gone, and retrying only costs.
+                        // A failed refresh of a cached course returns the cached activities
+                        Ok((activities, _)) if activities.is_empty() => {
+                            scheduler.forget(&identifier)
+                        }
+                        Ok(_) => {}
+                        Err(fetch_error) => {
+                            error!("Failed to prefetch {identifier:?}: {fetch_error:?}")
+                        }
+                    }
+                })
+                .await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_due() {
+        let scheduler = PrefetchScheduler::new();
+        let identifier = CourseIdentifier {
+            course_code: "TDT4100".to_owned(),
+            course_term: 1,
+            semester: "23v".to_owned(),
+        };
+        let now = Utc::now();
+
+        scheduler.note_request(&identifier);
+
+        assert!(scheduler.due(now, |_| Some(now)).is_empty());
+        assert_eq!(scheduler.due(now, |_| None), vec![identifier.clone()]);
+        assert_eq!(
+            scheduler.due(now, |_| Some(now - Duration::minutes(110))),
+            vec![identifier]
+        );
+
+        // Forgotten once no longer requested
+        assert!(scheduler.due(now + Duration::days(2), |_| None).is_empty());
+    }
+}
@@ -1,7 +1,7 @@
 use crate::error::AppResult;
+use crate::fetch::http_client::HttpClient;
 use crate::fetch::semesters::fetch_semesters;
 use crate::shared_types::SemestersWithCurrent;
-use reqwest::Client;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
@@ -9,13 +9,13 @@ use tracing::info;
 
 #[derive(Debug)]
 pub struct SemestersCache {
-    client: Client,
+    client: HttpClient,
     last_time_fetched: RwLock<Instant>,
     fetched_semesters: RwLock<Arc<SemestersWithCurrent>>,
 }
 
 impl SemestersCache {
-    pub async fn new(client: Client) -> anyhow::Result<Self> {
+    pub async fn new(client: HttpClient) -> anyhow::Result<Self> {
         let fetched_semesters = fetch_semesters(&client).await?;
         let last_time_fetched = Instant::now();
 
@@ -0,0 +1,525 @@
+use crate::caldav::resources::{collection_tag, event_resources, percent_decode, EventResource};
+use crate::caldav::xml::{escape_xml, parse_xml, XmlElement};
+use crate::calendar::calendar_handler::build_calendar;
+use crate::calendar::encode_query::decode_calendar_query;
+use crate::calendar::resolve_subscriptions::{resolve_subscriptions, ResolvedSubscription};
+use crate::error::AppResult;
+use crate::AppState;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use itertools::Itertools;
+use mini_moka::sync::Cache;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use time::ext::NumericalStdDuration;
+
+const DAV: &str = "DAV:";
+const CALDAV: &str = "urn:ietf:params:xml:ns:caldav";
+const CALENDAR_SERVER: &str = "http://calendarserver.org/ns/";
+
+const ALLOWED_METHODS: &str = "OPTIONS, GET, HEAD, PROPFIND, REPORT";
+
+const COLLECTION_PROPERTIES: [(&str, &str); 9] = [
+    (DAV, "resourcetype"),
+    (DAV, "displayname"),
+    (DAV, "getetag"),
+    (DAV, "current-user-principal"),
+    (DAV, "current-user-privilege-set"),
+    (DAV, "supported-report-set"),
+    (CALDAV, "supported-calendar-component-set"),
+    (CALENDAR_SERVER, "getctag"),
+    (DAV, "getcontenttype"),
+];
+
+const RESOURCE_PROPERTIES: [(&str, &str); 3] = [
+    (DAV, "resourcetype"),
+    (DAV, "getetag"),
+    (DAV, "getcontenttype"),
+];
+
+/// Properties returned by reports that don't ask for any in particular
+const REPORT_PROPERTIES: [(&str, &str); 2] = [(DAV, "getetag"), (CALDAV, "calendar-data")];
+
+/// A calendar collection backed by an encoded calendar query
+struct Collection {
+    href: String,
+    display_name: String,
+    ctag: String,
+    resources: Vec<EventResource>,
+}
+
+impl Collection {
+    fn resource_href(&self, resource: &EventResource) -> String {
+        format!("{}{}", self.href, resource.name)
+    }
+
+    fn find_resource(&self, name: &str) -> Option<&EventResource> {
+        let name = percent_decode(name);
+
+        self.resources
+            .iter()
+            .find(|resource| percent_decode(&resource.name) == name)
+    }
+}
+
+async fn load_collection(app_state: &AppState, query: &str) -> AppResult<Collection> {
+    let subscriptions = decode_calendar_query(query)?;
+    let resolved_subscriptions = resolve_subscriptions(app_state, subscriptions).await?;
+
+    let calendar = build_calendar(&resolved_subscriptions, false);
+    let resources = event_resources(&calendar);
+
+    Ok(Collection {
+        href: format!("/caldav/{query}/"),
+        display_name: resolved_subscriptions
+            .iter()
+            .map(ResolvedSubscription::display_name)
+            .join(", "),
+        ctag: collection_tag(&resources),
+        resources,
+    })
+}
+
+/// Collections are reused briefly, as a client syncing a calendar sends many requests in a row
+fn collection_time_to_live() -> Duration {
+    1.std_minutes()
+}
+
+/// Recently loaded collections by encoded calendar query
+pub struct CollectionCache {
+    cache: Cache<String, Arc<Collection>>,
+}
+
+impl Default for CollectionCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CollectionCache {
+    pub fn new() -> Self {
+        let cache = Cache::builder()
+            .time_to_live(collection_time_to_live())
+            .build();
+
+        Self { cache }
+    }
+
+    async fn get_or_load(&self, app_state: &AppState, query: &str) -> AppResult<Arc<Collection>> {
+        if let Some(collection) = self.cache.get(&query.to_owned()) {
+            return Ok(collection);
+        }
+
+        let collection = Arc::new(load_collection(app_state, query).await?);
+
+        self.cache.insert(query.to_owned(), collection.clone());
+        Ok(collection)
+    }
+}
+
+/// Properties asked for in a request body, where `None` means all of them
+fn requested_properties(root: Option<&XmlElement>) -> Option<Vec<(String, String)>> {
+    let prop = root?.child(DAV, "prop")?;
+
+    let properties = prop
+        .children
+        .iter()
+        .map(|property| (property.namespace.clone(), property.name.clone()))
+        .collect();
+
+    Some(properties)
+}
+
+fn property_tag(namespace: &str, name: &str, content: &str) -> String {
+    let prefix = match namespace {
+        DAV => "d",
+        CALDAV => "c",
+        CALENDAR_SERVER => "cs",
+        "" => return format!("<{name} xmlns=\"\">{content}</{name}>"),
+        _ => {
+            return if content.is_empty() {
+                format!("<x:{name} xmlns:x=\"{}\"/>", escape_xml(namespace))
+            } else {
+                format!(
+                    "<x:{name} xmlns:x=\"{}\">{content}</x:{name}>",
+                    escape_xml(namespace)
+                )
+            };
+        }
+    };
+
+    if content.is_empty() {
+        format!("<{prefix}:{name}/>")
+    } else {
+        format!("<{prefix}:{name}>{content}</{prefix}:{name}>")
+    }
+}
+
+fn collection_property(collection: &Collection, namespace: &str, name: &str) -> Option<String> {
+    let content = match (namespace, name) {
+        (DAV, "resourcetype") => "<d:collection/><c:calendar/>".to_owned(),
+        (DAV, "displayname") => escape_xml(&collection.display_name),
+        (DAV, "getetag") | (CALENDAR_SERVER, "getctag") => escape_xml(&collection.ctag),
+        (DAV, "getcontenttype") => "text/calendar; charset=utf-8".to_owned(),
+        (DAV, "current-user-principal") => {
+            format!("<d:href>{}</d:href>", escape_xml(&collection.href))
+        }
+        (DAV, "current-user-privilege-set") => {
+            "<d:privilege><d:read/></d:privilege><d:privilege><d:read-current-user-privilege-set/></d:privilege>"
+                .to_owned()
+        }
+        (DAV, "supported-report-set") => {
+            "<d:supported-report><d:report><c:calendar-query/></d:report></d:supported-report>\
+             <d:supported-report><d:report><c:calendar-multiget/></d:report></d:supported-report>"
+                .to_owned()
+        }
+        (CALDAV, "supported-calendar-component-set") => "<c:comp name=\"VEVENT\"/>".to_owned(),
+        _ => return None,
+    };
+
+    Some(property_tag(namespace, name, &content))
+}
+
+fn resource_property(resource: &EventResource, namespace: &str, name: &str) -> Option<String> {
+    let content = match (namespace, name) {
+        (DAV, "resourcetype") => String::new(),
+        (DAV, "getetag") => escape_xml(&resource.etag),
+        (DAV, "getcontenttype") => "text/calendar; charset=utf-8; component=VEVENT".to_owned(),
+        (CALDAV, "calendar-data") => escape_xml(&resource.calendar_data()),
+        _ => return None,
+    };
+
+    Some(property_tag(namespace, name, &content))
+}
+
+/// A `response` element with found properties and a separate status for unknown ones
+fn property_response(
+    href: &str,
+    requested: &[(String, String)],
+    property: impl Fn(&str, &str) -> Option<String>,
+) -> String {
+    let mut found = String::new();
+    let mut missing = String::new();
+
+    for (namespace, name) in requested {
+        match property(namespace, name) {
+            Some(value) => found += &value,
+            None => missing += &property_tag(namespace, name, ""),
+        }
+    }
+
+    let mut response = format!("<d:response><d:href>{}</d:href>", escape_xml(href));
+
+    if !found.is_empty() {
+        let _ = write!(
+            response,
+            "<d:propstat><d:prop>{found}</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>"
+        );
+    }
+
+    if !missing.is_empty() {
+        let _ = write!(
+            response,
+            "<d:propstat><d:prop>{missing}</d:prop><d:status>HTTP/1.1 404 Not Found</d:status></d:propstat>"
+        );
+    }
+
+    response + "</d:response>"
+}
+
+fn not_found_response(href: &str) -> String {
+    format!(
+        "<d:response><d:href>{}</d:href><d:status>HTTP/1.1 404 Not Found</d:status></d:response>",
+        escape_xml(href)
+    )
+}
+
+fn multistatus(responses: Vec<String>) -> Response {
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+         <d:multistatus xmlns:d=\"{DAV}\" xmlns:c=\"{CALDAV}\" xmlns:cs=\"{CALENDAR_SERVER}\">{}</d:multistatus>",
+        responses.concat()
+    );
+
+    (
+        StatusCode::MULTI_STATUS,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        body,
+    )
+        .into_response()
+}
+
+fn owned_properties(properties: &[(&str, &str)]) -> Vec<(String, String)> {
+    properties
+        .iter()
+        .map(|(namespace, name)| (namespace.to_string(), name.to_string()))
+        .collect()
+}
+
+/// Request bodies that aren't well-formed XML
+struct InvalidBody;
+
+impl IntoResponse for InvalidBody {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, "Invalid XML body").into_response()
+    }
+}
+
+fn parse_body(body: &str) -> Result<Option<XmlElement>, InvalidBody> {
+    if body.trim().is_empty() {
+        return Ok(None);
+    }
+
+    match parse_xml(body) {
+        Some(root) => Ok(Some(root)),
+        None => Err(InvalidBody),
+    }
+}
+
+fn options_response() -> Response {
+    (
+        StatusCode::OK,
+        [
+            (header::ALLOW, ALLOWED_METHODS),
+            (header::HeaderName::from_static("dav"), "1, calendar-access"),
+        ],
+    )
+        .into_response()
+}
+
+fn method_not_allowed() -> Response {
+    (
+        StatusCode::METHOD_NOT_ALLOWED,
+        [(header::ALLOW, ALLOWED_METHODS)],
+    )
+        .into_response()
+}
+
+fn calendar_response(etag: &str, headers: &HeaderMap, body: String) -> Response {
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag));
+
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+
+    (
+        [
+            (header::CONTENT_TYPE, "text/calendar; charset=utf-8"),
+            (header::ETAG, etag),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+fn propfind_collection(collection: &Collection, headers: &HeaderMap, body: &str) -> Response {
+    let root = match parse_body(body) {
+        Ok(root) => root,
+        Err(invalid) => return invalid.into_response(),
+    };
+
+    let requested = requested_properties(root.as_ref());
+
+    let collection_requested = requested
+        .clone()
+        .unwrap_or_else(|| owned_properties(&COLLECTION_PROPERTIES));
+    let mut responses = vec![property_response(
+        &collection.href,
+        &collection_requested,
+        |namespace, name| collection_property(collection, namespace, name),
+    )];
+
+    let depth = headers
+        .get("depth")
+        .and_then(|depth| depth.to_str().ok())
+        .unwrap_or("infinity");
+
+    if depth != "0" {
+        let resource_requested =
+            requested.unwrap_or_else(|| owned_properties(&RESOURCE_PROPERTIES));
+
+        responses.extend(collection.resources.iter().map(|resource| {
+            property_response(
+                &collection.resource_href(resource),
+                &resource_requested,
+                |namespace, name| resource_property(resource, namespace, name),
+            )
+        }));
+    }
+
+    multistatus(responses)
+}
+
+fn parse_time_range_bound(value: Option<&String>) -> Option<DateTime<Utc>> {
+    let value = value?;
+
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ")
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+fn calendar_query<'a>(collection: &'a Collection, root: &XmlElement) -> Vec<&'a EventResource> {
+    // Only events are served, so filters on other components match nothing
+    let only_events = root
+        .descendants(CALDAV, "comp-filter")
+        .iter()
+        .all(|filter| {
+            matches!(
+                filter.attributes.get("name").map(String::as_str),
+                Some("VCALENDAR" | "VEVENT")
+            )
+        });
+
+    if !only_events {
+        return Vec::new();
+    }
+
+    let time_range = root.descendants(CALDAV, "time-range").into_iter().next();
+
+    let (start, end) = match time_range {
+        Some(time_range) => (
+            parse_time_range_bound(time_range.attributes.get("start")),
+            parse_time_range_bound(time_range.attributes.get("end")),
+        ),
+        None => (None, None),
+    };
+
+    collection
+        .resources
+        .iter()
+        .filter(|resource| resource.overlaps(start, end))
+        .collect()
+}
+
+fn report(collection: &Collection, body: &str) -> Response {
+    let root = match parse_body(body) {
+        Ok(Some(root)) => root,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Missing report body").into_response(),
+        Err(invalid) => return invalid.into_response(),
+    };
+
+    let requested =
+        requested_properties(Some(&root)).unwrap_or_else(|| owned_properties(&REPORT_PROPERTIES));
+
+    let resource_response = |resource: &EventResource| {
+        property_response(
+            &collection.resource_href(resource),
+            &requested,
+            |namespace, name| resource_property(resource, namespace, name),
+        )
+    };
+
+    let responses = if root.is(CALDAV, "calendar-query") {
+        calendar_query(collection, &root)
+            .into_iter()
+            .map(resource_response)
+            .collect()
+    } else if root.is(CALDAV, "calendar-multiget") {
+        root.descendants(DAV, "href")
+            .into_iter()
+            .map(|href| {
+                let name = href.text.trim().rsplit('/').next().unwrap_or_default();
+
+                match collection.find_resource(name) {
+                    Some(resource) => resource_response(resource),
+                    None => not_found_response(href.text.trim()),
+                }
+            })
+            .collect()
+    } else {
+        return (
+            StatusCode::FORBIDDEN,
+            [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+            format!("<d:error xmlns:d=\"{DAV}\"><d:supported-report/></d:error>"),
+        )
+            .into_response();
+    };
+
+    multistatus(responses)
+}
+
+/// Read-only CalDAV calendar collection, with one resource per event
+pub async fn caldav_collection_handler(
+    method: Method,
+    Path(query): Path<String>,
+    headers: HeaderMap,
+    State(app_state): State<AppState>,
+    body: String,
+) -> AppResult<Response> {
+    if method == Method::OPTIONS {
+        return Ok(options_response());
+    }
+
+    let collection = app_state
+        .caldav_collections
+        .get_or_load(&app_state, &query)
+        .await?;
+
+    let response = match method.as_str() {
+        "GET" | "HEAD" => {
+            let mut calendar = icalendar::Calendar::new();
+            calendar.extend(
+                collection
+                    .resources
+                    .iter()
+                    .map(|resource| resource.event.clone()),
+            );
+
+            calendar_response(&collection.ctag, &headers, calendar.to_string())
+        }
+        "PROPFIND" => propfind_collection(&collection, &headers, &body),
+        "REPORT" => report(&collection, &body),
+        _ => method_not_allowed(),
+    };
+
+    Ok(response)
+}
+
+pub async fn caldav_resource_handler(
+    method: Method,
+    Path((query, name)): Path<(String, String)>,
+    headers: HeaderMap,
+    State(app_state): State<AppState>,
+    body: String,
+) -> AppResult<Response> {
+    if method == Method::OPTIONS {
+        return Ok(options_response());
+    }
+
+    let collection = app_state
+        .caldav_collections
+        .get_or_load(&app_state, &query)
+        .await?;
+
+    let Some(resource) = collection.find_resource(&name) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let response = match method.as_str() {
+        "GET" | "HEAD" => calendar_response(&resource.etag, &headers, resource.calendar_data()),
+        "PROPFIND" => {
+            let root = match parse_body(&body) {
+                Ok(root) => root,
+                Err(invalid) => return Ok(invalid.into_response()),
+            };
+
+            let requested = requested_properties(root.as_ref())
+                .unwrap_or_else(|| owned_properties(&RESOURCE_PROPERTIES));
+
+            multistatus(vec![property_response(
+                &collection.resource_href(resource),
+                &requested,
+                |namespace, name| resource_property(resource, namespace, name),
+            )])
+        }
+        _ => method_not_allowed(),
+    };
+
+    Ok(response)
+}
@@ -0,0 +1,3 @@
+pub mod caldav_handler;
+pub mod resources;
+pub mod xml;
@@ -0,0 +1,265 @@
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Europe::Oslo;
+use data_encoding::HEXLOWER;
+use icalendar::{
+    Calendar, CalendarComponent, CalendarDateTime, Component, DatePerhapsTime, Event, Property,
+};
+use itertools::Itertools;
+use ring::digest::{Context, SHA256};
+use std::collections::HashSet;
+
+/// One event of a calendar collection, served as its own `.ics` resource
+pub struct EventResource {
+    pub name: String,
+    pub event: Event,
+    pub etag: String,
+    pub range: Option<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+impl EventResource {
+    pub fn calendar_data(&self) -> String {
+        let mut calendar = Calendar::new();
+        calendar.push(self.event.clone());
+
+        calendar.to_string()
+    }
+
+    pub fn overlaps(&self, start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>) -> bool {
+        let Some((event_start, event_end)) = self.range else {
+            return true;
+        };
+
+        let starts_before_end = match end {
+            Some(end) => event_start < end,
+            None => true,
+        };
+
+        let ends_after_start = match start {
+            Some(start) => event_end > start,
+            None => true,
+        };
+
+        starts_before_end && ends_after_start
+    }
+}
+
+/// Keeps the characters that are safe in a path segment and percent-encodes the rest
+fn resource_name(uid: &str) -> String {
+    let mut name = String::new();
+
+    for byte in uid.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.') {
+            name.push(byte as char);
+        } else {
+            name += &format!("%{byte:02X}");
+        }
+    }
+
+    name + ".ics"
+}
+
+/// Decodes `%XX` escapes, as names may arrive both encoded and decoded
+pub fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let escaped = input
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Adds the field prefixed by its length, so neighbouring fields can't run together
+fn update_field(context: &mut Context, field: &str) {
+    context.update(&(field.len() as u64).to_le_bytes());
+    context.update(field.as_bytes());
+}
+
+fn finish_tag(context: Context) -> String {
+    format!("\"{}\"", HEXLOWER.encode(&context.finish().as_ref()[..16]))
+}
+
+fn hash_property(property: &Property, context: &mut Context) {
+    update_field(context, property.key());
+    update_field(context, property.value());
+
+    for (key, parameter) in property.params().iter().sorted_by_key(|(key, _)| *key) {
+        update_field(context, key);
+        update_field(context, parameter.value());
+    }
+}
+
+/// Content-derived tag, leaving out `DTSTAMP` as it is set to the time of every request
+pub fn event_etag(event: &Event) -> String {
+    let mut context = Context::new(&SHA256);
+
+    for property in event
+        .properties()
+        .values()
+        .chain(event.multi_properties())
+        .filter(|property| property.key() != "DTSTAMP")
+    {
+        hash_property(property, &mut context);
+    }
+
+    finish_tag(context)
+}
+
+/// Tag of the whole collection, changing whenever any event does
+pub fn collection_tag(resources: &[EventResource]) -> String {
+    let mut context = Context::new(&SHA256);
+
+    for resource in resources {
+        update_field(&mut context, &resource.name);
+        update_field(&mut context, &resource.etag);
+    }
+
+    finish_tag(context)
+}
+
+fn date_start(date: NaiveDate) -> Option<DateTime<Utc>> {
+    let local = Oslo
+        .from_local_datetime(&date.and_time(NaiveTime::MIN))
+        .earliest()?;
+
+    Some(local.with_timezone(&Utc))
+}
+
+fn utc_time(time: DatePerhapsTime) -> Option<DateTime<Utc>> {
+    match time {
+        DatePerhapsTime::DateTime(CalendarDateTime::Utc(time)) => Some(time),
+        DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone { date_time, .. })
+        | DatePerhapsTime::DateTime(CalendarDateTime::Floating(date_time)) => Oslo
+            .from_local_datetime(&date_time)
+            .earliest()
+            .map(|time| time.with_timezone(&Utc)),
+        DatePerhapsTime::Date(date) => date_start(date),
+    }
+}
+
+fn event_range(event: &Event) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let start = event.get_start()?;
+
+    // All-day events last until the start of the day after their end date
+    let end = match event.get_end() {
+        Some(DatePerhapsTime::Date(date)) => date_start(date + Duration::days(1)),
+        Some(end) => utc_time(end),
+        None => match start {
+            DatePerhapsTime::Date(date) => date_start(date + Duration::days(1)),
+            _ => None,
+        },
+    };
+
+    let start = utc_time(start)?;
+
+    Some((start, end.unwrap_or(start)))
+}
+
+/// The events of the calendar as resources, skipping repeated events from overlapping subscriptions
+pub fn event_resources(calendar: &Calendar) -> Vec<EventResource> {
+    let mut names = HashSet::new();
+
+    calendar
+        .components
+        .iter()
+        .filter_map(|component| match component {
+            CalendarComponent::Event(event) => Some(event),
+            _ => None,
+        })
+        .filter_map(|event| {
+            let name = resource_name(event.get_uid()?);
+
+            names.insert(name.clone()).then(|| EventResource {
+                name,
+                etag: event_etag(event),
+                range: event_range(event),
+                event: event.clone(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use icalendar::EventLike;
+
+    #[test]
+    fn test_resource_name() {
+        assert_eq!(resource_name("TDT4100-1_23v.F"), "TDT4100-1_23v.F.ics");
+        assert_eq!(resource_name("a/b c"), "a%2Fb%20c.ics");
+        assert_eq!(percent_decode("a%2Fb%20c.ics"), "a/b c.ics");
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+
+    #[test]
+    fn test_etag_ignores_timestamp() {
+        let start = Utc.with_ymd_and_hms(2023, 3, 13, 7, 15, 0).unwrap();
+        let event = |timestamp| {
+            Event::new()
+                .uid("1")
+                .starts(start)
+                .timestamp(timestamp)
+                .done()
+        };
+
+        assert_eq!(
+            event_etag(&event(start)),
+            event_etag(&event(start + Duration::days(1)))
+        );
+    }
+
+    #[test]
+    fn test_overlaps() {
+        let start = Utc.with_ymd_and_hms(2023, 3, 13, 7, 15, 0).unwrap();
+
+        let mut calendar = Calendar::new();
+        calendar.push(
+            Event::new()
+                .uid("1")
+                .starts(start)
+                .ends(start + Duration::hours(2))
+                .done(),
+        );
+        calendar.push(
+            Event::new()
+                .uid("1")
+                .starts(start)
+                .ends(start + Duration::hours(2))
+                .done(),
+        );
+        calendar.push(
+            Event::new()
+                .uid("2")
+                .all_day(NaiveDate::from_ymd_opt(2023, 3, 14).unwrap())
+                .done(),
+        );
+
+        let resources = event_resources(&calendar);
+        assert_eq!(resources.len(), 2);
+
+        assert!(resources[0].overlaps(Some(start + Duration::hours(1)), None));
+        assert!(!resources[0].overlaps(Some(start + Duration::hours(2)), None));
+        assert!(!resources[0].overlaps(None, Some(start)));
+
+        let day_after = Utc.with_ymd_and_hms(2023, 3, 14, 22, 0, 0).unwrap();
+        assert!(resources[1].overlaps(Some(day_after), None));
+        assert!(!resources[1].overlaps(Some(day_after + Duration::hours(1)), None));
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+/// An element of a parsed request body, with its namespace prefix resolved
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct XmlElement {
+    pub namespace: String,
+    pub name: String,
+    pub attributes: HashMap<String, String>,
+    pub children: Vec<XmlElement>,
+    pub text: String,
+}
+
+impl XmlElement {
+    pub fn is(&self, namespace: &str, name: &str) -> bool {
+        self.namespace == namespace && self.name == name
+    }
+
+    pub fn child(&self, namespace: &str, name: &str) -> Option<&XmlElement> {
+        self.children.iter().find(|child| child.is(namespace, name))
+    }
+
+    /// Every element below this one with the given name, in document order
+    pub fn descendants<'a>(&'a self, namespace: &str, name: &str) -> Vec<&'a XmlElement> {
+        let mut found = Vec::new();
+
+        for child in &self.children {
+            if child.is(namespace, name) {
+                found.push(child);
+            }
+
+            found.extend(child.descendants(namespace, name));
+        }
+
+        found
+    }
+}
+
+pub fn escape_xml(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn to_element(node: roxmltree::Node) -> XmlElement {
+    let tag_name = node.tag_name();
+
+    XmlElement {
+        namespace: tag_name.namespace().unwrap_or_default().to_owned(),
+        name: tag_name.name().to_owned(),
+        attributes: node
+            .attributes()
+            .map(|attribute| (attribute.name().to_owned(), attribute.value().to_owned()))
+            .collect(),
+        children: node
+            .children()
+            .filter(roxmltree::Node::is_element)
+            .map(to_element)
+            .collect(),
+        text: node
+            .children()
+            .filter(roxmltree::Node::is_text)
+            .filter_map(|text| text.text())
+            .collect(),
+    }
+}
+
+/// Parses a request body, with its namespace prefixes resolved
+pub fn parse_xml(input: &str) -> Option<XmlElement> {
+    let document = roxmltree::Document::parse(input).ok()?;
+
+    Some(to_element(document.root_element()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_xml() {
+        let body = r#"<?xml version="1.0" encoding="utf-8" ?>
+            <C:calendar-multiget xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+              <D:prop>
+                <D:getetag/>
+                <C:calendar-data />
+              </D:prop>
+              <!-- Resources to fetch -->
+              <D:href>/caldav/abc/1.ics</D:href>
+              <D:href>/caldav/abc/a&amp;b.ics</D:href>
+            </C:calendar-multiget>"#;
+
+        let root = parse_xml(body).unwrap();
+
+        assert!(root.is("urn:ietf:params:xml:ns:caldav", "calendar-multiget"));
+
+        let prop = root.child("DAV:", "prop").unwrap();
+        assert!(prop.children[0].is("DAV:", "getetag"));
+        assert!(prop.children[1].is("urn:ietf:params:xml:ns:caldav", "calendar-data"));
+
+        let hrefs = root
+            .descendants("DAV:", "href")
+            .into_iter()
+            .map(|href| href.text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(hrefs, vec!["/caldav/abc/1.ics", "/caldav/abc/a&b.ics"]);
+    }
+
+    #[test]
+    fn test_default_namespace_and_attributes() {
+        let body = r#"<calendar-query xmlns="urn:ietf:params:xml:ns:caldav">
+              <filter><comp-filter name="VCALENDAR"><comp-filter name='VEVENT'>
+                <time-range start="20230101T000000Z" end="20230201T000000Z"/>
+              </comp-filter></comp-filter></filter>
+            </calendar-query>"#;
+
+        let root = parse_xml(body).unwrap();
+        let time_ranges = root.descendants("urn:ietf:params:xml:ns:caldav", "time-range");
+
+        assert_eq!(time_ranges.len(), 1);
+        assert_eq!(time_ranges[0].attributes["start"], "20230101T000000Z");
+        assert_eq!(time_ranges[0].attributes["end"], "20230201T000000Z");
+    }
+
+    #[test]
+    fn test_invalid_xml() {
+        assert!(parse_xml("<a><b></a>").is_none());
+        assert!(parse_xml("<a>").is_none());
+
+        // Entity definitions could expand a small body into a huge one
+        assert!(parse_xml(r#"<!DOCTYPE a [<!ENTITY e "x">]><a>&e;</a>"#).is_none());
+    }
+}
@@ -1,24 +1,25 @@
+use chrono::{DateTime, Utc};
 use icalendar::{Component, Event, EventLike};
 use itertools::Itertools;
 
 use crate::shared_types::{Activity, Room};
 
-pub fn activity_to_event(activity: &Activity, custom_name: &Option<String>) -> Event {
-    let mut event = Event::new();
-
-    event.uid(&activity.id);
+/// Everything shown in the calendar event of an activity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventDetails {
+    pub uid: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub summary: String,
+    pub location: Option<String>,
+    pub description: String,
+}
 
+pub fn activity_details(activity: &Activity, custom_name: &Option<String>) -> EventDetails {
     let name = custom_name.as_ref().unwrap_or(&activity.course_code);
-    event.summary(&format!("{} | {}", name, activity.title));
-    event.starts(activity.start);
-    event.ends(activity.end);
 
     let format_room_name = |room: &Room| format!("{} ({})", room.name, room.building_name);
 
-    if let Some(primary_room) = activity.rooms.first() {
-        event.location(&format_room_name(primary_room));
-    }
-
     let mut description = format!("{} {}", activity.course_code, activity.title);
 
     description += "\n\n";
@@ -37,7 +38,36 @@ pub fn activity_to_event(activity: &Activity, custom_name: &Option<String>) -> E
         .map(|room| format!("{}: {}", format_room_name(room), room.url))
         .join("\n");
 
-    event.description(&description);
+    EventDetails {
+        uid: activity.id.clone(),
+        start: activity.start,
+        end: activity.end,
+        summary: format!("{} | {}", name, activity.title),
+        location: activity.rooms.first().map(format_room_name),
+        description,
+    }
+}
+
+/// Sets everything but the start and end of the event
+pub fn describe_event(event: &mut Event, details: &EventDetails) {
+    event.uid(&details.uid);
+    event.summary(&details.summary);
+
+    if let Some(location) = &details.location {
+        event.location(location);
+    }
+
+    event.description(&details.description);
+}
+
+pub fn activity_to_event(activity: &Activity, custom_name: &Option<String>) -> Event {
+    let details = activity_details(activity, custom_name);
+
+    let mut event = Event::new();
+
+    describe_event(&mut event, &details);
+    event.starts(details.start);
+    event.ends(details.end);
 
     event.done()
 }
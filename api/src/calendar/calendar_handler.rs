@@ -1,67 +1,119 @@
 use crate::error::AppResult;
 use crate::{
-    calendar::{activity_to_event::activity_to_event, encode_query::decode_calendar_query},
-    shared_types::Activity,
+    calendar::{
+        activity_to_event::activity_to_event,
+        custom_events::custom_activity_to_event,
+        encode_query::decode_calendar_query,
+        exam_to_event::exam_to_event,
+        http_caching::{cached_response, CacheValidators},
+        recurring_series::{activities_to_series, add_oslo_timezone},
+        resolve_subscriptions::{resolve_subscriptions, ResolvedSubscription},
+    },
+    shared_types::CalendarSubscription,
     AppState,
 };
 use axum::extract::{Query, State};
-use futures_util::{future::try_join_all, TryFutureExt};
-use icalendar::Calendar;
+use axum::http::HeaderMap;
+use axum::response::Response;
+use chrono::Utc;
+use icalendar::{Calendar, CalendarComponent, Component};
 use serde::Deserialize;
-use std::sync::Arc;
 
 #[derive(Deserialize)]
 pub struct HandlerQuery {
     query: String,
+    /// Compress weekly activities into recurring events
+    #[serde(default)]
+    recurring: bool,
+}
+
+/// Events of every subscription, optionally with weekly activities compressed into series
+///
+/// Series need the time zone from `add_oslo_timezone` added when serialized
+pub fn build_calendar(
+    resolved_subscriptions: &[ResolvedSubscription],
+    recurring: bool,
+) -> Calendar {
+    let mut calendar = Calendar::new();
+
+    for resolved_subscription in resolved_subscriptions {
+        let custom_name = resolved_subscription.custom_name();
+
+        if let CalendarSubscription::Custom(_) = resolved_subscription.subscription {
+            calendar.extend(
+                resolved_subscription
+                    .activities
+                    .iter()
+                    .map(custom_activity_to_event),
+            );
+
+            continue;
+        }
+
+        if recurring {
+            calendar.extend(activities_to_series(
+                resolved_subscription.filtered_activities(),
+                custom_name,
+            ));
+        } else {
+            calendar.extend(
+                resolved_subscription
+                    .filtered_activities()
+                    .map(|activity| activity_to_event(activity, custom_name)),
+            );
+        }
+
+        calendar.extend(
+            resolved_subscription
+                .exams
+                .iter()
+                .map(|exam| exam_to_event(exam, custom_name)),
+        );
+    }
+
+    calendar
 }
 
 pub async fn calendar_handler(
     query: Query<HandlerQuery>,
+    headers: HeaderMap,
     State(app_state): State<AppState>,
-) -> AppResult<String> {
-    let calendar_queries = decode_calendar_query(&query.query)?;
-    let activities_cache = &app_state.activities_cache;
+) -> AppResult<Response> {
+    let subscriptions = decode_calendar_query(&query.query)?;
+    let resolved_subscriptions = resolve_subscriptions(&app_state, subscriptions).await?;
 
-    #[derive(Debug)]
-    struct ActivitiesWithExtra {
-        activities: Arc<Vec<Activity>>,
-        student_groups: Vec<String>,
-        custom_name: Option<String>,
-    }
+    let now = Utc::now();
+    let last_modified = resolved_subscriptions
+        .iter()
+        .map(|resolved_subscription| resolved_subscription.fetched_at)
+        .max()
+        .unwrap_or(now);
+    let expires_at = resolved_subscriptions
+        .iter()
+        .map(|resolved_subscription| resolved_subscription.expires_at)
+        .min()
+        .unwrap_or(now);
 
-    let activities = calendar_queries.into_iter().map(|query| {
-        activities_cache
-            .get_or_fetch(query.identifier)
-            .map_ok(|activities| ActivitiesWithExtra {
-                activities,
-                student_groups: query.student_groups,
-                custom_name: query.custom_name,
-            })
-    });
+    let mut calendar = build_calendar(&resolved_subscriptions, query.recurring);
 
-    let all_activities_with_student_groups: Vec<ActivitiesWithExtra> =
-        try_join_all(activities).await?;
-
-    fn includes_target_group(activity: &Activity, target_student_groups: &[String]) -> bool {
-        target_student_groups
-            .iter()
-            .any(|target_student_group| activity.student_groups.contains(target_student_group))
+    // Stamping events with the time of the data instead of the request keeps the body stable
+    for component in &mut calendar.components {
+        if let CalendarComponent::Event(event) = component {
+            event.timestamp(last_modified);
+        }
     }
 
-    let events = all_activities_with_student_groups.iter().flat_map(
-        |ActivitiesWithExtra {
-             activities,
-             student_groups,
-             custom_name,
-         }| {
-            activities
-                .iter()
-                .filter(move |activity| includes_target_group(activity, student_groups))
-                .map(|activity| activity_to_event(activity, custom_name))
-        },
-    );
+    let mut body = calendar.to_string();
 
-    let calendar = events.collect::<Calendar>();
+    if query.recurring {
+        body = add_oslo_timezone(&body);
+    }
+    let validators = CacheValidators::new(&body, last_modified, expires_at);
 
-    Ok(calendar.to_string())
+    Ok(cached_response(
+        &headers,
+        &validators,
+        "text/calendar; charset=utf-8",
+        body,
+    ))
 }
@@ -0,0 +1,92 @@
+use crate::calendar::local_events::local_events;
+use icalendar::{Calendar, Component, EventLike};
+use itertools::Itertools;
+
+/// Columns recognized by Google Calendar imports, which Excel opens as is
+const HEADER: [&str; 8] = [
+    "Subject",
+    "Start Date",
+    "Start Time",
+    "End Date",
+    "End Time",
+    "All Day Event",
+    "Description",
+    "Location",
+];
+
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// The events of the calendar as CSV, with times in Norwegian local time
+pub fn calendar_to_csv(calendar: &Calendar) -> String {
+    let mut csv = HEADER.join(",");
+    csv += "\r\n";
+
+    for local_event in local_events(calendar) {
+        let event = local_event.event;
+        let end = local_event.end.unwrap_or(local_event.start);
+
+        let (start_time, end_time) = if local_event.all_day {
+            (String::new(), String::new())
+        } else {
+            (
+                local_event.start.format("%I:%M %p").to_string(),
+                end.format("%I:%M %p").to_string(),
+            )
+        };
+
+        let fields = [
+            event.get_summary().unwrap_or_default().to_owned(),
+            local_event.start.format("%m/%d/%Y").to_string(),
+            start_time,
+            end.format("%m/%d/%Y").to_string(),
+            end_time,
+            if local_event.all_day { "True" } else { "False" }.to_owned(),
+            event.get_description().unwrap_or_default().to_owned(),
+            event.get_location().unwrap_or_default().to_owned(),
+        ];
+
+        csv += &fields.iter().map(|field| escape_csv(field)).join(",");
+        csv += "\r\n";
+    }
+
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use icalendar::Event;
+
+    #[test]
+    fn test_calendar_to_csv() {
+        let start = Utc.with_ymd_and_hms(2023, 3, 13, 7, 15, 0).unwrap();
+
+        let mut calendar = Calendar::empty();
+        calendar.push(
+            Event::new()
+                .summary("TDT4100 | Forelesning")
+                .description("Ola \"Foreleser\" Nordmann, Kari Nordmann")
+                .location("R1 (Realfagbygget)")
+                .starts(start)
+                .ends(start + chrono::Duration::minutes(105))
+                .done(),
+        );
+
+        let csv = calendar_to_csv(&calendar);
+        let lines = csv.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[1],
+            "TDT4100 | Forelesning,03/13/2023,08:15 AM,03/13/2023,10:00 AM,False,\
+             \"Ola \"\"Foreleser\"\" Nordmann, Kari Nordmann\",R1 (Realfagbygget)"
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::shared_types::{Activity, CustomEvent, CustomEventSchedule, Room};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Europe::Oslo;
+use icalendar::{Component, Event, EventLike};
+use itertools::Itertools;
+
+/// Keeps a long-running schedule from growing the calendar without bounds
+const MAX_OCCURRENCES: usize = 400;
+
+fn schedule_dates(schedule: &CustomEventSchedule) -> Vec<NaiveDate> {
+    match schedule {
+        CustomEventSchedule::Weekly {
+            weekday,
+            first_date,
+            last_date,
+            interval_weeks,
+        } => {
+            let Some(first) = first_date
+                .iter_days()
+                .take(7)
+                .find(|date| date.weekday().number_from_monday() == *weekday)
+            else {
+                return Vec::new();
+            };
+
+            let step = Duration::weeks((*interval_weeks).max(1).into());
+
+            std::iter::successors(Some(first), |date| date.checked_add_signed(step))
+                .take_while(|date| date <= last_date)
+                .take(MAX_OCCURRENCES)
+                .collect()
+        }
+        CustomEventSchedule::Dates { dates } => dates
+            .iter()
+            .copied()
+            .sorted()
+            .dedup()
+            .take(MAX_OCCURRENCES)
+            .collect(),
+    }
+}
+
+fn local_to_utc(date: NaiveDate, time: NaiveTime) -> Option<DateTime<Utc>> {
+    let local = Oslo.from_local_datetime(&date.and_time(time)).earliest()?;
+
+    Some(local.with_timezone(&Utc))
+}
+
+/// Expands the schedule into activities, so custom events work everywhere scraped ones do
+pub fn custom_event_activities(event: &CustomEvent) -> Vec<Activity> {
+    schedule_dates(&event.schedule)
+        .into_iter()
+        .filter_map(|date| {
+            let start = local_to_utc(date, event.start_time)?;
+
+            // Events ending at or before their start time end the day after, like a night shift
+            let end_date = if event.end_time <= event.start_time {
+                date.succ_opt()?
+            } else {
+                date
+            };
+            let end = local_to_utc(end_date, event.end_time)?;
+
+            let rooms = event
+                .location
+                .iter()
+                .map(|location| Room {
+                    id: None,
+                    name: location.clone(),
+                    building_name: String::new(),
+                    url: String::new(),
+                })
+                .collect();
+
+            Some(Activity {
+                id: format!("custom-{}-{}", event.id, date.format("%Y%m%d")),
+                course_code: String::new(),
+                week: date.iso_week().week() as i32,
+                start,
+                end,
+                title: event.title.clone(),
+                summary: event.description.clone().unwrap_or_default(),
+                staff_members: Vec::new(),
+                student_groups: Vec::new(),
+                rooms,
+            })
+        })
+        .collect()
+}
+
+/// Custom events have no course or staff, so they are shown with just what the user entered
+pub fn custom_activity_to_event(activity: &Activity) -> Event {
+    let mut event = Event::new();
+
+    event.uid(&activity.id);
+    event.summary(&activity.title);
+
+    if let Some(room) = activity.rooms.first() {
+        event.location(&room.name);
+    }
+
+    if !activity.summary.is_empty() {
+        event.description(&activity.summary);
+    }
+
+    event.starts(activity.start);
+    event.ends(activity.end);
+
+    event.done()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom_event(schedule: CustomEventSchedule) -> CustomEvent {
+        CustomEvent {
+            id: "ta".to_owned(),
+            title: "Studass".to_owned(),
+            location: Some("R1".to_owned()),
+            description: None,
+            start_time: NaiveTime::from_hms_opt(14, 15, 0).unwrap(),
+            end_time: NaiveTime::from_hms_opt(16, 0, 0).unwrap(),
+            schedule,
+        }
+    }
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_weekly_schedule() {
+        let activities = custom_event_activities(&custom_event(CustomEventSchedule::Weekly {
+            weekday: 3,
+            first_date: date(3, 13),
+            last_date: date(4, 5),
+            interval_weeks: 2,
+        }));
+
+        let starts = activities
+            .iter()
+            .map(|activity| activity.start)
+            .collect::<Vec<_>>();
+
+        // The switch to summer time moves the UTC time an hour earlier
+        assert_eq!(
+            starts,
+            vec![
+                Utc.with_ymd_and_hms(2023, 3, 15, 13, 15, 0).unwrap(),
+                Utc.with_ymd_and_hms(2023, 3, 29, 12, 15, 0).unwrap(),
+            ]
+        );
+        assert_eq!(activities[0].id, "custom-ta-20230315");
+        assert_eq!(activities[0].rooms[0].name, "R1");
+    }
+
+    #[test]
+    fn test_explicit_dates() {
+        let activities = custom_event_activities(&custom_event(CustomEventSchedule::Dates {
+            dates: vec![date(3, 20), date(3, 14), date(3, 20)],
+        }));
+
+        assert_eq!(activities.len(), 2);
+        assert_eq!(activities[0].week, 11);
+        assert_eq!(
+            activities[1].end,
+            Utc.with_ymd_and_hms(2023, 3, 20, 15, 0, 0).unwrap()
+        );
+    }
+}
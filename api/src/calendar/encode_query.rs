@@ -1,41 +1,86 @@
 use crate::error::{AppError, AppResult};
-use crate::shared_types::{CalendarQuery, OldCalendarQuery};
+use crate::shared_types::{CalendarQuery, CalendarSubscription, OldCalendarQuery};
 use data_encoding::BASE64URL_NOPAD;
+use serde::Serialize;
 
-pub fn encode_calendar_query(query: &[CalendarQuery]) -> AppResult<String> {
-    let query_bytes = rmp_serde::to_vec(query).map_err(|_| AppError::ParsingError)?;
+/// Which version of the encoded format a query was decoded from
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CalendarQueryFormat {
+    /// Course queries encoded before `custom_name` was added
+    Old,
+    /// List of course queries
+    Courses,
+    /// List of subscriptions, which can be courses or programmes
+    Subscriptions,
+}
+
+fn encode<T: Serialize + ?Sized>(value: &T) -> AppResult<String> {
+    let query_bytes = rmp_serde::to_vec(value).map_err(|_| AppError::ParsingError)?;
     let encoded_query = BASE64URL_NOPAD.encode(&query_bytes);
 
     Ok(encoded_query)
 }
 
-pub fn decode_calendar_query(query: &str) -> AppResult<Vec<CalendarQuery>> {
+pub fn encode_calendar_query(query: &[CalendarQuery]) -> AppResult<String> {
+    encode(query)
+}
+
+pub fn encode_calendar_subscriptions(subscriptions: &[CalendarSubscription]) -> AppResult<String> {
+    encode(subscriptions)
+}
+
+pub fn decode_calendar_query(query: &str) -> AppResult<Vec<CalendarSubscription>> {
+    let (subscriptions, _format) = decode_calendar_query_with_format(query)?;
+
+    Ok(subscriptions)
+}
+
+pub fn decode_calendar_query_with_format(
+    query: &str,
+) -> AppResult<(Vec<CalendarSubscription>, CalendarQueryFormat)> {
     let bytes = BASE64URL_NOPAD
         .decode(query.as_bytes())
         .map_err(|_| AppError::ParsingError)?;
 
-    let calendar_queries = rmp_serde::from_slice::<Vec<CalendarQuery>>(&bytes)
+    let decoded = rmp_serde::from_slice::<Vec<CalendarSubscription>>(&bytes)
+        .map(|subscriptions| (subscriptions, CalendarQueryFormat::Subscriptions))
+        .or_else(|_error| {
+            rmp_serde::from_slice::<Vec<CalendarQuery>>(&bytes).map(|calendar_queries| {
+                let subscriptions = calendar_queries
+                    .into_iter()
+                    .map(CalendarSubscription::Course)
+                    .collect();
+
+                (subscriptions, CalendarQueryFormat::Courses)
+            })
+        })
         .or_else(|_error| {
             rmp_serde::from_slice::<Vec<OldCalendarQuery>>(&bytes).map(|old_calendar_queries| {
-                old_calendar_queries
+                let subscriptions = old_calendar_queries
                     .into_iter()
-                    .map(|old_calendar_query| CalendarQuery {
-                        identifier: old_calendar_query.identifier,
-                        student_groups: old_calendar_query.student_groups,
-                        custom_name: None,
+                    .map(|old_calendar_query| {
+                        CalendarSubscription::Course(CalendarQuery {
+                            identifier: old_calendar_query.identifier,
+                            student_groups: old_calendar_query.student_groups,
+                            custom_name: None,
+                            include_exams: false,
+                        })
                     })
-                    .collect()
+                    .collect();
+
+                (subscriptions, CalendarQueryFormat::Old)
             })
         })
         .map_err(|_| AppError::ParsingError)?;
 
-    Ok(calendar_queries)
+    Ok(decoded)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::shared_types::CourseIdentifier;
+    use crate::shared_types::{CourseIdentifier, ProgrammeCalendarQuery, ProgrammeIdentifier};
 
     #[test]
     fn test_encode_decode() {
@@ -47,11 +92,113 @@ mod tests {
             },
             student_groups: vec!["BPROG_2".to_owned()],
             custom_name: Some("Test".to_string()),
+            include_exams: false,
         }];
 
         let encoded = encode_calendar_query(&input).unwrap();
-        let decoded = decode_calendar_query(&encoded).unwrap();
+        let (decoded, format) = decode_calendar_query_with_format(&encoded).unwrap();
+
+        assert_eq!(format, CalendarQueryFormat::Courses);
+        assert_eq!(
+            input
+                .into_iter()
+                .map(CalendarSubscription::Course)
+                .collect::<Vec<_>>(),
+            decoded
+        );
+    }
+
+    #[test]
+    fn test_encode_decode_subscriptions() {
+        let input = vec![
+            CalendarSubscription::Course(CalendarQuery {
+                identifier: CourseIdentifier {
+                    course_code: "PROG1004".to_owned(),
+                    semester: "23v".to_owned(),
+                    course_term: 1,
+                },
+                student_groups: vec!["BPROG_2".to_owned()],
+                custom_name: None,
+                include_exams: true,
+            }),
+            CalendarSubscription::Programme(ProgrammeCalendarQuery {
+                identifier: ProgrammeIdentifier {
+                    programme: "BPROG".to_owned(),
+                    year: 2,
+                    semester: "23v".to_owned(),
+                },
+                custom_name: Some("Test".to_string()),
+            }),
+        ];
+
+        let encoded = encode_calendar_subscriptions(&input).unwrap();
+        let (decoded, format) = decode_calendar_query_with_format(&encoded).unwrap();
 
+        assert_eq!(format, CalendarQueryFormat::Subscriptions);
         assert_eq!(input, decoded);
     }
+
+    #[test]
+    fn test_decode_old_format() {
+        let identifier = CourseIdentifier {
+            course_code: "PROG1004".to_owned(),
+            semester: "23v".to_owned(),
+            course_term: 1,
+        };
+
+        let input = vec![OldCalendarQuery {
+            identifier: identifier.clone(),
+            student_groups: vec!["BPROG_2".to_owned()],
+        }];
+
+        let encoded = BASE64URL_NOPAD.encode(&rmp_serde::to_vec(&input).unwrap());
+        let (decoded, format) = decode_calendar_query_with_format(&encoded).unwrap();
+
+        assert_eq!(format, CalendarQueryFormat::Old);
+        assert_eq!(
+            decoded,
+            vec![CalendarSubscription::Course(CalendarQuery {
+                identifier,
+                student_groups: vec!["BPROG_2".to_owned()],
+                custom_name: None,
+                include_exams: false,
+            })]
+        );
+    }
+
+    #[test]
+    fn test_decode_courses_format_without_exams() {
+        #[derive(Serialize)]
+        struct CalendarQueryWithoutExams {
+            identifier: CourseIdentifier,
+            student_groups: Vec<String>,
+            custom_name: Option<String>,
+        }
+
+        let identifier = CourseIdentifier {
+            course_code: "PROG1004".to_owned(),
+            semester: "23v".to_owned(),
+            course_term: 1,
+        };
+
+        let input = vec![CalendarQueryWithoutExams {
+            identifier: identifier.clone(),
+            student_groups: vec!["BPROG_2".to_owned()],
+            custom_name: Some("Test".to_string()),
+        }];
+
+        let encoded = BASE64URL_NOPAD.encode(&rmp_serde::to_vec(&input).unwrap());
+        let (decoded, format) = decode_calendar_query_with_format(&encoded).unwrap();
+
+        assert_eq!(format, CalendarQueryFormat::Courses);
+        assert_eq!(
+            decoded,
+            vec![CalendarSubscription::Course(CalendarQuery {
+                identifier,
+                student_groups: vec!["BPROG_2".to_owned()],
+                custom_name: Some("Test".to_string()),
+                include_exams: false,
+            })]
+        );
+    }
 }
@@ -0,0 +1,34 @@
+use icalendar::{Component, Event, EventLike};
+
+use crate::shared_types::Exam;
+
+pub fn exam_to_event(exam: &Exam, custom_name: &Option<String>) -> Event {
+    let mut event = Event::new();
+
+    event.uid(&exam.id);
+
+    let name = custom_name.as_ref().unwrap_or(&exam.course_code);
+    event.summary(&format!("{} | Eksamen", name));
+    event.add_property("CATEGORIES", "Eksamen");
+
+    match (exam.start, exam.end) {
+        (Some(start), Some(end)) => {
+            event.starts(start);
+            event.ends(end);
+        }
+        (Some(start), None) => {
+            event.starts(start);
+        }
+        _ => {
+            event.all_day(exam.date);
+        }
+    }
+
+    if let Some(location) = &exam.location {
+        event.location(location);
+    }
+
+    event.description(&format!("{} {}", exam.course_code, exam.assessment_form));
+
+    event.done()
+}
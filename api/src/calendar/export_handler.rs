@@ -0,0 +1,95 @@
+use crate::error::AppResult;
+use crate::{
+    calendar::{
+        calendar_handler::build_calendar,
+        csv_export::calendar_to_csv,
+        encode_query::decode_calendar_query,
+        html_export::calendar_to_html,
+        jcal::calendar_to_jcal,
+        resolve_subscriptions::{resolve_subscriptions, ResolvedSubscription},
+    },
+    shared_types::Activity,
+    AppState,
+};
+use axum::extract::{Query, State};
+use axum::http::header;
+use axum::response::{Html, IntoResponse, Json, Response};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    query: String,
+}
+
+async fn resolve_query(
+    app_state: &AppState,
+    query: &ExportQuery,
+) -> AppResult<Vec<ResolvedSubscription>> {
+    let subscriptions = decode_calendar_query(&query.query)?;
+
+    resolve_subscriptions(app_state, subscriptions).await
+}
+
+/// The filtered activities of the subscriptions, without exams
+pub async fn json_handler(
+    query: Query<ExportQuery>,
+    State(app_state): State<AppState>,
+) -> AppResult<Json<Vec<Activity>>> {
+    let resolved_subscriptions = resolve_query(&app_state, &query).await?;
+
+    let activities = resolved_subscriptions
+        .iter()
+        .flat_map(|resolved_subscription| resolved_subscription.filtered_activities())
+        .cloned()
+        .collect();
+
+    Ok(Json(activities))
+}
+
+pub async fn jcal_handler(
+    query: Query<ExportQuery>,
+    State(app_state): State<AppState>,
+) -> AppResult<Response> {
+    let resolved_subscriptions = resolve_query(&app_state, &query).await?;
+    let calendar = build_calendar(&resolved_subscriptions, false);
+
+    let response = (
+        [(header::CONTENT_TYPE, "application/calendar+json")],
+        Json(calendar_to_jcal(&calendar)),
+    )
+        .into_response();
+
+    Ok(response)
+}
+
+pub async fn csv_handler(
+    query: Query<ExportQuery>,
+    State(app_state): State<AppState>,
+) -> AppResult<Response> {
+    let resolved_subscriptions = resolve_query(&app_state, &query).await?;
+    let calendar = build_calendar(&resolved_subscriptions, false);
+
+    let response = (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"calendar.csv\"",
+            ),
+        ],
+        calendar_to_csv(&calendar),
+    )
+        .into_response();
+
+    Ok(response)
+}
+
+pub async fn html_handler(
+    query: Query<ExportQuery>,
+    State(app_state): State<AppState>,
+) -> AppResult<Html<String>> {
+    let resolved_subscriptions = resolve_query(&app_state, &query).await?;
+    let calendar = build_calendar(&resolved_subscriptions, false);
+
+    Ok(Html(calendar_to_html(&calendar)))
+}
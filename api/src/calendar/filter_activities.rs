@@ -0,0 +1,45 @@
+use crate::shared_types::{Activity, CalendarSubscription};
+use crate::staff::teaches;
+use crate::student_groups::activity_matches_programme;
+use chrono::{DateTime, Utc};
+
+pub fn includes_target_group(activity: &Activity, target_student_groups: &[String]) -> bool {
+    target_student_groups
+        .iter()
+        .any(|target_student_group| activity.student_groups.contains(target_student_group))
+}
+
+/// Whether an activity fetched for the subscription should be part of the calendar
+pub fn subscription_includes(subscription: &CalendarSubscription, activity: &Activity) -> bool {
+    match subscription {
+        CalendarSubscription::Course(query) => {
+            includes_target_group(activity, &query.student_groups)
+        }
+        CalendarSubscription::Programme(query) => activity_matches_programme(
+            activity,
+            &query.identifier.programme,
+            Some(query.identifier.year),
+        ),
+        CalendarSubscription::Room(_) | CalendarSubscription::Custom(_) => true,
+        CalendarSubscription::Staff(identifier) => teaches(activity, &identifier.staff_id),
+    }
+}
+
+/// Whether the activity overlaps the range, where a missing bound is unbounded
+pub fn overlaps_range(
+    activity: &Activity,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> bool {
+    let after_from = match from {
+        Some(from) => activity.end > from,
+        None => true,
+    };
+
+    let before_to = match to {
+        Some(to) => activity.start < to,
+        None => true,
+    };
+
+    after_from && before_to
+}
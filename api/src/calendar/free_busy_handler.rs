@@ -0,0 +1,48 @@
+use crate::error::AppResult;
+use crate::free_busy::{free_busy, free_busy_calendar};
+use crate::shared_types::FreeBusyQuery;
+use crate::AppState;
+use axum::extract::{Query, State};
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct FreeBusyHandlerQuery {
+    /// Comma-separated encoded calendar queries
+    queries: String,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    anonymise: bool,
+}
+
+pub async fn free_busy_handler(
+    query: Query<FreeBusyHandlerQuery>,
+    State(app_state): State<AppState>,
+) -> AppResult<Response> {
+    let Query(query) = query;
+
+    let free_busy_query = FreeBusyQuery {
+        queries: query
+            .queries
+            .split(',')
+            .filter(|encoded_query| !encoded_query.is_empty())
+            .map(str::to_owned)
+            .collect(),
+        from: query.from,
+        to: query.to,
+        anonymise: query.anonymise,
+    };
+
+    let free_busy = free_busy(&app_state, free_busy_query).await?;
+
+    let response = (
+        [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        free_busy_calendar(&free_busy).to_string(),
+    )
+        .into_response();
+
+    Ok(response)
+}
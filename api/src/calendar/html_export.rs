@@ -0,0 +1,110 @@
+use crate::calendar::inspect_handler::escape_html;
+use crate::calendar::local_events::{local_events, LocalEvent};
+use chrono::{Datelike, Duration, IsoWeek, NaiveDate, Weekday};
+use icalendar::{Calendar, Component, EventLike};
+use itertools::Itertools;
+use std::fmt::Write;
+
+const DAY_NAMES: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+const STYLE: &str = "body{font-family:sans-serif;font-size:12px}\
+table{width:100%;border-collapse:collapse;table-layout:fixed;margin-bottom:24px}\
+th,td{border:1px solid #999;padding:4px;vertical-align:top}\
+.event{margin-bottom:6px}.time{font-weight:bold}.location{color:#555}\
+@media print{section{page-break-after:always}}";
+
+fn render_event(html: &mut String, local_event: &LocalEvent) {
+    let event = local_event.event;
+
+    let time = match (local_event.all_day, local_event.end) {
+        (true, _) => "All day".to_owned(),
+        (false, Some(end)) => format!(
+            "{}–{}",
+            local_event.start.format("%H:%M"),
+            end.format("%H:%M")
+        ),
+        (false, None) => local_event.start.format("%H:%M").to_string(),
+    };
+
+    let _ = write!(
+        html,
+        "<div class=\"event\"><div class=\"time\">{}</div><div>{}</div>",
+        time,
+        escape_html(event.get_summary().unwrap_or_default()),
+    );
+
+    if let Some(location) = event.get_location() {
+        let _ = write!(
+            html,
+            "<div class=\"location\">{}</div>",
+            escape_html(location)
+        );
+    }
+
+    html.push_str("</div>");
+}
+
+fn render_week(html: &mut String, week: IsoWeek, events: &[LocalEvent]) {
+    let monday = NaiveDate::from_isoywd_opt(week.year(), week.week(), Weekday::Mon)
+        .expect("ISO week of an event should be valid");
+
+    let _ = write!(
+        html,
+        "<section><h2>Week {}, {}</h2><table><tr>",
+        week.week(),
+        week.year()
+    );
+
+    for (offset, day_name) in DAY_NAMES.iter().enumerate() {
+        let date = monday + Duration::days(offset as i64);
+        let _ = write!(html, "<th>{} {}</th>", day_name, date.format("%d.%m"));
+    }
+
+    html.push_str("</tr><tr>");
+
+    for offset in 0..7 {
+        let date = monday + Duration::days(offset);
+
+        html.push_str("<td>");
+
+        for local_event in events.iter().filter(|event| event.start.date() == date) {
+            render_event(html, local_event);
+        }
+
+        html.push_str("</td>");
+    }
+
+    html.push_str("</tr></table></section>");
+}
+
+/// A printable page with a grid of the calendar's events for every week that has any
+pub fn calendar_to_html(calendar: &Calendar) -> String {
+    let mut html = String::new();
+
+    let _ = write!(
+        html,
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Timetable</title><style>{STYLE}</style></head><body>"
+    );
+
+    let events = local_events(calendar);
+
+    if events.is_empty() {
+        html.push_str("<p>No events</p>");
+    }
+
+    for (week, week_events) in &events.into_iter().group_by(|event| event.start.iso_week()) {
+        render_week(&mut html, week, &week_events.collect::<Vec<_>>());
+    }
+
+    html.push_str("</body></html>");
+
+    html
+}
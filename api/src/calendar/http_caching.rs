@@ -0,0 +1,143 @@
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, DurationRound, Utc};
+use data_encoding::HEXLOWER;
+use ring::digest::{digest, SHA256};
+
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+/// Calendars without fetched content, like ones with only custom events, never expire by themselves
+const MAX_AGE_LIMIT: i64 = 24 * 60 * 60;
+
+/// What clients need to revalidate or reuse a response without fetching it again
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheValidators {
+    pub etag: String,
+    pub last_modified: DateTime<Utc>,
+    pub max_age: i64,
+}
+
+impl CacheValidators {
+    pub fn new(body: &str, last_modified: DateTime<Utc>, expires_at: DateTime<Utc>) -> Self {
+        let body_digest = digest(&SHA256, body.as_bytes());
+
+        // HTTP dates have a precision of seconds
+        let last_modified = last_modified
+            .duration_trunc(chrono::Duration::seconds(1))
+            .unwrap_or(last_modified);
+
+        Self {
+            etag: format!("\"{}\"", HEXLOWER.encode(&body_digest.as_ref()[..16])),
+            last_modified,
+            max_age: (expires_at - Utc::now())
+                .num_seconds()
+                .clamp(0, MAX_AGE_LIMIT),
+        }
+    }
+
+    /// `If-None-Match` takes precedence, and `If-Modified-Since` is only used without it
+    pub fn is_not_modified(&self, headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+            let Ok(if_none_match) = if_none_match.to_str() else {
+                return false;
+            };
+
+            return if_none_match.split(',').any(|tag| {
+                let tag = tag.trim();
+                tag == "*" || tag.trim_start_matches("W/") == self.etag
+            });
+        }
+
+        headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+            .is_some_and(|since| self.last_modified <= since)
+    }
+
+    fn headers(&self) -> [(header::HeaderName, HeaderValue); 3] {
+        let header_value =
+            |value: String| HeaderValue::from_str(&value).expect("Header values should be ASCII");
+
+        [
+            (header::ETAG, header_value(self.etag.clone())),
+            (
+                header::LAST_MODIFIED,
+                header_value(self.last_modified.format(HTTP_DATE_FORMAT).to_string()),
+            ),
+            (
+                header::CACHE_CONTROL,
+                header_value(format!("public, max-age={}", self.max_age)),
+            ),
+        ]
+    }
+}
+
+/// The body with caching headers, or an empty 304 if the client's copy is still current
+pub fn cached_response(
+    headers: &HeaderMap,
+    validators: &CacheValidators,
+    content_type: &'static str,
+    body: String,
+) -> Response {
+    if validators.is_not_modified(headers) {
+        return (StatusCode::NOT_MODIFIED, validators.headers()).into_response();
+    }
+
+    (
+        validators.headers(),
+        [(header::CONTENT_TYPE, content_type)],
+        body,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn validators() -> CacheValidators {
+        let last_modified = Utc.with_ymd_and_hms(2023, 3, 13, 7, 15, 0).unwrap();
+
+        CacheValidators::new("BEGIN:VCALENDAR", last_modified, Utc::now())
+    }
+
+    fn headers(name: header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+
+        headers
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let validators = validators();
+
+        assert!(validators.is_not_modified(&headers(header::IF_NONE_MATCH, &validators.etag)));
+        assert!(validators.is_not_modified(&headers(
+            header::IF_NONE_MATCH,
+            &format!("\"other\", W/{}", validators.etag)
+        )));
+        assert!(!validators.is_not_modified(&headers(header::IF_NONE_MATCH, "\"other\"")));
+        assert!(!validators.is_not_modified(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn test_if_modified_since() {
+        let validators = validators();
+
+        assert!(validators.is_not_modified(&headers(
+            header::IF_MODIFIED_SINCE,
+            "Mon, 13 Mar 2023 07:15:00 GMT"
+        )));
+        assert!(!validators.is_not_modified(&headers(
+            header::IF_MODIFIED_SINCE,
+            "Mon, 13 Mar 2023 07:14:59 GMT"
+        )));
+
+        // A changed ETag wins over an unchanged modification time
+        let mut conditional = headers(header::IF_MODIFIED_SINCE, "Mon, 13 Mar 2023 08:00:00 GMT");
+        conditional.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"other\""));
+        assert!(!validators.is_not_modified(&conditional));
+    }
+}
@@ -0,0 +1,221 @@
+use crate::error::AppResult;
+use crate::{
+    calendar::{
+        encode_query::{decode_calendar_query_with_format, CalendarQueryFormat},
+        resolve_subscriptions::{resolve_subscriptions, ResolvedSubscription},
+    },
+    shared_types::CalendarSubscription,
+    AppState,
+};
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap};
+use axum::response::{Html, IntoResponse, Json, Response};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::Write;
+
+#[derive(Deserialize)]
+pub struct InspectQuery {
+    query: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionInspection {
+    pub subscription: CalendarSubscription,
+    /// Whether any activities were found upstream for the subscription
+    pub resolved: bool,
+    pub total_activities: usize,
+    /// Amount of events left after filtering, e.g. on the selected student groups
+    pub event_count: usize,
+    pub exam_count: usize,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryInspection {
+    pub format: CalendarQueryFormat,
+    pub subscriptions: Vec<SubscriptionInspection>,
+}
+
+fn inspect_subscription(resolved_subscription: ResolvedSubscription) -> SubscriptionInspection {
+    let activities = &resolved_subscription.activities;
+    let event_count = resolved_subscription.filtered_activities().count();
+
+    let mut warnings = Vec::new();
+
+    if activities.is_empty() {
+        warnings.push("No activities found".to_owned());
+    }
+
+    if let CalendarSubscription::Course(query) = &resolved_subscription.subscription {
+        let known_groups = activities
+            .iter()
+            .flat_map(|activity| &activity.student_groups)
+            .collect::<HashSet<_>>();
+
+        if query.student_groups.is_empty() {
+            warnings.push("No student groups selected".to_owned());
+        }
+
+        if !activities.is_empty() {
+            for student_group in &query.student_groups {
+                if !known_groups.contains(student_group) {
+                    warnings.push(format!("Unknown student group \"{student_group}\""));
+                }
+            }
+        }
+    }
+
+    SubscriptionInspection {
+        resolved: !activities.is_empty(),
+        total_activities: activities.len(),
+        event_count,
+        exam_count: resolved_subscription.exams.len(),
+        warnings,
+        subscription: resolved_subscription.subscription,
+    }
+}
+
+pub async fn inspect_calendar_query(
+    app_state: &AppState,
+    query: &str,
+) -> AppResult<QueryInspection> {
+    let (subscriptions, format) = decode_calendar_query_with_format(query)?;
+    let resolved_subscriptions = resolve_subscriptions(app_state, subscriptions).await?;
+
+    let subscriptions = resolved_subscriptions
+        .into_iter()
+        .map(inspect_subscription)
+        .collect();
+
+    Ok(QueryInspection {
+        format,
+        subscriptions,
+    })
+}
+
+pub fn escape_html(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+
+    for character in input.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(character),
+        }
+    }
+
+    escaped
+}
+
+fn describe_subscription(subscription: &CalendarSubscription) -> (String, &Option<String>) {
+    match subscription {
+        CalendarSubscription::Course(query) => {
+            let identifier = &query.identifier;
+            let description = format!(
+                "Course {} (term {}, {}), groups: {}",
+                identifier.course_code,
+                identifier.course_term,
+                identifier.semester,
+                query.student_groups.join(", ")
+            );
+
+            (description, &query.custom_name)
+        }
+        CalendarSubscription::Programme(query) => {
+            let identifier = &query.identifier;
+            let description = format!(
+                "Programme {} year {} ({})",
+                identifier.programme, identifier.year, identifier.semester
+            );
+
+            (description, &query.custom_name)
+        }
+        CalendarSubscription::Room(identifier) => {
+            let description = format!("Room {} ({})", identifier.room_id, identifier.semester);
+
+            (description, &None)
+        }
+        CalendarSubscription::Staff(identifier) => {
+            let description = format!(
+                "Staff member {} ({})",
+                identifier.staff_id, identifier.semester
+            );
+
+            (description, &None)
+        }
+        CalendarSubscription::Custom(event) => {
+            let description = format!(
+                "Custom event {}, {}–{}",
+                event.title, event.start_time, event.end_time
+            );
+
+            (description, &None)
+        }
+    }
+}
+
+fn render_html(inspection: &QueryInspection) -> String {
+    let mut html = String::new();
+
+    html += "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Calendar query</title></head><body>";
+
+    let format = match inspection.format {
+        CalendarQueryFormat::Old => "old",
+        CalendarQueryFormat::Courses => "courses",
+        CalendarQueryFormat::Subscriptions => "subscriptions",
+    };
+    let _ = write!(html, "<p>Format: {format}</p>");
+
+    html += "<table border=\"1\"><tr><th>Subscription</th><th>Custom name</th><th>Resolved</th><th>Events</th><th>Warnings</th></tr>";
+
+    for subscription in &inspection.subscriptions {
+        let (description, custom_name) = describe_subscription(&subscription.subscription);
+
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{} / {}</td><td>{}</td></tr>",
+            escape_html(&description),
+            escape_html(custom_name.as_deref().unwrap_or("")),
+            if subscription.resolved { "yes" } else { "no" },
+            subscription.event_count,
+            subscription.total_activities,
+            subscription
+                .warnings
+                .iter()
+                .map(|warning| escape_html(warning))
+                .join("<br>"),
+        );
+    }
+
+    html += "</table></body></html>";
+
+    html
+}
+
+pub async fn inspect_handler(
+    query: Query<InspectQuery>,
+    headers: HeaderMap,
+    State(app_state): State<AppState>,
+) -> AppResult<Response> {
+    let inspection = inspect_calendar_query(&app_state, &query.query).await?;
+
+    let wants_html = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+
+    let response = if wants_html {
+        Html(render_html(&inspection)).into_response()
+    } else {
+        Json(inspection).into_response()
+    };
+
+    Ok(response)
+}
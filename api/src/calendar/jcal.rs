@@ -0,0 +1,183 @@
+use icalendar::{Calendar, CalendarComponent, Component, Property};
+use serde_json::{json, Map, Value};
+
+/// Properties holding a date or date-time, as opposed to text
+const DATE_TIME_PROPERTIES: [&str; 8] = [
+    "DTSTART",
+    "DTEND",
+    "DTSTAMP",
+    "RECURRENCE-ID",
+    "EXDATE",
+    "RDATE",
+    "CREATED",
+    "LAST-MODIFIED",
+];
+
+/// Converts basic iCalendar dates like `20230313T071500Z` to the extended format used by jCal
+fn extended_date_time(value: &str) -> String {
+    let (date, time) = value.split_once('T').unwrap_or((value, ""));
+
+    let mut extended = String::new();
+
+    if date.len() == 8 {
+        extended += &format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]);
+    } else {
+        extended += date;
+    }
+
+    if time.len() >= 6 {
+        extended += &format!(
+            "T{}:{}:{}{}",
+            &time[..2],
+            &time[2..4],
+            &time[4..6],
+            &time[6..]
+        );
+    }
+
+    extended
+}
+
+fn recur_value(value: &str) -> Value {
+    let parts = value
+        .split(';')
+        .filter_map(|part| part.split_once('='))
+        .map(|(key, value)| {
+            let value = match key {
+                "UNTIL" => extended_date_time(value),
+                _ => value.to_owned(),
+            };
+
+            (key.to_lowercase(), Value::String(value))
+        })
+        .collect::<Map<_, _>>();
+
+    Value::Object(parts)
+}
+
+fn property_to_jcal(property: &Property) -> Value {
+    let key = property.key();
+    let value = property.value();
+
+    let parameters = property
+        .params()
+        .values()
+        .filter(|parameter| parameter.key() != "VALUE")
+        .map(|parameter| {
+            (
+                parameter.key().to_lowercase(),
+                Value::String(parameter.value().to_owned()),
+            )
+        })
+        .collect::<Map<_, _>>();
+
+    let (value_type, value) = if DATE_TIME_PROPERTIES.contains(&key) {
+        let value_type = if value.contains('T') {
+            "date-time"
+        } else {
+            "date"
+        };
+
+        (value_type, Value::String(extended_date_time(value)))
+    } else if key == "RRULE" {
+        ("recur", recur_value(value))
+    } else {
+        ("text", Value::String(value.to_owned()))
+    };
+
+    json!([key.to_lowercase(), parameters, value_type, value])
+}
+
+fn component_to_jcal<C: Component>(component: &C) -> Value {
+    let properties = component
+        .properties()
+        .values()
+        .chain(component.multi_properties())
+        .map(property_to_jcal)
+        .collect::<Vec<_>>();
+
+    let components = component
+        .components()
+        .iter()
+        .map(component_to_jcal)
+        .collect::<Vec<_>>();
+
+    json!([
+        component.component_kind().to_lowercase(),
+        properties,
+        components
+    ])
+}
+
+/// The calendar as jCal (RFC 7265), iCalendar as JSON
+pub fn calendar_to_jcal(calendar: &Calendar) -> Value {
+    let properties = calendar
+        .properties
+        .iter()
+        .map(property_to_jcal)
+        .collect::<Vec<_>>();
+
+    let components = calendar
+        .components
+        .iter()
+        .filter_map(|component| match component {
+            CalendarComponent::Event(event) => Some(component_to_jcal(event)),
+            CalendarComponent::Todo(todo) => Some(component_to_jcal(todo)),
+            CalendarComponent::Venue(venue) => Some(component_to_jcal(venue)),
+            CalendarComponent::Other(other) => Some(component_to_jcal(other)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    json!(["vcalendar", properties, components])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use icalendar::{Event, EventLike};
+
+    #[test]
+    fn test_extended_date_time() {
+        assert_eq!(
+            extended_date_time("20230313T071500Z"),
+            "2023-03-13T07:15:00Z"
+        );
+        assert_eq!(extended_date_time("20230313T081500"), "2023-03-13T08:15:00");
+        assert_eq!(extended_date_time("20230313"), "2023-03-13");
+    }
+
+    #[test]
+    fn test_calendar_to_jcal() {
+        let start = Utc.with_ymd_and_hms(2023, 3, 13, 7, 15, 0).unwrap();
+
+        let mut calendar = Calendar::empty();
+        calendar.push(
+            Event::new()
+                .uid("1")
+                .summary("TDT4100 | Forelesning")
+                .starts(start)
+                .done(),
+        );
+        calendar.push(
+            Event::new()
+                .uid("2")
+                .all_day(NaiveDate::from_ymd_opt(2023, 5, 10).unwrap())
+                .done(),
+        );
+
+        let jcal = calendar_to_jcal(&calendar);
+        let events = jcal[2].as_array().unwrap();
+
+        assert_eq!(jcal[0], "vcalendar");
+        assert_eq!(events[0][0], "vevent");
+
+        let first = events[0][1].as_array().unwrap();
+        assert!(first.contains(&json!(["dtstart", {}, "date-time", "2023-03-13T07:15:00Z"])));
+        assert!(first.contains(&json!(["summary", {}, "text", "TDT4100 | Forelesning"])));
+
+        let second = events[1][1].as_array().unwrap();
+        assert!(second.contains(&json!(["dtstart", {}, "date", "2023-05-10"])));
+    }
+}
@@ -0,0 +1,51 @@
+use chrono::{NaiveDateTime, NaiveTime};
+use chrono_tz::Europe::Oslo;
+use icalendar::{Calendar, CalendarComponent, CalendarDateTime, Component, DatePerhapsTime, Event};
+
+/// An event with its times in Norwegian local time, for formats without time zones
+pub struct LocalEvent<'a> {
+    pub event: &'a Event,
+    pub start: NaiveDateTime,
+    pub end: Option<NaiveDateTime>,
+    pub all_day: bool,
+}
+
+fn local_time(time: DatePerhapsTime) -> (NaiveDateTime, bool) {
+    match time {
+        DatePerhapsTime::DateTime(CalendarDateTime::Utc(time)) => {
+            (time.with_timezone(&Oslo).naive_local(), false)
+        }
+        DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone { date_time, .. }) => {
+            (date_time, false)
+        }
+        DatePerhapsTime::DateTime(CalendarDateTime::Floating(date_time)) => (date_time, false),
+        DatePerhapsTime::Date(date) => (date.and_time(NaiveTime::MIN), true),
+    }
+}
+
+/// Events of the calendar sorted by start, skipping any without a start
+pub fn local_events(calendar: &Calendar) -> Vec<LocalEvent<'_>> {
+    let mut events = calendar
+        .components
+        .iter()
+        .filter_map(|component| match component {
+            CalendarComponent::Event(event) => Some(event),
+            _ => None,
+        })
+        .filter_map(|event| {
+            let (start, all_day) = local_time(event.get_start()?);
+            let end = event.get_end().map(|end| local_time(end).0);
+
+            Some(LocalEvent {
+                event,
+                start,
+                end,
+                all_day,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    events.sort_by_key(|local_event| local_event.start);
+
+    events
+}
@@ -1,3 +1,16 @@
 pub mod activity_to_event;
 pub mod calendar_handler;
+pub mod csv_export;
+pub mod custom_events;
 pub mod encode_query;
+pub mod exam_to_event;
+pub mod export_handler;
+pub mod filter_activities;
+pub mod free_busy_handler;
+pub mod html_export;
+pub mod http_caching;
+pub mod inspect_handler;
+pub mod jcal;
+pub mod local_events;
+pub mod recurring_series;
+pub mod resolve_subscriptions;
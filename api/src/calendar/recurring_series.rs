@@ -0,0 +1,479 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Europe::Oslo;
+use icalendar::{CalendarDateTime, Component, Event, EventLike, Property};
+use itertools::Itertools;
+use std::collections::HashSet;
+
+use crate::calendar::activity_to_event::{activity_details, describe_event, EventDetails};
+use crate::shared_types::Activity;
+
+const TIMEZONE_ID: &str = "Europe/Oslo";
+const LOCAL_FORMAT: &str = "%Y%m%dT%H%M%S";
+
+/// Series refer to local time, so they keep their time of day across daylight saving changes
+const OSLO_TIMEZONE: &str = "BEGIN:VTIMEZONE\r
+TZID:Europe/Oslo\r
+BEGIN:DAYLIGHT\r
+TZOFFSETFROM:+0100\r
+TZOFFSETTO:+0200\r
+TZNAME:CEST\r
+DTSTART:19700329T020000\r
+RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU\r
+END:DAYLIGHT\r
+BEGIN:STANDARD\r
+TZOFFSETFROM:+0200\r
+TZOFFSETTO:+0100\r
+TZNAME:CET\r
+DTSTART:19701025T030000\r
+RRULE:FREQ=YEARLY;BYMONTH=10;BYDAY=-1SU\r
+END:STANDARD\r
+END:VTIMEZONE\r
+";
+
+/// Adds the VTIMEZONE the series' TZID refers to before the first component of the calendar
+///
+/// It is added to the serialized calendar, as components built with `icalendar` always get a
+/// `DTSTAMP` and a random `UID`, which are invalid in time zones and would change every response.
+pub fn add_oslo_timezone(ics: &str) -> String {
+    let header_end = ics
+        .find("\r\nBEGIN:")
+        .or_else(|| ics.find("\r\nEND:VCALENDAR"))
+        .map_or(ics.len(), |index| index + 2);
+
+    let (header, components) = ics.split_at(header_end);
+
+    format!("{header}{OSLO_TIMEZONE}{components}")
+}
+
+fn local(time: DateTime<Utc>) -> NaiveDateTime {
+    time.with_timezone(&Oslo).naive_local()
+}
+
+fn local_date_time(time: DateTime<Utc>) -> CalendarDateTime {
+    CalendarDateTime::WithTimezone {
+        date_time: local(time),
+        tzid: TIMEZONE_ID.to_owned(),
+    }
+}
+
+fn local_property(key: &str, date_time: NaiveDateTime) -> Property {
+    Property::new(key, &date_time.format(LOCAL_FORMAT).to_string())
+        .add_parameter("TZID", TIMEZONE_ID)
+        .done()
+}
+
+struct Occurrence {
+    id: String,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+impl Occurrence {
+    fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+
+    /// Weekday and local time of day, which must match for the occurrence to be part of the weekly rule
+    fn pattern(&self) -> (u32, NaiveTime) {
+        let start = local(self.start);
+
+        (start.weekday().num_days_from_monday(), start.time())
+    }
+}
+
+fn plain_event(details: &EventDetails, occurrence: &Occurrence) -> Event {
+    let mut event = Event::new();
+
+    describe_event(&mut event, details);
+    event.uid(&occurrence.id);
+    event.starts(occurrence.start);
+    event.ends(occurrence.end);
+
+    event.done()
+}
+
+/// Builds one weekly series from occurrences sharing everything but their times.
+///
+/// Occurrences on the most common weekday and time form the `RRULE`, with skipped weeks as
+/// `EXDATE`. Others are added with `RDATE`, and any with a different duration are overridden
+/// by an event with the same UID and a `RECURRENCE-ID`.
+fn series_events(details: &EventDetails, occurrences: Vec<Occurrence>) -> Vec<Event> {
+    let (pattern, _) = occurrences
+        .iter()
+        .map(Occurrence::pattern)
+        .counts()
+        .into_iter()
+        .max_by(|(pattern_a, count_a), (pattern_b, count_b)| {
+            count_a.cmp(count_b).then_with(|| pattern_b.cmp(pattern_a))
+        })
+        .expect("Series should have occurrences");
+
+    let (on_grid, off_grid): (Vec<_>, Vec<_>) = occurrences
+        .iter()
+        .partition(|occurrence| occurrence.pattern() == pattern);
+
+    let duration = on_grid
+        .iter()
+        .map(|occurrence| occurrence.duration())
+        .counts()
+        .into_iter()
+        .max_by(|(duration_a, count_a), (duration_b, count_b)| {
+            count_a
+                .cmp(count_b)
+                .then_with(|| duration_b.cmp(duration_a))
+        })
+        .map(|(duration, _)| duration)
+        .expect("Series should have occurrences on the grid");
+
+    let first = on_grid[0];
+    let last = on_grid[on_grid.len() - 1];
+    let uid = format!("series-{}", first.id);
+
+    let mut master = Event::new();
+
+    describe_event(&mut master, details);
+    master.uid(&uid);
+    master.starts(local_date_time(first.start));
+    master.ends(local_date_time(first.start + duration));
+
+    if on_grid.len() > 1 {
+        master.add_property(
+            "RRULE",
+            &format!("FREQ=WEEKLY;UNTIL={}", last.start.format("%Y%m%dT%H%M%SZ")),
+        );
+
+        let present = on_grid
+            .iter()
+            .map(|occurrence| local(occurrence.start))
+            .collect::<HashSet<_>>();
+
+        let weeks = (local(last.start) - local(first.start)).num_weeks();
+
+        for week in 1..weeks {
+            let expected = local(first.start) + Duration::weeks(week);
+
+            if !present.contains(&expected) {
+                master.append_multi_property(local_property("EXDATE", expected));
+            }
+        }
+    }
+
+    for occurrence in &off_grid {
+        master.append_multi_property(local_property("RDATE", local(occurrence.start)));
+    }
+
+    let overrides = occurrences
+        .iter()
+        .filter(|occurrence| occurrence.duration() != duration)
+        .map(|occurrence| {
+            let mut event = Event::new();
+
+            describe_event(&mut event, details);
+            event.uid(&uid);
+            event.append_property(local_property("RECURRENCE-ID", local(occurrence.start)));
+            event.starts(occurrence.start);
+            event.ends(occurrence.end);
+
+            event.done()
+        });
+
+    std::iter::once(master.done()).chain(overrides).collect()
+}
+
+/// Compresses activities that only differ in time into recurring events.
+///
+/// The expansion of the result has the same events as calling `activity_to_event` on every activity.
+pub fn activities_to_series<'a>(
+    activities: impl IntoIterator<Item = &'a Activity>,
+    custom_name: &Option<String>,
+) -> Vec<Event> {
+    let groups = activities
+        .into_iter()
+        .map(|activity| activity_details(activity, custom_name))
+        .into_group_map_by(|details| {
+            (
+                details.summary.clone(),
+                details.location.clone(),
+                details.description.clone(),
+            )
+        });
+
+    groups
+        .into_values()
+        .map(|group| {
+            group
+                .into_iter()
+                .sorted_by_key(|details| details.start)
+                .collect::<Vec<_>>()
+        })
+        .sorted_by_key(|group| group[0].start)
+        .flat_map(|group| {
+            let mut starts = HashSet::new();
+
+            // Instances are identified by their start, so duplicates have to stay separate events
+            let (series, duplicates): (Vec<_>, Vec<_>) = group
+                .into_iter()
+                .map(|details| {
+                    let occurrence = Occurrence {
+                        id: details.uid.clone(),
+                        start: details.start,
+                        end: details.end,
+                    };
+
+                    (details, occurrence)
+                })
+                .partition(|(_, occurrence)| starts.insert(occurrence.start));
+
+            let mut events = duplicates
+                .iter()
+                .map(|(details, occurrence)| plain_event(details, occurrence))
+                .collect::<Vec<_>>();
+
+            match series.len() {
+                0 => {}
+                1 => events.push(plain_event(&series[0].0, &series[0].1)),
+                _ => {
+                    let details = series[0].0.clone();
+                    let occurrences = series
+                        .into_iter()
+                        .map(|(_, occurrence)| occurrence)
+                        .collect();
+
+                    events.extend(series_events(&details, occurrences));
+                }
+            }
+
+            events
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calendar::activity_to_event::activity_to_event;
+    use crate::shared_types::{Room, StaffMember};
+    use chrono::TimeZone;
+    use icalendar::DatePerhapsTime;
+    use std::collections::HashMap;
+
+    fn activity(
+        id: &str,
+        title: &str,
+        start: (u32, u32, u32),
+        minutes: i64,
+        room: &str,
+    ) -> Activity {
+        let (month, day, hour) = start;
+        let start = Oslo
+            .with_ymd_and_hms(2023, month, day, hour, 15, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+
+        Activity {
+            id: id.to_owned(),
+            course_code: "TDT4100".to_owned(),
+            week: start.iso_week().week() as i32,
+            start,
+            end: start + Duration::minutes(minutes),
+            title: title.to_owned(),
+            summary: String::new(),
+            staff_members: vec![StaffMember {
+                id: None,
+                first_name: "Ola".to_owned(),
+                last_name: "Nordmann".to_owned(),
+            }],
+            student_groups: Vec::new(),
+            rooms: vec![Room {
+                id: None,
+                name: room.to_owned(),
+                building_name: "Realfagbygget".to_owned(),
+                url: String::new(),
+            }],
+        }
+    }
+
+    fn to_utc(date_time: NaiveDateTime) -> DateTime<Utc> {
+        Oslo.from_local_datetime(&date_time)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn event_time(time: Option<DatePerhapsTime>) -> DateTime<Utc> {
+        match time {
+            Some(DatePerhapsTime::DateTime(CalendarDateTime::Utc(time))) => time,
+            Some(DatePerhapsTime::DateTime(CalendarDateTime::WithTimezone {
+                date_time, ..
+            })) => to_utc(date_time),
+            time => panic!("Unexpected event time {time:?}"),
+        }
+    }
+
+    fn parse_local(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, LOCAL_FORMAT).unwrap()
+    }
+
+    fn details(event: &Event, start: DateTime<Utc>, end: DateTime<Utc>) -> EventDetails {
+        EventDetails {
+            uid: String::new(),
+            start,
+            end,
+            summary: event.get_summary().unwrap().to_owned(),
+            location: event.get_location().map(str::to_owned),
+            description: event.get_description().unwrap().to_owned(),
+        }
+    }
+
+    /// Expands the series the way a calendar client would, ignoring UIDs
+    fn expand(events: &[Event]) -> Vec<EventDetails> {
+        let overrides = events
+            .iter()
+            .filter_map(|event| {
+                let recurrence_id = event.property_value("RECURRENCE-ID")?;
+                let key = (event.get_uid()?.to_owned(), parse_local(recurrence_id));
+
+                Some((key, event))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut expanded = events
+            .iter()
+            .filter(|event| event.property_value("RECURRENCE-ID").is_none())
+            .flat_map(|event| {
+                let start = event_time(event.get_start());
+                let duration = event_time(event.get_end()) - start;
+                let local_start = local(start);
+
+                let multi_values = |key: &str| {
+                    event
+                        .multi_properties()
+                        .iter()
+                        .filter(|property| property.key() == key)
+                        .map(|property| parse_local(property.value()))
+                        .collect::<Vec<_>>()
+                };
+
+                let mut instances = vec![local_start];
+
+                if let Some(rule) = event.property_value("RRULE") {
+                    let until = rule.strip_prefix("FREQ=WEEKLY;UNTIL=").unwrap();
+                    let until = NaiveDateTime::parse_from_str(until, "%Y%m%dT%H%M%SZ")
+                        .unwrap()
+                        .and_utc();
+
+                    instances = (0..)
+                        .map(|week| local_start + Duration::weeks(week))
+                        .take_while(|instance| to_utc(*instance) <= until)
+                        .collect();
+                }
+
+                let exdates = multi_values("EXDATE");
+                instances.retain(|instance| !exdates.contains(instance));
+                instances.extend(multi_values("RDATE"));
+
+                instances
+                    .into_iter()
+                    .map(|instance| {
+                        let uid = event.get_uid().unwrap().to_owned();
+
+                        match overrides.get(&(uid, instance)) {
+                            Some(overridden) => details(
+                                overridden,
+                                event_time(overridden.get_start()),
+                                event_time(overridden.get_end()),
+                            ),
+                            None => details(event, to_utc(instance), to_utc(instance) + duration),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        expanded.sort_by_key(|details| details.start);
+        expanded
+    }
+
+    fn expected(activities: &[Activity]) -> Vec<EventDetails> {
+        activities
+            .iter()
+            .map(|activity| {
+                let event = activity_to_event(activity, &None);
+                details(
+                    &event,
+                    event_time(event.get_start()),
+                    event_time(event.get_end()),
+                )
+            })
+            .sorted_by_key(|details| details.start)
+            .collect()
+    }
+
+    #[test]
+    fn test_expands_to_same_events() {
+        let activities = vec![
+            // Weekly lecture across the daylight saving change, skipping one week
+            activity("1", "Forelesning", (3, 13, 8), 105, "R1"),
+            activity("2", "Forelesning", (3, 20, 8), 105, "R1"),
+            activity("3", "Forelesning", (3, 27, 8), 105, "R1"),
+            activity("4", "Forelesning", (4, 10, 8), 105, "R1"),
+            // Moved to a Tuesday one week, and a longer lecture in the last week
+            activity("5", "Forelesning", (4, 18, 10), 105, "R1"),
+            activity("6", "Forelesning", (4, 24, 8), 165, "R1"),
+            // Same time in another room is a separate series
+            activity("7", "Forelesning", (3, 14, 8), 105, "R2"),
+            activity("8", "Forelesning", (3, 21, 8), 105, "R2"),
+            // Single and duplicated activities
+            activity("9", "Øving", (3, 15, 12), 120, "R3"),
+            activity("10", "Lab", (3, 16, 12), 120, "R3"),
+            activity("11", "Lab", (3, 16, 12), 120, "R3"),
+        ];
+
+        let events = activities_to_series(&activities, &None);
+
+        assert_eq!(expand(&events), expected(&activities));
+        assert!(events.len() < activities.len());
+    }
+
+    #[test]
+    fn test_series_properties() {
+        let activities = vec![
+            activity("1", "Forelesning", (3, 13, 8), 105, "R1"),
+            activity("2", "Forelesning", (3, 20, 8), 105, "R1"),
+            activity("3", "Forelesning", (4, 3, 8), 105, "R1"),
+            activity("4", "Forelesning", (4, 5, 10), 105, "R1"),
+        ];
+
+        let events = activities_to_series(&activities, &None);
+
+        assert_eq!(events.len(), 1);
+
+        let series = events[0].to_string();
+
+        assert!(series.contains("UID:series-1"));
+        assert!(series.contains("DTSTART;TZID=Europe/Oslo:20230313T081500"));
+        assert!(series.contains("RRULE:FREQ=WEEKLY;UNTIL=20230403T061500Z"));
+        assert!(series.contains("EXDATE;TZID=Europe/Oslo:20230327T081500"));
+        assert!(series.contains("RDATE;TZID=Europe/Oslo:20230405T101500"));
+    }
+
+    #[test]
+    fn test_add_oslo_timezone() {
+        let activities = vec![
+            activity("1", "Forelesning", (3, 13, 8), 105, "R1"),
+            activity("2", "Forelesning", (3, 20, 8), 105, "R1"),
+        ];
+
+        let mut calendar = icalendar::Calendar::new();
+        calendar.extend(activities_to_series(&activities, &None));
+
+        let ics = add_oslo_timezone(&calendar.to_string());
+        let timezone_start = ics.find("BEGIN:VTIMEZONE").unwrap();
+
+        assert!(timezone_start < ics.find("BEGIN:VEVENT").unwrap());
+        assert!(ics[..timezone_start].ends_with("CALSCALE:GREGORIAN\r\n"));
+        assert_eq!(ics.matches("DTSTAMP").count(), 1);
+
+        let empty = add_oslo_timezone(&icalendar::Calendar::new().to_string());
+        assert!(empty.ends_with("END:VTIMEZONE\r\nEND:VCALENDAR\r\n"));
+    }
+}
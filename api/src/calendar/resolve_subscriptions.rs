@@ -0,0 +1,174 @@
+use crate::caching::activities_cache::activities_time_to_live;
+use crate::caching::exams_cache::exams_time_to_live;
+use crate::calendar::custom_events::custom_event_activities;
+use crate::calendar::filter_activities::subscription_includes;
+use crate::error::AppResult;
+use crate::shared_types::{Activity, CalendarSubscription, Exam, ExamIdentifier};
+use crate::staff::staff_activities_with_time;
+use crate::AppState;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use futures_util::future::try_join_all;
+use std::sync::Arc;
+use tracing::warn;
+
+#[derive(Debug)]
+pub struct ResolvedSubscription {
+    pub subscription: CalendarSubscription,
+    /// All activities fetched for the subscription, before filtering
+    pub activities: Arc<Vec<Activity>>,
+    /// Only fetched for course queries including exams
+    pub exams: Arc<Vec<Exam>>,
+    /// When the newest of the underlying cache entries was fetched
+    pub fetched_at: DateTime<Utc>,
+    /// When the first of the underlying cache entries expires
+    pub expires_at: DateTime<Utc>,
+}
+
+impl ResolvedSubscription {
+    pub fn custom_name(&self) -> &Option<String> {
+        match &self.subscription {
+            CalendarSubscription::Course(query) => &query.custom_name,
+            CalendarSubscription::Programme(query) => &query.custom_name,
+            CalendarSubscription::Room(_)
+            | CalendarSubscription::Staff(_)
+            | CalendarSubscription::Custom(_) => &None,
+        }
+    }
+
+    /// The custom name, or otherwise what the subscription is for, e.g. a course code
+    pub fn display_name(&self) -> String {
+        if let Some(custom_name) = self.custom_name() {
+            return custom_name.clone();
+        }
+
+        match &self.subscription {
+            CalendarSubscription::Course(query) => query.identifier.course_code.clone(),
+            CalendarSubscription::Programme(query) => query.identifier.programme.clone(),
+            CalendarSubscription::Room(identifier) => identifier.room_id.clone(),
+            CalendarSubscription::Staff(identifier) => identifier.staff_id.clone(),
+            CalendarSubscription::Custom(event) => event.title.clone(),
+        }
+    }
+
+    pub fn filtered_activities(&self) -> impl Iterator<Item = &Activity> {
+        self.activities
+            .iter()
+            .filter(|activity| subscription_includes(&self.subscription, activity))
+    }
+}
+
+fn to_chrono(duration: std::time::Duration) -> Duration {
+    Duration::from_std(duration).unwrap_or(Duration::zero())
+}
+
+pub async fn resolve_subscription(
+    app_state: &AppState,
+    subscription: CalendarSubscription,
+) -> AppResult<ResolvedSubscription> {
+    // Staff calendars combine several cache entries, the oldest of which expires first
+    let (activities, activities_fetched_at, oldest_fetched_at) = match &subscription {
+        CalendarSubscription::Course(query) => {
+            let activities_cache = &app_state.activities_cache;
+            let (activities, fetched_at) = activities_cache
+                .get_or_fetch_with_time(query.identifier.clone())
+                .await?;
+
+            if !activities.is_empty() {
+                app_state.prefetch_scheduler.note_request(&query.identifier);
+            }
+
+            (activities, fetched_at, fetched_at)
+        }
+        CalendarSubscription::Programme(query) => {
+            let programme_activities_cache = &app_state.programme_activities_cache;
+            let (activities, fetched_at) = programme_activities_cache
+                .get_or_fetch_with_time(query.identifier.clone())
+                .await?;
+
+            (activities, fetched_at, fetched_at)
+        }
+        CalendarSubscription::Room(identifier) => {
+            let room_activities_cache = &app_state.room_activities_cache;
+            let (activities, fetched_at) = room_activities_cache
+                .get_or_fetch_with_time(identifier.clone())
+                .await?;
+
+            (activities, fetched_at, fetched_at)
+        }
+        CalendarSubscription::Staff(identifier) => {
+            let (activities, newest_fetched_at, oldest_fetched_at) =
+                staff_activities_with_time(app_state, identifier).await?;
+
+            (Arc::new(activities), newest_fetched_at, oldest_fetched_at)
+        }
+        CalendarSubscription::Custom(event) => {
+            // Nothing is fetched, and the event only changes along with the query itself
+            let fetched_at = Utc.timestamp_opt(0, 0).unwrap();
+
+            (
+                Arc::new(custom_event_activities(event)),
+                fetched_at,
+                fetched_at,
+            )
+        }
+    };
+
+    // Empty timetables are not cached, so they shouldn't be cached downstream either
+    let mut expires_at = match &subscription {
+        CalendarSubscription::Custom(_) => DateTime::<Utc>::MAX_UTC,
+        _ if activities.is_empty() => oldest_fetched_at,
+        _ => oldest_fetched_at + to_chrono(activities_time_to_live()),
+    };
+    let mut fetched_at = activities_fetched_at;
+
+    let exams = match &subscription {
+        CalendarSubscription::Course(query) if query.include_exams => {
+            let exams_cache = &app_state.exams_cache;
+            let identifier = ExamIdentifier {
+                course_code: query.identifier.course_code.clone(),
+                semester: query.identifier.semester.clone(),
+            };
+
+            // Exams are an extra, so the timetable is still served without them
+            match exams_cache.get_or_fetch_with_time(identifier).await {
+                Ok((exams, exams_fetched_at)) => {
+                    fetched_at = fetched_at.max(exams_fetched_at);
+                    expires_at = expires_at.min(exams_fetched_at + to_chrono(exams_time_to_live()));
+
+                    exams
+                }
+                Err(error) => {
+                    warn!(
+                        "Failed to fetch exams for {}: {error:?}",
+                        query.identifier.course_code
+                    );
+
+                    // Not cached downstream, so the exams show up once they can be fetched
+                    expires_at = expires_at.min(Utc::now());
+
+                    Arc::new(Vec::new())
+                }
+            }
+        }
+        _ => Arc::new(Vec::new()),
+    };
+
+    Ok(ResolvedSubscription {
+        subscription,
+        activities,
+        exams,
+        fetched_at,
+        expires_at,
+    })
+}
+
+pub async fn resolve_subscriptions(
+    app_state: &AppState,
+    subscriptions: Vec<CalendarSubscription>,
+) -> AppResult<Vec<ResolvedSubscription>> {
+    let resolved = subscriptions
+        .into_iter()
+        .map(|subscription| resolve_subscription(app_state, subscription));
+
+    try_join_all(resolved).await
+}
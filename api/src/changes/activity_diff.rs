@@ -0,0 +1,116 @@
+use crate::shared_types::{Activity, ActivityChanges, MovedActivity};
+use std::collections::HashMap;
+
+fn is_moved(before: &Activity, after: &Activity) -> bool {
+    let rooms = |activity: &Activity| {
+        activity
+            .rooms
+            .iter()
+            .map(|room| (room.id.clone(), room.name.clone()))
+            .collect::<Vec<_>>()
+    };
+
+    before.start != after.start || before.end != after.end || rooms(before) != rooms(after)
+}
+
+/// Matches activities by id, so an activity moved upstream shows up as moved rather than replaced
+pub fn diff_activities(before: &[Activity], after: &[Activity]) -> ActivityChanges {
+    let before_by_id = before
+        .iter()
+        .map(|activity| (activity.id.as_str(), activity))
+        .collect::<HashMap<_, _>>();
+    let after_by_id = after
+        .iter()
+        .map(|activity| (activity.id.as_str(), activity))
+        .collect::<HashMap<_, _>>();
+
+    let mut changes = ActivityChanges::default();
+
+    for activity in after {
+        match before_by_id.get(activity.id.as_str()) {
+            Some(previous) if is_moved(previous, activity) => changes.moved.push(MovedActivity {
+                before: (*previous).clone(),
+                after: activity.clone(),
+            }),
+            Some(_) => {}
+            None => changes.added.push(activity.clone()),
+        }
+    }
+
+    changes.removed = before
+        .iter()
+        .filter(|activity| !after_by_id.contains_key(activity.id.as_str()))
+        .cloned()
+        .collect();
+
+    changes
+}
+
+impl ActivityChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.moved.is_empty()
+    }
+
+    /// Only the changes touching activities that match the predicate, before or after moving
+    pub fn filter(&self, include: impl Fn(&Activity) -> bool) -> ActivityChanges {
+        ActivityChanges {
+            added: self.added.iter().filter(|a| include(a)).cloned().collect(),
+            removed: self
+                .removed
+                .iter()
+                .filter(|a| include(a))
+                .cloned()
+                .collect(),
+            moved: self
+                .moved
+                .iter()
+                .filter(|moved| include(&moved.before) || include(&moved.after))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone, Utc};
+
+    fn activity(id: &str, hour: u32) -> Activity {
+        let start = Utc.with_ymd_and_hms(2023, 1, 10, hour, 0, 0).unwrap();
+
+        Activity {
+            id: id.to_owned(),
+            course_code: "TDT4100".to_owned(),
+            week: 2,
+            start,
+            end: start + Duration::hours(2),
+            title: "Forelesning".to_owned(),
+            summary: String::new(),
+            staff_members: Vec::new(),
+            student_groups: Vec::new(),
+            rooms: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_diff_activities() {
+        let before = vec![activity("a", 8), activity("b", 10), activity("c", 12)];
+        let after = vec![activity("a", 8), activity("b", 11), activity("d", 14)];
+
+        let changes = diff_activities(&before, &after);
+        let ids = |activities: &[Activity]| {
+            activities
+                .iter()
+                .map(|activity| activity.id.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids(&changes.added), vec!["d"]);
+        assert_eq!(ids(&changes.removed), vec!["c"]);
+        assert_eq!(changes.moved.len(), 1);
+        assert_eq!(changes.moved[0].after.start.format("%H").to_string(), "11");
+
+        assert!(diff_activities(&before, &before).is_empty());
+    }
+}
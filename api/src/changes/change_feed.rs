@@ -0,0 +1,264 @@
+use crate::caldav::xml::escape_xml;
+use crate::calendar::encode_query::decode_calendar_query;
+use crate::calendar::filter_activities::subscription_includes;
+use crate::changes::change_tracker::ChangeTracker;
+use crate::error::AppResult;
+use crate::shared_types::{Activity, CalendarQuery, CalendarSubscription, CourseChanges};
+use chrono::{DateTime, SecondsFormat, Utc};
+use chrono_tz::Europe::Oslo;
+use futures_util::{stream, Stream};
+use itertools::Itertools;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+
+/// The course entries of an encoded calendar query, as only courses are tracked for changes
+pub fn selection_courses(query: &str) -> AppResult<Vec<CalendarQuery>> {
+    let courses = decode_calendar_query(query)?
+        .into_iter()
+        .filter_map(|subscription| match subscription {
+            CalendarSubscription::Course(query) => Some(query),
+            _ => None,
+        })
+        .collect();
+
+    Ok(courses)
+}
+
+/// The changes to activities the selection includes, if any
+pub fn selection_changes(
+    course_changes: &CourseChanges,
+    courses: &[CalendarQuery],
+) -> Option<CourseChanges> {
+    let query = courses
+        .iter()
+        .find(|query| query.identifier == course_changes.identifier)?;
+
+    let subscription = CalendarSubscription::Course(query.clone());
+    let changes = course_changes
+        .changes
+        .filter(|activity| subscription_includes(&subscription, activity));
+
+    (!changes.is_empty()).then(|| CourseChanges {
+        changes,
+        ..course_changes.clone()
+    })
+}
+
+/// Recent changes to the selection, newest first
+pub fn feed_entries(tracker: &ChangeTracker, courses: &[CalendarQuery]) -> Vec<CourseChanges> {
+    courses
+        .iter()
+        .flat_map(|query| tracker.history(&query.identifier))
+        .filter_map(|course_changes| selection_changes(&course_changes, courses))
+        .sorted_by(|a, b| b.detected_at.cmp(&a.detected_at))
+        .collect()
+}
+
+fn describe_activity(activity: &Activity) -> String {
+    let start = activity.start.with_timezone(&Oslo);
+    let end = activity.end.with_timezone(&Oslo);
+
+    let rooms = activity.rooms.iter().map(|room| &room.name).join(", ");
+
+    format!(
+        "{} {}, {}–{}{}",
+        activity.course_code,
+        activity.title,
+        start.format("%a %d %b %H:%M"),
+        end.format("%H:%M"),
+        if rooms.is_empty() {
+            String::new()
+        } else {
+            format!(" ({rooms})")
+        }
+    )
+}
+
+/// One line per change, with both the old and new time and room of moved activities
+pub fn describe_changes(course_changes: &[CourseChanges]) -> String {
+    let mut lines = Vec::new();
+
+    for course_changes in course_changes {
+        let changes = &course_changes.changes;
+
+        lines.extend(
+            changes
+                .added
+                .iter()
+                .map(|activity| format!("Added: {}", describe_activity(activity))),
+        );
+        lines.extend(
+            changes
+                .removed
+                .iter()
+                .map(|activity| format!("Cancelled: {}", describe_activity(activity))),
+        );
+        lines.extend(changes.moved.iter().map(|moved| {
+            format!(
+                "Moved: {}\n  now {}",
+                describe_activity(&moved.before),
+                describe_activity(&moved.after)
+            )
+        }));
+    }
+
+    lines.join("\n")
+}
+
+fn atom_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Atom feed with one entry per detected change to a course
+pub fn changes_to_atom(query: &str, entries: &[CourseChanges]) -> String {
+    let updated = entries
+        .iter()
+        .map(|course_changes| course_changes.detected_at)
+        .max()
+        .unwrap_or_else(Utc::now);
+
+    let mut feed = String::new();
+
+    feed += r#"<?xml version="1.0" encoding="utf-8"?>"#;
+    feed += r#"<feed xmlns="http://www.w3.org/2005/Atom">"#;
+    feed += "<title>Timetable changes</title>";
+    feed += &format!("<id>urn:ntnu-timeplan:changes:{}</id>", escape_xml(query));
+    feed += &format!("<updated>{}</updated>", atom_time(updated));
+    feed += "<author><name>NTNU Timeplan</name></author>";
+
+    for course_changes in entries {
+        let identifier = &course_changes.identifier;
+        let changes = &course_changes.changes;
+        let count = changes.added.len() + changes.removed.len() + changes.moved.len();
+
+        feed += "<entry>";
+        feed += &format!(
+            "<id>urn:ntnu-timeplan:changes:{}:{}:{}:{}</id>",
+            escape_xml(&identifier.course_code),
+            identifier.course_term,
+            escape_xml(&identifier.semester),
+            course_changes.detected_at.timestamp_millis()
+        );
+        feed += &format!(
+            "<title>{}: {count} {}</title>",
+            escape_xml(&identifier.course_code),
+            if count == 1 { "change" } else { "changes" }
+        );
+        feed += &format!(
+            "<updated>{}</updated>",
+            atom_time(course_changes.detected_at)
+        );
+        feed += &format!(
+            r#"<content type="text">{}</content>"#,
+            escape_xml(&describe_changes(std::slice::from_ref(course_changes)))
+        );
+        feed += "</entry>";
+    }
+
+    feed += "</feed>";
+
+    feed
+}
+
+/// Changes to the selection as they are detected, until the stream is dropped
+pub fn selection_change_stream(
+    tracker: &ChangeTracker,
+    courses: Vec<CalendarQuery>,
+) -> impl Stream<Item = CourseChanges> + Send + Sync + 'static {
+    let mut changes = tracker.subscribe();
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        loop {
+            let course_changes = tokio::select! {
+                received = changes.recv() => received,
+                _ = sender.closed() => break,
+            };
+
+            match course_changes {
+                Ok(course_changes) => {
+                    if let Some(relevant) = selection_changes(&course_changes, &courses) {
+                        if sender.send(relevant).is_err() {
+                            break;
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+
+    stream::unfold(receiver, |mut receiver| async move {
+        let course_changes = receiver.recv().await?;
+
+        Some((course_changes, receiver))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::caldav::xml::parse_xml;
+    use crate::shared_types::{ActivityChanges, CourseIdentifier};
+    use chrono::{Duration, TimeZone};
+
+    fn activity(id: &str, group: &str) -> Activity {
+        let start = Utc.with_ymd_and_hms(2023, 1, 10, 8, 15, 0).unwrap();
+
+        Activity {
+            id: id.to_owned(),
+            course_code: "TDT4100".to_owned(),
+            week: 2,
+            start,
+            end: start + Duration::hours(2),
+            title: "Forelesning".to_owned(),
+            summary: String::new(),
+            staff_members: Vec::new(),
+            student_groups: vec![group.to_owned()],
+            rooms: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_changes_to_atom() {
+        let identifier = CourseIdentifier {
+            course_code: "TDT4100".to_owned(),
+            course_term: 1,
+            semester: "23v".to_owned(),
+        };
+        let course_changes = CourseChanges {
+            identifier: identifier.clone(),
+            detected_at: Utc.with_ymd_and_hms(2023, 1, 9, 12, 0, 0).unwrap(),
+            changes: ActivityChanges {
+                removed: vec![activity("a", "MTDT_1"), activity("b", "BIT_1")],
+                ..Default::default()
+            },
+        };
+        let courses = vec![CalendarQuery {
+            identifier,
+            student_groups: vec!["MTDT_1".to_owned()],
+            custom_name: None,
+            include_exams: false,
+        }];
+
+        let entries = vec![selection_changes(&course_changes, &courses).unwrap()];
+        let feed = parse_xml(&changes_to_atom("abc", &entries)).unwrap();
+
+        let atom = "http://www.w3.org/2005/Atom";
+        let entry = feed.child(atom, "entry").unwrap();
+
+        assert_eq!(
+            feed.child(atom, "updated").unwrap().text,
+            "2023-01-09T12:00:00Z"
+        );
+        assert_eq!(
+            entry.child(atom, "title").unwrap().text,
+            "TDT4100: 1 change"
+        );
+        assert_eq!(
+            entry.child(atom, "content").unwrap().text,
+            "Cancelled: TDT4100 Forelesning, Tue 10 Jan 09:15–11:15"
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use crate::calendar::resolve_subscriptions::resolve_subscriptions;
+use crate::changes::change_feed::{changes_to_atom, feed_entries, selection_courses};
+use crate::error::AppResult;
+use crate::shared_types::CalendarSubscription;
+use crate::AppState;
+use axum::extract::{Query, State};
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct ChangeFeedQuery {
+    query: String,
+}
+
+pub async fn change_feed_handler(
+    query: Query<ChangeFeedQuery>,
+    State(app_state): State<AppState>,
+) -> AppResult<Response> {
+    let courses = selection_courses(&query.query)?;
+
+    // Fetches the courses again once their cache expires, which is when changes are detected
+    let subscriptions = courses
+        .iter()
+        .cloned()
+        .map(CalendarSubscription::Course)
+        .collect();
+    resolve_subscriptions(&app_state, subscriptions).await?;
+
+    let entries = feed_entries(&app_state.change_tracker, &courses);
+
+    let response = (
+        [(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+        changes_to_atom(&query.query, &entries),
+    )
+        .into_response();
+
+    Ok(response)
+}
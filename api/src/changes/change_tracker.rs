@@ -0,0 +1,261 @@
+use crate::changes::activity_diff::diff_activities;
+use crate::shared_types::{Activity, CourseChanges, CourseIdentifier};
+use crate::AppState;
+use chrono::Utc;
+use itertools::Itertools;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use time::ext::NumericalStdDuration;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, info, warn};
+
+/// How often watched courses are checked, which only fetches them once their cache expires
+fn poll_interval() -> Duration {
+    10.std_minutes()
+}
+
+/// How long courses that come back empty are left alone, as every poll retries them upstream
+fn empty_course_poll_interval() -> Duration {
+    1.std_days()
+}
+
+/// Detected changes kept per course, for feeds of what changed recently
+const MAX_HISTORY: usize = 100;
+
+const CHANGES_CHANNEL_CAPACITY: usize = 64;
+
+/// Remembers the last fetch of every course timetable, to tell what changed in the next one
+pub struct ChangeTracker {
+    previous: Mutex<HashMap<CourseIdentifier, Arc<Vec<Activity>>>>,
+    history: Mutex<HashMap<CourseIdentifier, VecDeque<CourseChanges>>>,
+    changes: broadcast::Sender<CourseChanges>,
+}
+
+impl Default for ChangeTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChangeTracker {
+    pub fn new() -> Self {
+        let (changes, _) = broadcast::channel(CHANGES_CHANNEL_CAPACITY);
+
+        Self {
+            previous: Mutex::new(HashMap::new()),
+            history: Mutex::new(HashMap::new()),
+            changes,
+        }
+    }
+
+    /// Receives every change detected from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<CourseChanges> {
+        self.changes.subscribe()
+    }
+
+    /// The most recent changes to the course since the server started, oldest first
+    pub fn history(&self, identifier: &CourseIdentifier) -> Vec<CourseChanges> {
+        self.history
+            .lock()
+            .expect("Change tracker lock should not be poisoned")
+            .get(identifier)
+            .map(|history| history.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn remembers(&self, identifier: &CourseIdentifier) -> bool {
+        self.previous
+            .lock()
+            .expect("Change tracker lock should not be poisoned")
+            .contains_key(identifier)
+    }
+
+    /// Compares the next fetch against `activities`, like a stored fetch from before a restart
+    pub fn seed(&self, identifier: &CourseIdentifier, activities: Arc<Vec<Activity>>) {
+        self.previous
+            .lock()
+            .expect("Change tracker lock should not be poisoned")
+            .entry(identifier.clone())
+            .or_insert(activities);
+    }
+
+    /// Changes since the previous fetch, if there was one and anything changed
+    pub fn record(
+        &self,
+        identifier: &CourseIdentifier,
+        activities: Arc<Vec<Activity>>,
+    ) -> Option<CourseChanges> {
+        let previous = self
+            .previous
+            .lock()
+            .expect("Change tracker lock should not be poisoned")
+            .insert(identifier.clone(), activities.clone())?;
+
+        let changes = diff_activities(&previous, &activities);
+
+        if changes.is_empty() {
+            return None;
+        }
+
+        let course_changes = CourseChanges {
+            identifier: identifier.clone(),
+            detected_at: Utc::now(),
+            changes,
+        };
+
+        let mut history = self
+            .history
+            .lock()
+            .expect("Change tracker lock should not be poisoned");
+        let course_history = history.entry(identifier.clone()).or_default();
+
+        course_history.push_back(course_changes.clone());
+
+        if course_history.len() > MAX_HISTORY {
+            course_history.pop_front();
+        }
+
+        // Sending only fails when nobody is listening
+        let _ = self.changes.send(course_changes.clone());
+
+        Some(course_changes)
+    }
+}
+
+/// Stores and diffs every course timetable fetch, and notifies webhooks and email subscribers about the changes
+///
+/// Courses someone is notified about are also fetched again when they expire, so changes are
+/// found without anyone visiting their calendars
+pub fn spawn_change_detection(app_state: AppState) {
+    let mut fetches = app_state.activities_cache.subscribe();
+    let listener_state = app_state.clone();
+
+    tokio::spawn(async move {
+        loop {
+            let (identifier, activities) = match fetches.recv().await {
+                Ok(fetch) => fetch,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Change detection skipped {skipped} timetable fetches");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            let change_tracker = &listener_state.change_tracker;
+            let snapshot_store = &listener_state.snapshot_store;
+
+            if !change_tracker.remembers(&identifier) {
+                if let Ok(Some(latest)) = snapshot_store.latest(&identifier).await {
+                    change_tracker.seed(&identifier, Arc::new(latest));
+                }
+            }
+
+            if snapshot_store
+                .record(&identifier, &activities)
+                .await
+                .is_err()
+            {
+                error!("Failed to store snapshot of {:?}", &identifier);
+            }
+
+            let Some(course_changes) = listener_state
+                .change_tracker
+                .record(&identifier, activities)
+            else {
+                continue;
+            };
+
+            info!("Detected changes to {:?}", &identifier);
+
+            let webhook_registry = listener_state.webhook_registry.clone();
+            let email_notifications = listener_state.email_notifications.clone();
+            tokio::spawn(async move {
+                webhook_registry.notify(&course_changes).await;
+                email_notifications.handle_changes(&course_changes).await;
+            });
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(poll_interval());
+        // Courses without a timetable, and when to poll them again
+        let mut empty_courses = HashMap::<CourseIdentifier, Instant>::new();
+
+        loop {
+            interval.tick().await;
+
+            let now = Instant::now();
+            empty_courses.retain(|_, poll_again_at| *poll_again_at > now);
+
+            let subscribed_courses = app_state
+                .webhook_registry
+                .subscribed_courses()
+                .into_iter()
+                .chain(app_state.email_notifications.subscribed_courses())
+                .unique();
+
+            for identifier in subscribed_courses {
+                if empty_courses.contains_key(&identifier) {
+                    continue;
+                }
+
+                match app_state
+                    .activities_cache
+                    .get_or_fetch(identifier.clone())
+                    .await
+                {
+                    Ok(activities) if activities.is_empty() => {
+                        warn!("Not polling {:?} for a while, as it is empty", &identifier);
+                        empty_courses.insert(identifier, now + empty_course_poll_interval());
+                    }
+                    Ok(_) => {}
+                    Err(fetch_error) => {
+                        error!("Failed to poll course for changes: {fetch_error:?}");
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn activities(hour: u32) -> Arc<Vec<Activity>> {
+        let start = Utc.with_ymd_and_hms(2023, 1, 10, hour, 0, 0).unwrap();
+
+        Arc::new(vec![Activity {
+            id: "a".to_owned(),
+            course_code: "TDT4100".to_owned(),
+            week: 2,
+            start,
+            end: start + chrono::Duration::hours(2),
+            title: "Forelesning".to_owned(),
+            summary: String::new(),
+            staff_members: Vec::new(),
+            student_groups: Vec::new(),
+            rooms: Vec::new(),
+        }])
+    }
+
+    #[test]
+    fn test_record() {
+        let tracker = ChangeTracker::new();
+        let identifier = CourseIdentifier {
+            course_code: "TDT4100".to_owned(),
+            course_term: 1,
+            semester: "23v".to_owned(),
+        };
+
+        assert!(tracker.record(&identifier, activities(8)).is_none());
+        assert!(tracker.record(&identifier, activities(8)).is_none());
+
+        let course_changes = tracker.record(&identifier, activities(10)).unwrap();
+        assert_eq!(course_changes.changes.moved.len(), 1);
+        assert_eq!(tracker.history(&identifier).len(), 1);
+    }
+}
@@ -0,0 +1,6 @@
+pub mod activity_diff;
+pub mod change_feed;
+pub mod change_feed_handler;
+pub mod change_tracker;
+pub mod snapshots;
+pub mod webhooks;
@@ -0,0 +1,501 @@
+use crate::changes::activity_diff::diff_activities;
+use crate::error::AppResult;
+use crate::shared_types::{Activity, ActivityChanges, CourseIdentifier, SnapshotSummary};
+use crate::storage::{data_directory, storage_error};
+use chrono::{DateTime, Utc};
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+/// Older snapshots are dropped once a course has more than this
+const MAX_SNAPSHOTS_PER_COURSE: usize = 100;
+
+/// One line of a snapshot file
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredSnapshot<'a> {
+    /// Missing from files written before snapshots were dropped, where it is the line number
+    #[serde(default)]
+    id: Option<u32>,
+    fetched_at: DateTime<Utc>,
+    activities: Cow<'a, [Activity]>,
+}
+
+/// Where a snapshot is in the file of its course
+struct SnapshotEntry {
+    summary: SnapshotSummary,
+    offset: u64,
+    /// Without the newline
+    length: u64,
+}
+
+/// What is kept in memory about the snapshots of a course
+#[derive(Default)]
+struct CourseSnapshots {
+    entries: Vec<SnapshotEntry>,
+    /// Hash of the activities of the newest snapshot, to tell whether a fetch is distinct
+    latest_digest: Option<Vec<u8>>,
+}
+
+impl CourseSnapshots {
+    fn entry(&self, id: u32) -> Option<&SnapshotEntry> {
+        self.entries
+            .binary_search_by_key(&id, |entry| entry.summary.id)
+            .ok()
+            .map(|index| &self.entries[index])
+    }
+
+    fn file_length(&self) -> u64 {
+        self.entries
+            .last()
+            .map(|entry| entry.offset + entry.length + 1)
+            .unwrap_or_default()
+    }
+}
+
+fn activities_digest(activities: &[Activity]) -> AppResult<Vec<u8>> {
+    let json = serde_json::to_vec(activities).map_err(storage_error)?;
+
+    Ok(digest(&SHA256, &json).as_ref().to_vec())
+}
+
+async fn read_line(path: &Path, entry: &SnapshotEntry) -> AppResult<Vec<Activity>> {
+    let mut file = File::open(path).await.map_err(storage_error)?;
+    file.seek(SeekFrom::Start(entry.offset))
+        .await
+        .map_err(storage_error)?;
+
+    let mut line = vec![0; entry.length as usize];
+    file.read_exact(&mut line).await.map_err(storage_error)?;
+
+    let snapshot: StoredSnapshot = serde_json::from_slice(&line).map_err(storage_error)?;
+
+    Ok(snapshot.activities.into_owned())
+}
+
+/// Every distinct fetched timetable of a course, appended as JSON lines to one file per course
+pub struct SnapshotStore {
+    directory: PathBuf,
+    /// Every course has its own lock, so disk access for one course never holds up the others
+    courses: std::sync::Mutex<HashMap<CourseIdentifier, Arc<Mutex<CourseSnapshots>>>>,
+}
+
+impl SnapshotStore {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            courses: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(data_directory().join("snapshots"))
+    }
+
+    /// File of the course, unless the identifier has characters that could escape the directory
+    fn path(&self, identifier: &CourseIdentifier) -> Option<PathBuf> {
+        let is_safe = |part: &str| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|character| character.is_ascii_alphanumeric() || character == '-')
+        };
+
+        if !is_safe(&identifier.course_code) || !is_safe(&identifier.semester) {
+            return None;
+        }
+
+        Some(self.directory.join(format!(
+            "{}_{}_{}.jsonl",
+            identifier.semester, identifier.course_code, identifier.course_term
+        )))
+    }
+
+    /// Reads where every snapshot of the course is, ignoring a last line still being written
+    async fn load(&self, path: &Path) -> AppResult<CourseSnapshots> {
+        let contents = match fs::read(path).await {
+            Ok(contents) => contents,
+            Err(read_error) if read_error.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(read_error) => return Err(storage_error(read_error)),
+        };
+
+        let mut snapshots = CourseSnapshots::default();
+        let mut offset = 0;
+
+        while let Some(length) = contents[offset..].iter().position(|byte| *byte == b'\n') {
+            let line = &contents[offset..offset + length];
+            let snapshot: StoredSnapshot = serde_json::from_slice(line).map_err(storage_error)?;
+
+            snapshots.entries.push(SnapshotEntry {
+                summary: SnapshotSummary {
+                    id: snapshot.id.unwrap_or(snapshots.entries.len() as u32),
+                    fetched_at: snapshot.fetched_at,
+                    activity_count: snapshot.activities.len() as u32,
+                },
+                offset: offset as u64,
+                length: length as u64,
+            });
+            snapshots.latest_digest = Some(activities_digest(&snapshot.activities)?);
+
+            offset += length + 1;
+        }
+
+        Ok(snapshots)
+    }
+
+    /// The snapshots of the course, loading them from disk the first time
+    ///
+    /// Courses without snapshots are only kept in memory when `create` is set, as anyone can ask
+    /// for courses that are never fetched
+    async fn course(
+        &self,
+        identifier: &CourseIdentifier,
+        path: &Path,
+        create: bool,
+    ) -> AppResult<Option<Arc<Mutex<CourseSnapshots>>>> {
+        let loaded = self
+            .courses
+            .lock()
+            .expect("Snapshot store lock should not be poisoned")
+            .get(identifier)
+            .cloned();
+
+        if loaded.is_some() {
+            return Ok(loaded);
+        }
+
+        let snapshots = self.load(path).await?;
+
+        if snapshots.entries.is_empty() && !create {
+            return Ok(None);
+        }
+
+        // Kept if another request loaded the course meanwhile, as it may have recorded since
+        let course = self
+            .courses
+            .lock()
+            .expect("Snapshot store lock should not be poisoned")
+            .entry(identifier.clone())
+            .or_insert_with(|| Arc::new(Mutex::new(snapshots)))
+            .clone();
+
+        Ok(Some(course))
+    }
+
+    /// Rewrites the file with only the newest snapshots
+    async fn drop_oldest(&self, path: &Path, snapshots: &mut CourseSnapshots) -> AppResult<()> {
+        let dropped = snapshots
+            .entries
+            .len()
+            .saturating_sub(MAX_SNAPSHOTS_PER_COURSE);
+
+        if dropped == 0 {
+            return Ok(());
+        }
+
+        let kept_from = snapshots.entries[dropped].offset;
+
+        let mut file = File::open(path).await.map_err(storage_error)?;
+        file.seek(SeekFrom::Start(kept_from))
+            .await
+            .map_err(storage_error)?;
+
+        let mut kept = Vec::new();
+        file.read_to_end(&mut kept).await.map_err(storage_error)?;
+
+        // Renaming is atomic, so a crash while writing never loses the history
+        let temporary_path = path.with_extension("jsonl.tmp");
+        fs::write(&temporary_path, kept)
+            .await
+            .map_err(storage_error)?;
+        fs::rename(&temporary_path, path)
+            .await
+            .map_err(storage_error)?;
+
+        snapshots.entries.drain(..dropped);
+
+        for entry in &mut snapshots.entries {
+            entry.offset -= kept_from;
+        }
+
+        Ok(())
+    }
+
+    /// Appends the activities unless they are identical to the newest snapshot
+    ///
+    /// Returns whether a snapshot was added
+    pub async fn record(
+        &self,
+        identifier: &CourseIdentifier,
+        activities: &[Activity],
+    ) -> AppResult<bool> {
+        let Some(path) = self.path(identifier) else {
+            return Ok(false);
+        };
+
+        let course = self
+            .course(identifier, &path, true)
+            .await?
+            .expect("Courses are created when missing");
+
+        // Held until the file is written, so concurrent fetches of the course can't interleave
+        let mut snapshots = course.lock().await;
+
+        let digest = activities_digest(activities)?;
+
+        if snapshots.latest_digest.as_ref() == Some(&digest) {
+            return Ok(false);
+        }
+
+        let id = snapshots
+            .entries
+            .last()
+            .map(|entry| entry.summary.id + 1)
+            .unwrap_or_default();
+        let fetched_at = Utc::now();
+        let mut line = serde_json::to_string(&StoredSnapshot {
+            id: Some(id),
+            fetched_at,
+            activities: Cow::Borrowed(activities),
+        })
+        .map_err(storage_error)?;
+        line.push('\n');
+
+        fs::create_dir_all(&self.directory)
+            .await
+            .map_err(storage_error)?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&path)
+            .await
+            .map_err(storage_error)?;
+
+        // A line left half written by a crash is cut off, so the offsets match the file
+        let offset = snapshots.file_length();
+        file.set_len(offset).await.map_err(storage_error)?;
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(storage_error)?;
+        file.write_all(line.as_bytes())
+            .await
+            .map_err(storage_error)?;
+
+        snapshots.entries.push(SnapshotEntry {
+            summary: SnapshotSummary {
+                id,
+                fetched_at,
+                activity_count: activities.len() as u32,
+            },
+            offset,
+            length: line.len() as u64 - 1,
+        });
+        snapshots.latest_digest = Some(digest);
+
+        self.drop_oldest(&path, &mut snapshots).await?;
+
+        Ok(true)
+    }
+
+    /// Snapshots of the course, oldest first
+    pub async fn list(&self, identifier: &CourseIdentifier) -> AppResult<Vec<SnapshotSummary>> {
+        let Some(path) = self.path(identifier) else {
+            return Ok(Vec::new());
+        };
+
+        let Some(course) = self.course(identifier, &path, false).await? else {
+            return Ok(Vec::new());
+        };
+
+        let snapshots = course.lock().await;
+
+        Ok(snapshots
+            .entries
+            .iter()
+            .map(|entry| entry.summary.clone())
+            .collect())
+    }
+
+    /// Reads only the line of the snapshot
+    pub async fn activities(
+        &self,
+        identifier: &CourseIdentifier,
+        id: u32,
+    ) -> AppResult<Option<Vec<Activity>>> {
+        let Some(path) = self.path(identifier) else {
+            return Ok(None);
+        };
+
+        let Some(course) = self.course(identifier, &path, false).await? else {
+            return Ok(None);
+        };
+
+        // Held while reading, as dropping old snapshots moves the others
+        let snapshots = course.lock().await;
+
+        match snapshots.entry(id) {
+            Some(entry) => Ok(Some(read_line(&path, entry).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The newest snapshot, which is the last fetch from before a restart
+    pub async fn latest(&self, identifier: &CourseIdentifier) -> AppResult<Option<Vec<Activity>>> {
+        let latest = self
+            .list(identifier)
+            .await?
+            .last()
+            .map(|summary| summary.id);
+
+        match latest {
+            Some(id) => self.activities(identifier, id).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Changes from one snapshot to another, if both exist
+    pub async fn diff(
+        &self,
+        identifier: &CourseIdentifier,
+        from: u32,
+        to: u32,
+    ) -> AppResult<Option<ActivityChanges>> {
+        let (Some(before), Some(after)) = (
+            self.activities(identifier, from).await?,
+            self.activities(identifier, to).await?,
+        ) else {
+            return Ok(None);
+        };
+
+        Ok(Some(diff_activities(&before, &after)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::temporary_directory;
+    use chrono::{Duration, TimeZone};
+
+    fn activities(hour: u32) -> Vec<Activity> {
+        let start = Utc.with_ymd_and_hms(2023, 1, 10, hour, 0, 0).unwrap();
+
+        vec![Activity {
+            id: "a".to_owned(),
+            course_code: "TDT4100".to_owned(),
+            week: 2,
+            start,
+            end: start + Duration::hours(2),
+            title: "Lab".to_owned(),
+            summary: String::new(),
+            staff_members: Vec::new(),
+            student_groups: Vec::new(),
+            rooms: Vec::new(),
+        }]
+    }
+
+    fn identifier() -> CourseIdentifier {
+        CourseIdentifier {
+            course_code: "TDT4100".to_owned(),
+            course_term: 1,
+            semester: "23v".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_and_diff() {
+        let directory = temporary_directory("snapshots");
+        let identifier = identifier();
+
+        let store = SnapshotStore::new(&directory);
+        assert!(store.record(&identifier, &activities(8)).await.unwrap());
+        assert!(!store.record(&identifier, &activities(8)).await.unwrap());
+        assert!(store.record(&identifier, &activities(10)).await.unwrap());
+
+        // A new store reads the history back from disk
+        let store = SnapshotStore::new(&directory);
+        assert_eq!(store.list(&identifier).await.unwrap().len(), 2);
+        assert!(!store.record(&identifier, &activities(10)).await.unwrap());
+
+        let changes = store.diff(&identifier, 0, 1).await.unwrap().unwrap();
+        assert_eq!(changes.moved.len(), 1);
+        assert!(store.diff(&identifier, 0, 2).await.unwrap().is_none());
+
+        let traversal = CourseIdentifier {
+            course_code: "../TDT4100".to_owned(),
+            ..identifier
+        };
+        assert!(store.list(&traversal).await.unwrap().is_empty());
+
+        fs::remove_dir_all(directory).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replaces_partial_line() {
+        let directory = temporary_directory("snapshots");
+        let identifier = identifier();
+
+        let store = SnapshotStore::new(&directory);
+        assert!(store.record(&identifier, &activities(8)).await.unwrap());
+
+        // As if the server crashed while writing the next snapshot
+        let path = store.path(&identifier).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).await.unwrap();
+        file.write_all(b"{\"id\":1,\"fetchedAt").await.unwrap();
+
+        let store = SnapshotStore::new(&directory);
+        assert!(store.record(&identifier, &activities(10)).await.unwrap());
+
+        let store = SnapshotStore::new(&directory);
+        assert_eq!(store.list(&identifier).await.unwrap().len(), 2);
+        assert_eq!(
+            store.latest(&identifier).await.unwrap().unwrap()[0].start,
+            activities(10)[0].start
+        );
+
+        fs::remove_dir_all(directory).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_drops_oldest_snapshots() {
+        let directory = temporary_directory("snapshots");
+        let identifier = identifier();
+
+        let store = SnapshotStore::new(&directory);
+
+        for number in 0..MAX_SNAPSHOTS_PER_COURSE + 2 {
+            let hour = number as u32 % 24;
+            let mut activities = activities(hour);
+            activities[0].id = number.to_string();
+
+            assert!(store.record(&identifier, &activities).await.unwrap());
+        }
+
+        // Ids are kept, and the newest snapshots can still be read after the file is rewritten
+        for store in [store, SnapshotStore::new(&directory)] {
+            let summaries = store.list(&identifier).await.unwrap();
+            assert_eq!(summaries.len(), MAX_SNAPSHOTS_PER_COURSE);
+            assert_eq!(summaries[0].id, 2);
+
+            let newest = MAX_SNAPSHOTS_PER_COURSE as u32 + 1;
+            let latest = store.latest(&identifier).await.unwrap().unwrap();
+            assert_eq!(latest[0].id, newest.to_string());
+            assert!(store.activities(&identifier, 1).await.unwrap().is_none());
+            assert_eq!(
+                store.activities(&identifier, 2).await.unwrap().unwrap()[0].id,
+                "2"
+            );
+        }
+
+        fs::remove_dir_all(directory).await.unwrap();
+    }
+}
@@ -0,0 +1,628 @@
+use crate::caching::activities_cache::ActivitiesCache;
+use crate::calendar::filter_activities::includes_target_group;
+use crate::error::{AppError, AppResult};
+use crate::fetch::http_client::USER_AGENT;
+use crate::notifications::rate_limit::RateLimiter;
+use crate::shared_types::{
+    ActivityChanges, CourseChanges, CourseIdentifier, WebhookRegistration,
+    WebhookSubscriptionRequest, WebhookUnsubscribeRequest,
+};
+use crate::storage::JsonFile;
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
+use futures_util::future::join_all;
+use hyper::client::connect::dns::Name;
+use itertools::Itertools;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Url};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use time::ext::NumericalStdDuration;
+use tokio::net::lookup_host;
+use tracing::warn;
+use url::Host;
+
+/// Carries `sha256=` and the hex HMAC-SHA256 of the body, keyed with the subscription secret
+pub const SIGNATURE_HEADER: &str = "X-Timeplan-Signature-256";
+
+/// Webhooks every client can register per hour
+const MAX_REGISTRATIONS_PER_CLIENT: usize = 5;
+
+fn request_timeout() -> Duration {
+    10.std_seconds()
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct WebhookSubscription {
+    secret: String,
+    identifier: CourseIdentifier,
+    student_groups: Vec<String>,
+    url: Url,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WebhookPayload<'a> {
+    subscription_id: &'a str,
+    identifier: &'a CourseIdentifier,
+    detected_at: DateTime<Utc>,
+    changes: ActivityChanges,
+}
+
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+
+    format!(
+        "sha256={}",
+        HEXLOWER.encode(hmac::sign(&key, body).as_ref())
+    )
+}
+
+pub fn random_hex(length: usize) -> AppResult<String> {
+    let mut bytes = vec![0; length];
+
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| AppError::InternalError)?;
+
+    Ok(HEXLOWER.encode(&bytes))
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [first, second, third, _] = ip.octets();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "This network", shared address space for carrier-grade NAT, protocol assignments,
+        // benchmarking and reserved
+        || first == 0
+        || (first == 100 && (64..128).contains(&second))
+        || (first == 192 && second == 0 && third == 0)
+        || (first == 198 && (second == 18 || second == 19))
+        || first >= 240)
+}
+
+/// Whether the address is on the public internet, so webhooks can't be used to reach our own network
+fn is_public_address(ip: IpAddr) -> bool {
+    let ip = match ip {
+        IpAddr::V4(ip) => return is_public_ipv4(ip),
+        IpAddr::V6(ip) => ip,
+    };
+
+    if let Some(mapped) = ip.to_ipv4_mapped() {
+        return is_public_ipv4(mapped);
+    }
+
+    let segments = ip.segments();
+    let embedded_ipv4 = |high: u16, low: u16| {
+        let [a, b] = high.to_be_bytes();
+        let [c, d] = low.to_be_bytes();
+
+        Ipv4Addr::new(a, b, c, d)
+    };
+
+    match segments {
+        // NAT64 and 6to4 reach the IPv4 address they embed
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] => is_public_ipv4(embedded_ipv4(high, low)),
+        [0x2002, high, low, ..] => is_public_ipv4(embedded_ipv4(high, low)),
+        [first, second, ..] => {
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // Unique local, link-local and documentation
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+                || (first == 0x2001 && second == 0x0db8))
+        }
+    }
+}
+
+/// Resolves webhook hosts to allowed addresses only
+///
+/// Requests connect to the addresses checked here, so a host can't pass the check and then
+/// resolve somewhere else
+struct AllowedAddressResolver {
+    is_allowed: fn(IpAddr) -> bool,
+}
+
+impl Resolve for AllowedAddressResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let is_allowed = self.is_allowed;
+
+        Box::pin(async move {
+            let addresses = lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|address| is_allowed(address.ip()))
+                .collect::<Vec<SocketAddr>>();
+
+            if addresses.is_empty() {
+                return Err(format!("{} has no allowed addresses", name.as_str()).into());
+            }
+
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Webhooks to call when course timetables change, kept across restarts
+pub struct WebhookRegistry {
+    client: Client,
+    /// Which addresses webhooks may be delivered to, which is only relaxed in tests
+    is_allowed: fn(IpAddr) -> bool,
+    subscriptions: RwLock<HashMap<String, WebhookSubscription>>,
+    file: JsonFile,
+    client_limiter: RateLimiter,
+}
+
+impl WebhookRegistry {
+    pub fn new(file: JsonFile) -> AppResult<Self> {
+        Self::with_address_filter(file, is_public_address)
+    }
+
+    pub fn from_env() -> AppResult<Self> {
+        Self::new(JsonFile::in_data_directory("webhooks.json"))
+    }
+
+    fn with_address_filter(file: JsonFile, is_allowed: fn(IpAddr) -> bool) -> AppResult<Self> {
+        // Redirects and proxies would connect to addresses that are never checked
+        let client = Client::builder()
+            .user_agent(USER_AGENT)
+            .dns_resolver(Arc::new(AllowedAddressResolver { is_allowed }))
+            .redirect(Policy::none())
+            .no_proxy()
+            .build()?;
+
+        Ok(Self {
+            client,
+            is_allowed,
+            subscriptions: RwLock::new(file.load_or_default()),
+            file,
+            client_limiter: RateLimiter::new(
+                MAX_REGISTRATIONS_PER_CLIENT,
+                chrono::Duration::hours(1),
+            ),
+        })
+    }
+
+    async fn save(&self) -> AppResult<()> {
+        self.file
+            .save(|| {
+                self.subscriptions
+                    .read()
+                    .expect("Webhook registry lock should not be poisoned")
+                    .clone()
+            })
+            .await
+    }
+
+    /// Fails unless every address of the host is allowed
+    async fn check_destination(&self, url: &Url) -> AppResult<()> {
+        let forbidden =
+            || AppError::InvalidInput("Webhook URLs must point to a public address".to_owned());
+
+        let addresses = match url.host() {
+            Some(Host::Ipv4(ip)) => vec![IpAddr::V4(ip)],
+            Some(Host::Ipv6(ip)) => vec![IpAddr::V6(ip)],
+            Some(Host::Domain(domain)) => lookup_host((domain, 0))
+                .await
+                .map_err(|_| {
+                    AppError::InvalidInput("Webhook host could not be resolved".to_owned())
+                })?
+                .map(|address| address.ip())
+                .collect(),
+            None => return Err(forbidden()),
+        };
+
+        if addresses.is_empty() || !addresses.into_iter().all(self.is_allowed) {
+            return Err(forbidden());
+        }
+
+        Ok(())
+    }
+
+    /// Only courses with a timetable can be watched, and clients can only register a few webhooks
+    pub async fn register(
+        &self,
+        request: WebhookSubscriptionRequest,
+        client: Option<IpAddr>,
+        activities_cache: &ActivitiesCache,
+    ) -> AppResult<WebhookRegistration> {
+        let url = Url::parse(&request.url)
+            .map_err(|_| AppError::InvalidInput("Invalid webhook URL".to_owned()))?;
+
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(AppError::InvalidInput(
+                "Webhook URLs must use http or https".to_owned(),
+            ));
+        }
+
+        self.check_destination(&url).await?;
+
+        let client_key = client.map(|client| client.to_string()).unwrap_or_default();
+
+        if !self.client_limiter.try_acquire(&client_key, Utc::now()) {
+            return Err(AppError::InvalidInput(
+                "Too many webhooks registered, please try again later".to_owned(),
+            ));
+        }
+
+        let activities = activities_cache
+            .get_or_fetch(request.identifier.clone())
+            .await?;
+
+        if activities.is_empty() {
+            return Err(AppError::InvalidInput(
+                "The course has no timetable".to_owned(),
+            ));
+        }
+
+        let registration = WebhookRegistration {
+            id: random_hex(16)?,
+            secret: random_hex(32)?,
+        };
+
+        self.subscriptions
+            .write()
+            .expect("Webhook registry lock should not be poisoned")
+            .insert(
+                registration.id.clone(),
+                WebhookSubscription {
+                    secret: registration.secret.clone(),
+                    identifier: request.identifier,
+                    student_groups: request.student_groups,
+                    url,
+                },
+            );
+
+        self.save().await?;
+
+        Ok(registration)
+    }
+
+    /// Requires the secret, so only whoever registered the webhook can remove it
+    pub async fn unregister(&self, request: WebhookUnsubscribeRequest) -> AppResult<bool> {
+        let removed = {
+            let mut subscriptions = self
+                .subscriptions
+                .write()
+                .expect("Webhook registry lock should not be poisoned");
+
+            let matches = subscriptions.get(&request.id).is_some_and(|subscription| {
+                ring::constant_time::verify_slices_are_equal(
+                    subscription.secret.as_bytes(),
+                    request.secret.as_bytes(),
+                )
+                .is_ok()
+            });
+
+            matches && subscriptions.remove(&request.id).is_some()
+        };
+
+        if removed {
+            self.save().await?;
+        }
+
+        Ok(removed)
+    }
+
+    pub fn subscribed_courses(&self) -> Vec<CourseIdentifier> {
+        self.subscriptions
+            .read()
+            .expect("Webhook registry lock should not be poisoned")
+            .values()
+            .map(|subscription| subscription.identifier.clone())
+            .unique()
+            .collect()
+    }
+
+    /// Sends the changes relevant to each subscription of the course, logging failed deliveries
+    pub async fn notify(&self, course_changes: &CourseChanges) {
+        let deliveries = self
+            .subscriptions
+            .read()
+            .expect("Webhook registry lock should not be poisoned")
+            .iter()
+            .filter(|(_, subscription)| subscription.identifier == course_changes.identifier)
+            .filter_map(|(id, subscription)| {
+                let changes = if subscription.student_groups.is_empty() {
+                    course_changes.changes.clone()
+                } else {
+                    course_changes.changes.filter(|activity| {
+                        includes_target_group(activity, &subscription.student_groups)
+                    })
+                };
+
+                if changes.is_empty() {
+                    return None;
+                }
+
+                let payload = WebhookPayload {
+                    subscription_id: id,
+                    identifier: &course_changes.identifier,
+                    detected_at: course_changes.detected_at,
+                    changes,
+                };
+                let body = serde_json::to_vec(&payload).ok()?;
+
+                Some((
+                    subscription.url.clone(),
+                    sign(&subscription.secret, &body),
+                    body,
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        let requests = deliveries
+            .into_iter()
+            .map(|(url, signature, body)| async move {
+                // The host may resolve differently than when the webhook was registered
+                if let Err(forbidden) = self.check_destination(&url).await {
+                    warn!("Not delivering webhook to {url}: {forbidden}");
+                    return;
+                }
+
+                let response = self
+                    .client
+                    .post(url.clone())
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .header(SIGNATURE_HEADER, signature)
+                    .timeout(request_timeout())
+                    .body(body)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+
+                if let Err(delivery_error) = response {
+                    warn!("Failed to deliver webhook to {url}: {delivery_error}");
+                }
+            });
+
+        join_all(requests).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::http_client::HttpClient;
+    use crate::shared_types::Activity;
+    use crate::storage::tests::temporary_directory;
+    use axum::extract::State;
+    use axum::http::HeaderMap;
+    use chrono::TimeZone;
+    use std::sync::{Arc, Mutex};
+
+    type Received = Arc<Mutex<Vec<(String, Vec<u8>)>>>;
+
+    async fn receive(State(received): State<Received>, headers: HeaderMap, body: String) {
+        let signature = headers[SIGNATURE_HEADER].to_str().unwrap().to_owned();
+
+        received
+            .lock()
+            .unwrap()
+            .push((signature, body.into_bytes()));
+    }
+
+    /// A local stand-in for a webhook receiver, returning its URL and what it has received
+    fn spawn_receiver() -> (String, Received) {
+        let received = Received::default();
+        let app = axum::Router::new()
+            .route("/hook", axum::routing::post(receive))
+            .with_state(received.clone());
+
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let url = format!("http://{}/hook", server.local_addr());
+
+        tokio::spawn(server);
+
+        (url, received)
+    }
+
+    fn activity(id: &str, group: &str) -> Activity {
+        let start = Utc.with_ymd_and_hms(2023, 1, 10, 8, 0, 0).unwrap();
+
+        Activity {
+            id: id.to_owned(),
+            course_code: "TDT4100".to_owned(),
+            week: 2,
+            start,
+            end: start + chrono::Duration::hours(2),
+            title: "Øving".to_owned(),
+            summary: String::new(),
+            staff_members: Vec::new(),
+            student_groups: vec![group.to_owned()],
+            rooms: Vec::new(),
+        }
+    }
+
+    fn identifier() -> CourseIdentifier {
+        CourseIdentifier {
+            course_code: "TDT4100".to_owned(),
+            course_term: 1,
+            semester: "23v".to_owned(),
+        }
+    }
+
+    /// A cache that already has the timetable of the course, so nothing is fetched
+    fn activities_cache() -> ActivitiesCache {
+        let activities_cache = ActivitiesCache::new(HttpClient::new().unwrap());
+        activities_cache.insert(identifier(), vec![activity("a", "MTDT_1")]);
+
+        activities_cache
+    }
+
+    #[tokio::test]
+    async fn test_signed_delivery() {
+        let (url, received) = spawn_receiver();
+        let identifier = identifier();
+
+        let directory = temporary_directory("webhooks");
+        let file = || JsonFile::new(directory.join("webhooks.json"));
+
+        let registry = WebhookRegistry::with_address_filter(file(), |_| true).unwrap();
+        let registration = registry
+            .register(
+                WebhookSubscriptionRequest {
+                    identifier: identifier.clone(),
+                    student_groups: vec!["MTDT_1".to_owned()],
+                    url,
+                },
+                None,
+                &activities_cache(),
+            )
+            .await
+            .unwrap();
+
+        registry
+            .notify(&CourseChanges {
+                identifier,
+                detected_at: Utc::now(),
+                changes: ActivityChanges {
+                    added: vec![activity("a", "MTDT_1"), activity("b", "BIT_1")],
+                    ..Default::default()
+                },
+            })
+            .await;
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 1);
+
+        let (signature, body) = &received[0];
+        assert_eq!(*signature, sign(&registration.secret, body));
+
+        let payload = serde_json::from_slice::<serde_json::Value>(body).unwrap();
+        assert_eq!(payload["subscriptionId"], registration.id.as_str());
+        assert_eq!(payload["changes"]["added"].as_array().unwrap().len(), 1);
+        assert_eq!(payload["changes"]["added"][0]["id"], "a");
+
+        // Kept across restarts
+        let registry = WebhookRegistry::with_address_filter(file(), |_| true).unwrap();
+        assert_eq!(registry.subscribed_courses().len(), 1);
+
+        assert!(!registry
+            .unregister(WebhookUnsubscribeRequest {
+                id: registration.id.clone(),
+                secret: "wrong".to_owned(),
+            })
+            .await
+            .unwrap());
+        assert!(registry
+            .unregister(WebhookUnsubscribeRequest {
+                id: registration.id,
+                secret: registration.secret,
+            })
+            .await
+            .unwrap());
+        assert!(registry.subscribed_courses().is_empty());
+
+        tokio::fs::remove_dir_all(directory).await.unwrap();
+    }
+
+    #[test]
+    fn test_is_public_address() {
+        for address in ["93.184.216.34", "2606:2800:220:1::"] {
+            assert!(is_public_address(address.parse().unwrap()), "{address}");
+        }
+
+        for address in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "2002:0a00:0001::",
+        ] {
+            assert!(!is_public_address(address.parse().unwrap()), "{address}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejects_internal_webhooks() {
+        let directory = temporary_directory("webhooks");
+        let registry =
+            WebhookRegistry::new(JsonFile::new(directory.join("webhooks.json"))).unwrap();
+        let activities_cache = activities_cache();
+        let request = |url: &str| WebhookSubscriptionRequest {
+            identifier: identifier(),
+            student_groups: Vec::new(),
+            url: url.to_owned(),
+        };
+
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://localhost/hook",
+            "ftp://example.com/hook",
+            "not a url",
+        ] {
+            assert!(
+                matches!(
+                    registry
+                        .register(request(url), None, &activities_cache)
+                        .await,
+                    Err(AppError::InvalidInput(_))
+                ),
+                "{url}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_limits_registrations_per_client() {
+        let (url, _) = spawn_receiver();
+        let directory = temporary_directory("webhooks");
+        let registry = WebhookRegistry::with_address_filter(
+            JsonFile::new(directory.join("webhooks.json")),
+            |_| true,
+        )
+        .unwrap();
+        let activities_cache = activities_cache();
+        let request = || WebhookSubscriptionRequest {
+            identifier: identifier(),
+            student_groups: Vec::new(),
+            url: url.clone(),
+        };
+        let client = "203.0.113.1".parse().ok();
+
+        for _ in 0..MAX_REGISTRATIONS_PER_CLIENT {
+            registry
+                .register(request(), client, &activities_cache)
+                .await
+                .unwrap();
+        }
+
+        assert!(matches!(
+            registry
+                .register(request(), client, &activities_cache)
+                .await,
+            Err(AppError::InvalidInput(_))
+        ));
+        assert!(registry
+            .register(request(), "203.0.113.2".parse().ok(), &activities_cache)
+            .await
+            .is_ok());
+
+        tokio::fs::remove_dir_all(directory).await.unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+use crate::error::{AppError, AppResult};
+use crate::free_busy::{query_busy_slots, resolve_queries};
+use crate::shared_types::{CommonFreeSlot, CommonFreeSlotsQuery, TimeSlot};
+use crate::time_slots::{free_slots, merge_slots};
+use crate::AppState;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Europe::Oslo;
+use itertools::Itertools;
+use std::collections::BTreeMap;
+use tracing::error;
+
+const DEFAULT_LIMIT: u32 = 20;
+const MAX_LIMIT: u32 = 100;
+/// Every date in the window is searched and compared, so the window is kept to about a year
+const MAX_WINDOW_DAYS: i64 = 366;
+
+fn local_time(date: NaiveDate, hour: u32) -> Option<DateTime<Utc>> {
+    let time = NaiveTime::from_hms_opt(hour, 0, 0)?;
+    let local = Oslo.from_local_datetime(&date.and_time(time)).earliest()?;
+
+    Some(local.with_timezone(&Utc))
+}
+
+/// Recurring slots where nobody is busy, ranked by how many weeks they are free and then by length
+///
+/// Every free gap found on some date is a candidate, which is then checked against the same
+/// weekday of every other week in the window
+pub fn common_free_slots(busy: &[TimeSlot], query: &CommonFreeSlotsQuery) -> Vec<CommonFreeSlot> {
+    let weekdays = if query.weekdays.is_empty() {
+        (1..=5).collect()
+    } else {
+        query.weekdays.clone()
+    };
+
+    let latest_hour = query.latest_hour.min(23);
+    let min_duration = Duration::minutes(query.min_duration_minutes.max(1).into());
+
+    let first_date = query.from.with_timezone(&Oslo).date_naive();
+    let last_date = query.to.with_timezone(&Oslo).date_naive();
+
+    // Local free gaps of every searched date, and how often each weekday was searched
+    let mut free_by_date = BTreeMap::<NaiveDate, Vec<(NaiveTime, NaiveTime)>>::new();
+    let mut total_weeks = BTreeMap::<u32, u32>::new();
+
+    for date in first_date.iter_days().take_while(|date| *date <= last_date) {
+        let weekday = date.weekday().number_from_monday();
+
+        if !weekdays.contains(&weekday) {
+            continue;
+        }
+
+        let (Some(window_start), Some(window_end)) = (
+            local_time(date, query.earliest_hour),
+            local_time(date, latest_hour),
+        ) else {
+            continue;
+        };
+
+        let window_start = window_start.max(query.from);
+        let window_end = window_end.min(query.to);
+
+        if window_start >= window_end {
+            continue;
+        }
+
+        *total_weeks.entry(weekday).or_default() += 1;
+
+        let free = free_slots(busy, window_start, window_end, min_duration)
+            .into_iter()
+            .map(|slot| {
+                (
+                    slot.start.with_timezone(&Oslo).time(),
+                    slot.end.with_timezone(&Oslo).time(),
+                )
+            })
+            .collect();
+
+        free_by_date.insert(date, free);
+    }
+
+    let candidates = free_by_date
+        .iter()
+        .flat_map(|(date, free)| {
+            let weekday = date.weekday().number_from_monday();
+
+            free.iter().map(move |(start, end)| (weekday, *start, *end))
+        })
+        .unique();
+
+    let mut slots = candidates
+        .map(|(weekday, start, end)| {
+            let free_dates = free_by_date
+                .iter()
+                .filter(|(date, _)| date.weekday().number_from_monday() == weekday)
+                .filter(|(_, free)| {
+                    free.iter()
+                        .any(|(free_start, free_end)| *free_start <= start && *free_end >= end)
+                })
+                .map(|(date, _)| *date)
+                .collect::<Vec<_>>();
+
+            CommonFreeSlot {
+                weekday,
+                start,
+                end,
+                duration_minutes: (end - start).num_minutes() as u32,
+                free_dates,
+                total_weeks: total_weeks.get(&weekday).copied().unwrap_or_default(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    slots.sort_by(|a, b| {
+        b.free_dates
+            .len()
+            .cmp(&a.free_dates.len())
+            .then(b.duration_minutes.cmp(&a.duration_minutes))
+            .then(a.weekday.cmp(&b.weekday))
+            .then(a.start.cmp(&b.start))
+    });
+
+    slots.truncate(query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize);
+
+    slots
+}
+
+pub async fn find_common_free_slots(
+    app_state: &AppState,
+    query: CommonFreeSlotsQuery,
+) -> AppResult<Vec<CommonFreeSlot>> {
+    if query.from >= query.to || query.earliest_hour >= query.latest_hour {
+        return Ok(Vec::new());
+    }
+
+    if query.to - query.from > Duration::days(MAX_WINDOW_DAYS) {
+        return Err(AppError::InvalidInput(format!(
+            "Free slots can be searched for at most {MAX_WINDOW_DAYS} days at a time"
+        )));
+    }
+
+    let resolved_queries = resolve_queries(app_state, &query.queries).await?;
+
+    let busy = merge_slots(
+        resolved_queries
+            .iter()
+            .flat_map(|resolved_subscriptions| query_busy_slots(resolved_subscriptions)),
+    );
+
+    // Comparing every date is CPU bound, so it shouldn't hold up other requests on the runtime
+    tokio::task::spawn_blocking(move || common_free_slots(&busy, &query))
+        .await
+        .map_err(|join_error| {
+            error!("Common free slots search failed: {join_error}");
+
+            AppError::InternalError
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        // January 2023 is in winter time, one hour ahead of UTC
+        Utc.with_ymd_and_hms(2023, 1, day, hour - 1, 0, 0).unwrap()
+    }
+
+    fn time(hour: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_common_free_slots() {
+        // Tuesdays 10 and 17 January, with the afternoon busy in the second week
+        let busy = vec![
+            TimeSlot {
+                start: at(10, 8),
+                end: at(10, 12),
+            },
+            TimeSlot {
+                start: at(17, 8),
+                end: at(17, 12),
+            },
+            TimeSlot {
+                start: at(17, 14),
+                end: at(17, 16),
+            },
+        ];
+
+        let query = CommonFreeSlotsQuery {
+            queries: Vec::new(),
+            from: at(9, 1),
+            to: at(21, 1),
+            weekdays: vec![2],
+            earliest_hour: 8,
+            latest_hour: 16,
+            min_duration_minutes: 60,
+            limit: None,
+        };
+
+        let slots = common_free_slots(&busy, &query)
+            .into_iter()
+            .map(|slot| {
+                (
+                    slot.start,
+                    slot.end,
+                    slot.free_dates.len(),
+                    slot.total_weeks,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            slots,
+            vec![(time(12), time(14), 2, 2), (time(12), time(16), 1, 2)]
+        );
+    }
+}
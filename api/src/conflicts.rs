@@ -0,0 +1,227 @@
+use crate::calendar::resolve_subscriptions::resolve_subscriptions;
+use crate::error::AppResult;
+use crate::shared_types::{
+    Activity, ActivityConflict, CalendarQuery, CalendarSubscription, ConflictingActivity,
+    CourseConflictShare, CourseIdentifier, TimeSlot, TimetableConflicts,
+};
+use crate::time_slots::merge_slots;
+use crate::AppState;
+use itertools::Itertools;
+use std::collections::{BTreeSet, HashMap};
+
+/// The activities of one selected course, after filtering on student groups
+pub struct SelectedCourse<'a> {
+    pub identifier: CourseIdentifier,
+    pub activities: Vec<&'a Activity>,
+}
+
+fn conflicting_activity(course: &CourseIdentifier, activity: &Activity) -> ConflictingActivity {
+    ConflictingActivity {
+        course: course.clone(),
+        activity_id: activity.id.clone(),
+        title: activity.title.clone(),
+        start: activity.start,
+        end: activity.end,
+    }
+}
+
+pub fn find_conflicts(courses: &[SelectedCourse]) -> TimetableConflicts {
+    let activities = courses
+        .iter()
+        .enumerate()
+        .flat_map(|(course_index, course)| {
+            course
+                .activities
+                .iter()
+                .map(move |activity| (course_index, *activity))
+        })
+        .sorted_by_key(|(_, activity)| activity.start)
+        .collect::<Vec<_>>();
+
+    let mut conflicts = Vec::new();
+    let mut overlaps_by_activity = HashMap::<(usize, &str), Vec<TimeSlot>>::new();
+
+    for (index, (first_course, first)) in activities.iter().enumerate() {
+        let later_activities = activities[index + 1..]
+            .iter()
+            .take_while(|(_, second)| second.start < first.end);
+
+        for (second_course, second) in later_activities {
+            if first_course == second_course || first.id == second.id {
+                continue;
+            }
+
+            let overlap = TimeSlot {
+                start: first.start.max(second.start),
+                end: first.end.min(second.end),
+            };
+
+            if overlap.start >= overlap.end {
+                continue;
+            }
+
+            for (course_index, activity) in [(*first_course, first), (*second_course, second)] {
+                overlaps_by_activity
+                    .entry((course_index, &activity.id))
+                    .or_default()
+                    .push(overlap.clone());
+            }
+
+            conflicts.push(ActivityConflict {
+                first: conflicting_activity(&courses[*first_course].identifier, first),
+                second: conflicting_activity(&courses[*second_course].identifier, second),
+                week: first.week,
+                overlap,
+            });
+        }
+    }
+
+    let courses = courses
+        .iter()
+        .enumerate()
+        .map(|(course_index, course)| {
+            let total_minutes = course
+                .activities
+                .iter()
+                .map(|activity| (activity.end - activity.start).num_minutes() as i32)
+                .sum::<i32>();
+
+            let mut conflicting_minutes = 0;
+            let mut weeks = BTreeSet::new();
+
+            for activity in &course.activities {
+                let Some(overlaps) = overlaps_by_activity.get(&(course_index, &activity.id)) else {
+                    continue;
+                };
+
+                // An activity can overlap several others, so only count each minute once
+                conflicting_minutes += merge_slots(overlaps.iter().cloned())
+                    .iter()
+                    .map(|slot| (slot.end - slot.start).num_minutes() as i32)
+                    .sum::<i32>();
+
+                weeks.insert(activity.week);
+            }
+
+            let share = if total_minutes > 0 {
+                conflicting_minutes as f64 / total_minutes as f64
+            } else {
+                0.0
+            };
+
+            CourseConflictShare {
+                course: course.identifier.clone(),
+                total_minutes,
+                conflicting_minutes,
+                share,
+                weeks: weeks.into_iter().collect(),
+            }
+        })
+        .collect();
+
+    TimetableConflicts { conflicts, courses }
+}
+
+pub async fn find_timetable_conflicts(
+    app_state: &AppState,
+    queries: Vec<CalendarQuery>,
+) -> AppResult<TimetableConflicts> {
+    let subscriptions = queries
+        .into_iter()
+        .map(CalendarSubscription::Course)
+        .collect();
+    let resolved_subscriptions = resolve_subscriptions(app_state, subscriptions).await?;
+
+    let courses = resolved_subscriptions
+        .iter()
+        .filter_map(
+            |resolved_subscription| match &resolved_subscription.subscription {
+                CalendarSubscription::Course(query) => Some(SelectedCourse {
+                    identifier: query.identifier.clone(),
+                    activities: resolved_subscription.filtered_activities().collect(),
+                }),
+                _ => None,
+            },
+        )
+        .collect::<Vec<_>>();
+
+    Ok(find_conflicts(&courses))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeZone, Utc};
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 1, 10, hour, 0, 0).unwrap()
+    }
+
+    fn activity(id: &str, start: u32, end: u32) -> Activity {
+        Activity {
+            id: id.to_owned(),
+            course_code: String::new(),
+            week: 2,
+            start: at(start),
+            end: at(end),
+            title: "Forelesning".to_owned(),
+            summary: String::new(),
+            staff_members: Vec::new(),
+            student_groups: Vec::new(),
+            rooms: Vec::new(),
+        }
+    }
+
+    fn identifier(course_code: &str) -> CourseIdentifier {
+        CourseIdentifier {
+            course_code: course_code.to_owned(),
+            course_term: 1,
+            semester: "23v".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_find_conflicts() {
+        let first = [activity("a1", 8, 10), activity("a2", 12, 14)];
+        let second = [activity("b1", 9, 13), activity("b2", 14, 16)];
+
+        let courses = [
+            SelectedCourse {
+                identifier: identifier("A"),
+                activities: first.iter().collect(),
+            },
+            SelectedCourse {
+                identifier: identifier("B"),
+                activities: second.iter().collect(),
+            },
+        ];
+
+        let conflicts = find_conflicts(&courses);
+
+        let overlaps = conflicts
+            .conflicts
+            .iter()
+            .map(|conflict| conflict.overlap.clone())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            overlaps,
+            vec![
+                TimeSlot {
+                    start: at(9),
+                    end: at(10)
+                },
+                TimeSlot {
+                    start: at(12),
+                    end: at(13)
+                },
+            ]
+        );
+
+        assert_eq!(conflicts.courses[0].conflicting_minutes, 120);
+        assert_eq!(conflicts.courses[0].share, 0.5);
+        assert_eq!(conflicts.courses[1].conflicting_minutes, 120);
+        assert_eq!(conflicts.courses[1].total_minutes, 360);
+        assert_eq!(conflicts.courses[1].weeks, vec![2]);
+    }
+}
@@ -11,11 +11,26 @@ pub enum AppError {
     ReqwestError(#[from] reqwest::Error),
 
     ParsingError,
+
+    /// Sending email failed, or it is not configured
+    EmailError,
+
+    /// Reading or writing persisted data failed
+    StorageError,
+
+    /// A bug or an unavailable system resource, rather than anything about the request
+    InternalError,
+
+    /// The request asks for something that can't be done, with a message for the client
+    InvalidInput(String),
 }
 
 impl Display for AppError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Internal server error")
+        match self {
+            AppError::InvalidInput(message) => write!(f, "{message}"),
+            _ => write!(f, "Internal server error"),
+        }
     }
 }
 
@@ -27,14 +42,23 @@ impl From<AppError> for rspc::Error {
             AppError::ReqwestError(cause) => {
                 rspc::Error::with_cause(ErrorCode::InternalServerError, message, cause)
             }
-            AppError::ParsingError => rspc::Error::new(ErrorCode::InternalServerError, message),
+            AppError::ParsingError
+            | AppError::EmailError
+            | AppError::StorageError
+            | AppError::InternalError => rspc::Error::new(ErrorCode::InternalServerError, message),
+            AppError::InvalidInput(message) => rspc::Error::new(ErrorCode::BadRequest, message),
         }
     }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response()
+        let status = match self {
+            AppError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        (status, self.to_string()).into_response()
     }
 }
 
@@ -1,20 +1,21 @@
 use crate::error::{AppError, AppResult};
-use reqwest::Client;
+use crate::fetch::http_client::HttpClient;
 use serde::Deserialize;
 use std::collections::HashMap;
 
 use crate::shared_types::Course;
 
-pub async fn fetch_courses(semester: &str, client: &Client) -> AppResult<HashMap<String, Course>> {
-    let res = client
-        .get(format!(
-            "https://tp.uio.no/ntnu/timeplan/emner.php?sem={semester}"
-        ))
-        .send()
+pub async fn fetch_courses(
+    semester: &str,
+    client: &HttpClient,
+) -> AppResult<HashMap<String, Course>> {
+    let page_html = client
+        .get_text(
+            &format!("https://tp.uio.no/ntnu/timeplan/emner.php?sem={semester}"),
+            &[],
+        )
         .await?;
 
-    let page_html = res.text().await?;
-
     let courses = {
         let (_, courses) = page_html
             .split_once("var courses = ")
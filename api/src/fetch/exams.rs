@@ -0,0 +1,235 @@
+use crate::error::{AppError, AppResult};
+use crate::fetch::http_client::HttpClient;
+use crate::shared_types::{Exam, ExamIdentifier};
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Europe::Oslo;
+use data_encoding::HEXLOWER;
+use ring::digest::{digest, SHA256};
+use scraper::{ElementRef, Html, Selector};
+
+/// Id from what the exam is rather than its row, so reordering the page keeps calendar UIDs
+///
+/// The time is left out, so exams keep their id when they are given one, unless it is needed to
+/// tell apart exams with the same date and form
+fn exam_id(course_code: &str, semester: &str, exam: &Exam, include_time: bool) -> String {
+    let mut key = format!("{}|{}", exam.date, exam.assessment_form);
+
+    if include_time {
+        key += &format!("|{:?}", exam.start);
+    }
+
+    let hash = HEXLOWER.encode(&digest(&SHA256, key.as_bytes()).as_ref()[..8]);
+
+    format!("exam-{course_code}-{semester}-{hash}")
+}
+
+/// Course pages are per academic year, so spring "23v" belongs to 2022 and autumn "23h" to 2023
+fn academic_year(semester: &str) -> AppResult<i32> {
+    let (year, season) = semester.split_at(semester.len().saturating_sub(1));
+    let year = 2000 + year.parse::<i32>().map_err(|_| AppError::ParsingError)?;
+
+    match season {
+        "v" | "V" => Ok(year - 1),
+        "h" | "H" => Ok(year),
+        _ => Err(AppError::ParsingError),
+    }
+}
+
+/// Name of the season as written in the exam table, e.g. "Vår ORD" or "Høst UTS"
+fn season_name(semester: &str) -> &'static str {
+    if semester.ends_with(['v', 'V']) {
+        "Vår"
+    } else {
+        "Høst"
+    }
+}
+
+fn element_text(element: ElementRef) -> String {
+    element.text().collect::<String>().trim().to_owned()
+}
+
+fn parse_duration(input: &str) -> Option<Duration> {
+    let hours = input
+        .split_whitespace()
+        .next()?
+        .replace(',', ".")
+        .parse::<f64>()
+        .ok()?;
+
+    Some(Duration::minutes((hours * 60.0).round() as i64))
+}
+
+fn local_to_utc(date: NaiveDate, time: NaiveTime) -> Option<DateTime<Utc>> {
+    let local = Oslo.from_local_datetime(&date.and_time(time)).earliest()?;
+
+    Some(local.with_timezone(&Utc))
+}
+
+pub async fn fetch_exams(identifier: &ExamIdentifier, client: &HttpClient) -> AppResult<Vec<Exam>> {
+    let ExamIdentifier {
+        course_code,
+        semester,
+    } = identifier;
+
+    // The code is put straight into the URL path
+    let is_course_code = !course_code.is_empty()
+        && course_code
+            .chars()
+            .all(|char| char.is_ascii_uppercase() || char.is_ascii_digit());
+
+    if !is_course_code {
+        return Err(AppError::InvalidInput("Invalid course code".to_owned()));
+    }
+
+    let year = academic_year(semester)?;
+
+    let html = client
+        .get_text(
+            &format!("https://www.ntnu.no/studier/emner/{course_code}/{year}"),
+            &[],
+        )
+        .await?;
+    let document = Html::parse_document(&html);
+
+    let header_selector = Selector::parse("#exam-info table thead th").unwrap();
+    let row_selector = Selector::parse("#exam-info table tbody tr").unwrap();
+    let cell_selector = Selector::parse("td").unwrap();
+
+    // Look up columns by header, as not every course lists every column
+    let headers = document
+        .select(&header_selector)
+        .map(element_text)
+        .collect::<Vec<_>>();
+
+    let column = |names: &[&str]| {
+        headers.iter().position(|header| {
+            names
+                .iter()
+                .any(|name| header.to_lowercase().starts_with(name))
+        })
+    };
+
+    // Courses without exams have no exam table
+    let Some(date_column) = column(&["dato"]) else {
+        return Ok(Vec::new());
+    };
+
+    let season_column = column(&["semester", "termin"]);
+    let form_column = column(&["vurderingsform"]);
+    let time_column = column(&["tid", "starttid"]);
+    let duration_column = column(&["varighet"]);
+    let location_column = column(&["rom", "sted", "bygning"]);
+
+    let season = season_name(semester);
+    let mut exams = Vec::new();
+
+    for row in document.select(&row_selector) {
+        let cells = row
+            .select(&cell_selector)
+            .map(element_text)
+            .collect::<Vec<_>>();
+        let cell = |column: Option<usize>| {
+            column
+                .and_then(|column| cells.get(column))
+                .filter(|cell| !cell.is_empty())
+        };
+
+        if let Some(row_season) = cell(season_column) {
+            if !row_season.starts_with(season) {
+                continue;
+            }
+        }
+
+        // Rows without a date are exams that have not been scheduled yet
+        let Some(date) = cell(Some(date_column))
+            .and_then(|date| NaiveDate::parse_from_str(date, "%d.%m.%Y").ok())
+        else {
+            continue;
+        };
+
+        let start = cell(time_column)
+            .and_then(|time| NaiveTime::parse_from_str(time, "%H:%M").ok())
+            .and_then(|time| local_to_utc(date, time));
+
+        let end = start
+            .zip(cell(duration_column).and_then(|duration| parse_duration(duration)))
+            .map(|(start, duration)| start + duration);
+
+        exams.push(Exam {
+            id: String::new(),
+            course_code: course_code.clone(),
+            assessment_form: cell(form_column).cloned().unwrap_or_default(),
+            date,
+            start,
+            end,
+            location: cell(location_column).cloned(),
+        });
+    }
+
+    for index in 0..exams.len() {
+        let exam = &exams[index];
+        let is_ambiguous = exams.iter().enumerate().any(|(other_index, other)| {
+            other_index != index
+                && other.date == exam.date
+                && other.assessment_form == exam.assessment_form
+        });
+
+        exams[index].id = exam_id(course_code, semester, exam, is_ambiguous);
+    }
+
+    Ok(exams)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_academic_year() {
+        assert_eq!(academic_year("23v").unwrap(), 2022);
+        assert_eq!(academic_year("23h").unwrap(), 2023);
+        assert!(academic_year("23x").is_err());
+    }
+
+    #[test]
+    fn test_exam_id() {
+        let exam = |assessment_form: &str, hour: u32| Exam {
+            id: String::new(),
+            course_code: "TDT4100".to_owned(),
+            assessment_form: assessment_form.to_owned(),
+            date: NaiveDate::from_ymd_opt(2023, 5, 26).unwrap(),
+            start: Some(Utc.with_ymd_and_hms(2023, 5, 26, hour, 0, 0).unwrap()),
+            end: None,
+            location: None,
+        };
+
+        let id = exam_id("TDT4100", "23v", &exam("Skriftlig", 7), false);
+
+        assert_eq!(id, exam_id("TDT4100", "23v", &exam("Skriftlig", 13), false));
+        assert_ne!(id, exam_id("TDT4100", "23v", &exam("Muntlig", 7), false));
+        assert_ne!(
+            exam_id("TDT4100", "23v", &exam("Skriftlig", 7), true),
+            exam_id("TDT4100", "23v", &exam("Skriftlig", 13), true)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejects_invalid_course_codes() {
+        let identifier = ExamIdentifier {
+            course_code: "../TDT4100".to_owned(),
+            semester: "23v".to_owned(),
+        };
+
+        assert!(matches!(
+            fetch_exams(&identifier, &HttpClient::new().unwrap()).await,
+            Err(AppError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("4 timer"), Some(Duration::hours(4)));
+        assert_eq!(parse_duration("3,5"), Some(Duration::minutes(210)));
+        assert_eq!(parse_duration(""), None);
+    }
+}
@@ -0,0 +1,281 @@
+use crate::error::{AppError, AppResult};
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, StatusCode};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use time::ext::NumericalStdDuration;
+use tokio::sync::Semaphore;
+use tokio::time::sleep;
+use tracing::warn;
+
+pub const USER_AGENT: &str = concat!(
+    "ntnu-timeplan/",
+    env!("CARGO_PKG_VERSION"),
+    " (calendar subscriptions for NTNU timetables)"
+);
+
+/// Sustained requests per second to a single host
+const REQUESTS_PER_SECOND: f64 = 4.0;
+
+/// Requests to a single host that can be sent at once after a quiet period
+const BURST_SIZE: f64 = 8.0;
+
+/// Requests to a single host in flight at the same time
+const MAX_CONCURRENT_REQUESTS: usize = 4;
+
+/// Times a request is retried after the host answers that it is overloaded
+const MAX_RATE_LIMITED_RETRIES: u32 = 3;
+
+/// Longer waits asked for by `Retry-After` fail the request instead
+fn max_retry_after() -> Duration {
+    5.std_minutes()
+}
+
+fn max_backoff() -> Duration {
+    30.std_seconds()
+}
+
+/// Random number between 0 and 1
+fn random_fraction() -> f64 {
+    let mut bytes = [0; 4];
+
+    // Without randomness, the jitter is just always zero
+    let _ = SystemRandom::new().fill(&mut bytes);
+
+    f64::from(u32::from_le_bytes(bytes)) / f64::from(u32::MAX)
+}
+
+/// Exponential delay before retry number `attempt`, from 1, with up to half of it random
+pub fn backoff_delay(attempt: u32) -> Duration {
+    let delay = 500
+        .std_milliseconds()
+        .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(max_backoff());
+
+    delay.mul_f64(0.5 + random_fraction() / 2.0)
+}
+
+/// How long a `Retry-After` header asks to wait, given either in seconds or as a date
+fn retry_after(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+    /// Set when the host asks us to back off
+    blocked_until: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct HostLimiter {
+    concurrency: Semaphore,
+    bucket: Mutex<TokenBucket>,
+}
+
+impl HostLimiter {
+    fn new() -> Self {
+        Self {
+            concurrency: Semaphore::new(MAX_CONCURRENT_REQUESTS),
+            bucket: Mutex::new(TokenBucket {
+                tokens: BURST_SIZE,
+                refilled_at: Instant::now(),
+                blocked_until: None,
+            }),
+        }
+    }
+
+    /// Waits until a request may be sent
+    async fn take_token(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self
+                    .bucket
+                    .lock()
+                    .expect("Host limiter lock should not be poisoned");
+                let now = Instant::now();
+
+                let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed * REQUESTS_PER_SECOND).min(BURST_SIZE);
+                bucket.refilled_at = now;
+
+                match bucket.blocked_until {
+                    Some(blocked_until) if blocked_until > now => blocked_until - now,
+                    _ if bucket.tokens >= 1.0 => {
+                        bucket.tokens -= 1.0;
+                        return;
+                    }
+                    _ => Duration::from_secs_f64((1.0 - bucket.tokens) / REQUESTS_PER_SECOND),
+                }
+            };
+
+            sleep(wait).await;
+        }
+    }
+
+    fn block_for(&self, duration: Duration) {
+        let mut bucket = self
+            .bucket
+            .lock()
+            .expect("Host limiter lock should not be poisoned");
+        let blocked_until = Instant::now() + duration;
+
+        bucket.blocked_until = bucket.blocked_until.max(Some(blocked_until));
+    }
+}
+
+/// Client for scraping upstream, limiting how fast and how much each host is requested
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: Client,
+    hosts: Arc<Mutex<HashMap<String, Arc<HostLimiter>>>>,
+}
+
+impl HttpClient {
+    pub fn new() -> AppResult<Self> {
+        let client = Client::builder().user_agent(USER_AGENT).build()?;
+
+        Ok(Self {
+            client,
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    fn host_limiter(&self, host: &str) -> Arc<HostLimiter> {
+        self.hosts
+            .lock()
+            .expect("Host limiters lock should not be poisoned")
+            .entry(host.to_owned())
+            .or_insert_with(|| Arc::new(HostLimiter::new()))
+            .clone()
+    }
+
+    /// Body of a GET request, retried when the host is overloaded
+    pub async fn get_text(&self, url: &str, query: &[(&str, String)]) -> AppResult<String> {
+        let request = self.client.get(url).query(query).build()?;
+        let host = request.url().host_str().unwrap_or_default().to_owned();
+        let limiter = self.host_limiter(&host);
+
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+
+            let request = request.try_clone().ok_or(AppError::ParsingError)?;
+
+            let _permit = limiter
+                .concurrency
+                .acquire()
+                .await
+                .expect("Host limiter semaphore is never closed");
+            limiter.take_token().await;
+
+            let response = self.client.execute(request).await?;
+            let status = response.status();
+
+            if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE
+            {
+                return Ok(response.text().await?);
+            }
+
+            let wait = retry_after(response.headers(), Utc::now())
+                .unwrap_or_else(|| backoff_delay(attempt));
+
+            if attempt > MAX_RATE_LIMITED_RETRIES || wait > max_retry_after() {
+                limiter.block_for(wait.min(max_retry_after()));
+
+                return Err(response.error_for_status().unwrap_err().into());
+            }
+
+            warn!("{host} answered {status}, retrying in {wait:?}");
+            limiter.block_for(wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap as AxumHeaderMap;
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use axum::Router;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_retry_after() {
+        let now = DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "120".parse().unwrap());
+        assert_eq!(retry_after(&headers, now), Some(120.std_seconds()));
+
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:29:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers, now), Some(60.std_seconds()));
+    }
+
+    #[tokio::test]
+    async fn test_honors_retry_after() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let server_requests = requests.clone();
+
+        let app = Router::new().route(
+            "/",
+            get(move |headers: AxumHeaderMap| async move {
+                assert!(headers["user-agent"]
+                    .to_str()
+                    .unwrap()
+                    .starts_with("ntnu-timeplan/"));
+
+                if server_requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                    (
+                        StatusCode::TOO_MANY_REQUESTS,
+                        [("retry-after", "1")],
+                        "slow down",
+                    )
+                        .into_response()
+                } else {
+                    "timetable".into_response()
+                }
+            }),
+        );
+
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let url = format!("http://{}/", server.local_addr());
+        tokio::spawn(server);
+
+        let started = Instant::now();
+        let text = HttpClient::new()
+            .unwrap()
+            .get_text(&url, &[])
+            .await
+            .unwrap();
+
+        assert_eq!(text, "timetable");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert!(started.elapsed() >= 1.std_seconds());
+    }
+}
@@ -1,3 +1,8 @@
 pub mod activities;
 pub mod courses;
+pub mod exams;
+pub mod http_client;
+pub mod programmes;
+pub mod rooms;
 pub mod semesters;
+pub mod timetable;
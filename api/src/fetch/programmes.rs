@@ -0,0 +1,27 @@
+use crate::error::AppResult;
+use crate::fetch::http_client::HttpClient;
+use crate::fetch::timetable::{fetch_timetable, TimetableQuery};
+use crate::shared_types::{Activity, ProgrammeIdentifier};
+
+impl TimetableQuery for ProgrammeIdentifier {
+    fn query(&self) -> Vec<(&'static str, String)> {
+        let ProgrammeIdentifier {
+            programme,
+            year,
+            semester,
+        } = self;
+
+        vec![
+            ("type", "student".to_owned()),
+            ("sem", semester.into()),
+            ("id[]", format!("{programme}_{year}")),
+        ]
+    }
+}
+
+pub async fn fetch_programme_activities(
+    programme_identifier: &ProgrammeIdentifier,
+    client: &HttpClient,
+) -> AppResult<Vec<Activity>> {
+    fetch_timetable(programme_identifier, client).await
+}
@@ -0,0 +1,26 @@
+use crate::error::AppResult;
+use crate::fetch::http_client::HttpClient;
+use crate::fetch::timetable::{fetch_timetable, TimetableQuery};
+use crate::shared_types::{Activity, RoomIdentifier};
+
+impl TimetableQuery for RoomIdentifier {
+    /// Plenty of rooms have no bookings in a semester, which upstream lists as an empty data array
+    const MAY_BE_EMPTY: bool = true;
+
+    fn query(&self) -> Vec<(&'static str, String)> {
+        let RoomIdentifier { room_id, semester } = self;
+
+        vec![
+            ("type", "room".to_owned()),
+            ("sem", semester.into()),
+            ("id[]", room_id.into()),
+        ]
+    }
+}
+
+pub async fn fetch_room_activities(
+    room_identifier: &RoomIdentifier,
+    client: &HttpClient,
+) -> AppResult<Vec<Activity>> {
+    fetch_timetable(room_identifier, client).await
+}
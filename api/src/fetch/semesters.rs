@@ -1,18 +1,18 @@
 use crate::error::{AppError, AppResult};
-use reqwest::Client;
+use crate::fetch::http_client::HttpClient;
 use scraper::{Html, Selector};
 use std::collections::HashMap;
 
 use crate::shared_types::{Semester, SemestersWithCurrent};
 
-pub async fn fetch_semesters(client: &Client) -> AppResult<SemestersWithCurrent> {
-    let response = client
-        .get("https://tp.educloud.no/ntnu/timeplan/timeplan.php?type=courseact")
-        .send()
+pub async fn fetch_semesters(client: &HttpClient) -> AppResult<SemestersWithCurrent> {
+    let html = client
+        .get_text(
+            "https://tp.educloud.no/ntnu/timeplan/timeplan.php?type=courseact",
+            &[],
+        )
         .await?;
 
-    let html = response.text().await?;
-
     let document = Html::parse_document(&html);
 
     let selector = Selector::parse("select#semesterselect option").unwrap();
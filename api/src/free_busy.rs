@@ -0,0 +1,304 @@
+use crate::calendar::encode_query::decode_calendar_query;
+use crate::calendar::resolve_subscriptions::{resolve_subscriptions, ResolvedSubscription};
+use crate::error::AppResult;
+use crate::shared_types::{BusyInterval, FreeBusy, FreeBusyQuery, TimeSlot};
+use crate::time_slots::merge_slots;
+use crate::AppState;
+use chrono::{DateTime, Utc};
+use futures_util::future::try_join_all;
+use icalendar::{Calendar, CalendarComponent, Component, Property};
+use itertools::Itertools;
+
+const UTC_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Merged busy time of one calendar query, both activities and exams with a set time
+pub fn query_busy_slots(resolved_subscriptions: &[ResolvedSubscription]) -> Vec<TimeSlot> {
+    let activities = resolved_subscriptions
+        .iter()
+        .flat_map(|resolved_subscription| resolved_subscription.filtered_activities())
+        .map(|activity| TimeSlot {
+            start: activity.start,
+            end: activity.end,
+        });
+
+    let exams = resolved_subscriptions
+        .iter()
+        .flat_map(|resolved_subscription| resolved_subscription.exams.iter())
+        .filter_map(|exam| match (exam.start, exam.end) {
+            (Some(start), Some(end)) => Some(TimeSlot { start, end }),
+            _ => None,
+        });
+
+    merge_slots(activities.chain(exams))
+}
+
+fn clip_slots(
+    slots: Vec<TimeSlot>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Vec<TimeSlot> {
+    slots
+        .into_iter()
+        .filter_map(|slot| {
+            let start = from.map_or(slot.start, |from| slot.start.max(from));
+            let end = to.map_or(slot.end, |to| slot.end.min(to));
+
+            (start < end).then_some(TimeSlot { start, end })
+        })
+        .collect()
+}
+
+/// Splits the busy time of all queries into intervals with the same set of busy calendars
+///
+/// When anonymised, the intervals are merged and do not tell who is busy
+pub fn busy_intervals(per_query: &[Vec<TimeSlot>], anonymise: bool) -> Vec<BusyInterval> {
+    if anonymise {
+        return merge_slots(per_query.iter().flatten().cloned())
+            .into_iter()
+            .map(|slot| BusyInterval {
+                start: slot.start,
+                end: slot.end,
+                busy_calendars: Vec::new(),
+            })
+            .collect();
+    }
+
+    let boundaries = per_query
+        .iter()
+        .flatten()
+        .flat_map(|slot| [slot.start, slot.end])
+        .sorted()
+        .dedup()
+        .collect::<Vec<_>>();
+
+    let mut intervals = Vec::<BusyInterval>::new();
+
+    for (start, end) in boundaries.into_iter().tuple_windows() {
+        let busy_calendars = per_query
+            .iter()
+            .enumerate()
+            .filter(|(_, slots)| {
+                slots
+                    .iter()
+                    .any(|slot| slot.start <= start && slot.end >= end)
+            })
+            .map(|(index, _)| index as u32)
+            .collect::<Vec<_>>();
+
+        if busy_calendars.is_empty() {
+            continue;
+        }
+
+        match intervals.last_mut() {
+            Some(last) if last.end == start && last.busy_calendars == busy_calendars => {
+                last.end = end;
+            }
+            _ => intervals.push(BusyInterval {
+                start,
+                end,
+                busy_calendars,
+            }),
+        }
+    }
+
+    intervals
+}
+
+/// Decodes and resolves each of the encoded calendar queries
+pub async fn resolve_queries(
+    app_state: &AppState,
+    queries: &[String],
+) -> AppResult<Vec<Vec<ResolvedSubscription>>> {
+    try_join_all(queries.iter().map(|encoded_query| async move {
+        let subscriptions = decode_calendar_query(encoded_query)?;
+
+        resolve_subscriptions(app_state, subscriptions).await
+    }))
+    .await
+}
+
+pub async fn free_busy(app_state: &AppState, query: FreeBusyQuery) -> AppResult<FreeBusy> {
+    let resolved_queries = resolve_queries(app_state, &query.queries).await?;
+
+    let per_query = resolved_queries
+        .iter()
+        .map(|resolved_subscriptions| {
+            clip_slots(
+                query_busy_slots(resolved_subscriptions),
+                query.from,
+                query.to,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let calendars = if query.anonymise {
+        Vec::new()
+    } else {
+        resolved_queries
+            .iter()
+            .map(|resolved_subscriptions| {
+                resolved_subscriptions
+                    .iter()
+                    .map(ResolvedSubscription::display_name)
+                    .unique()
+                    .join(", ")
+            })
+            .collect()
+    };
+
+    Ok(FreeBusy {
+        from: query.from,
+        to: query.to,
+        calendars,
+        busy: busy_intervals(&per_query, query.anonymise),
+    })
+}
+
+fn free_busy_component(
+    uid: &str,
+    free_busy: &FreeBusy,
+    intervals: &[&BusyInterval],
+) -> CalendarComponent {
+    let mut component = "BEGIN:VFREEBUSY\r\nEND:VFREEBUSY\r\n"
+        .parse::<CalendarComponent>()
+        .expect("An empty VFREEBUSY should parse");
+
+    if let CalendarComponent::Other(ref mut other) = component {
+        other.uid(uid);
+        other.timestamp(Utc::now());
+
+        let from = free_busy
+            .from
+            .or_else(|| intervals.first().map(|interval| interval.start));
+        let to = free_busy
+            .to
+            .or_else(|| intervals.last().map(|interval| interval.end));
+
+        if let Some(from) = from {
+            other.add_property("DTSTART", &from.format(UTC_FORMAT).to_string());
+        }
+
+        if let Some(to) = to {
+            other.add_property("DTEND", &to.format(UTC_FORMAT).to_string());
+        }
+
+        for interval in intervals {
+            let period = format!(
+                "{}/{}",
+                interval.start.format(UTC_FORMAT),
+                interval.end.format(UTC_FORMAT)
+            );
+
+            other.append_multi_property(
+                Property::new("FREEBUSY", &period)
+                    .add_parameter("FBTYPE", "BUSY")
+                    .done(),
+            );
+        }
+    }
+
+    component
+}
+
+/// One VFREEBUSY per calendar, or a single merged one when anonymised
+pub fn free_busy_calendar(free_busy: &FreeBusy) -> Calendar {
+    let mut calendar = Calendar::new();
+
+    if free_busy.calendars.is_empty() {
+        let intervals = free_busy.busy.iter().collect::<Vec<_>>();
+        calendar.push(free_busy_component("freebusy", free_busy, &intervals));
+
+        return calendar;
+    }
+
+    for (index, name) in free_busy.calendars.iter().enumerate() {
+        // Intervals overlapping this calendar's busy time are merged back together
+        let slots = free_busy
+            .busy
+            .iter()
+            .filter(|interval| interval.busy_calendars.contains(&(index as u32)))
+            .map(|interval| TimeSlot {
+                start: interval.start,
+                end: interval.end,
+            });
+
+        let merged = busy_intervals(&[merge_slots(slots)], true);
+        let intervals = merged.iter().collect::<Vec<_>>();
+
+        let mut component =
+            free_busy_component(&format!("freebusy-{index}"), free_busy, &intervals);
+
+        if let CalendarComponent::Other(ref mut other) = component {
+            other.add_property("COMMENT", name);
+        }
+
+        calendar.push(component);
+    }
+
+    calendar
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn slot(start: u32, end: u32) -> TimeSlot {
+        TimeSlot {
+            start: Utc.with_ymd_and_hms(2023, 1, 10, start, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, 10, end, 0, 0).unwrap(),
+        }
+    }
+
+    fn summary(intervals: &[BusyInterval]) -> Vec<(u32, u32, Vec<u32>)> {
+        use chrono::Timelike;
+
+        intervals
+            .iter()
+            .map(|interval| {
+                (
+                    interval.start.hour(),
+                    interval.end.hour(),
+                    interval.busy_calendars.clone(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_busy_intervals() {
+        let per_query = vec![vec![slot(8, 10), slot(12, 14)], vec![slot(9, 12)]];
+
+        assert_eq!(
+            summary(&busy_intervals(&per_query, false)),
+            vec![
+                (8, 9, vec![0]),
+                (9, 10, vec![0, 1]),
+                (10, 12, vec![1]),
+                (12, 14, vec![0]),
+            ]
+        );
+
+        assert_eq!(
+            summary(&busy_intervals(&per_query, true)),
+            vec![(8, 14, vec![])]
+        );
+    }
+
+    #[test]
+    fn test_free_busy_calendar() {
+        let free_busy = FreeBusy {
+            from: None,
+            to: None,
+            calendars: vec!["TDT4100".to_owned(), "TMA4100".to_owned()],
+            busy: busy_intervals(&[vec![slot(8, 10)], vec![slot(9, 11)]], false),
+        };
+
+        let ics = free_busy_calendar(&free_busy).to_string();
+
+        assert_eq!(ics.matches("BEGIN:VFREEBUSY").count(), 2);
+        assert!(ics.contains("FREEBUSY;FBTYPE=BUSY:20230110T080000Z/20230110T100000Z"));
+        assert!(ics.contains("FREEBUSY;FBTYPE=BUSY:20230110T090000Z/20230110T110000Z"));
+        assert!(ics.contains("COMMENT:TMA4100"));
+    }
+}
@@ -0,0 +1,94 @@
+use crate::error::AppResult;
+use crate::search::normalize::normalize;
+use crate::shared_types::{FreeRoom, FreeRoomsQuery, Room, RoomIdentifier};
+use crate::time_slots::{busy_slots, free_slots};
+use crate::AppState;
+use chrono::Duration;
+use futures_util::{stream, StreamExt, TryStreamExt};
+use std::collections::BTreeMap;
+
+/// Room schedules fetched at the same time when looking for free rooms
+const MAX_CONCURRENT_FETCHES: usize = 8;
+
+/// Rooms seen in cached timetables for `semester` whose building name contains `building`
+fn known_rooms_in_building(app_state: &AppState, semester: &str, building: &str) -> Vec<Room> {
+    let building = normalize(building);
+
+    let cached_activities = app_state
+        .activities_cache
+        .cached_timetables()
+        .into_iter()
+        .filter(|(identifier, _)| identifier.semester == semester)
+        .map(|(_, activities)| activities)
+        .chain(
+            app_state
+                .programme_activities_cache
+                .cached_timetables()
+                .into_iter()
+                .filter(|(identifier, _)| identifier.semester == semester)
+                .map(|(_, activities)| activities),
+        )
+        .chain(
+            app_state
+                .room_activities_cache
+                .cached_timetables()
+                .into_iter()
+                .filter(|(identifier, _)| identifier.semester == semester)
+                .map(|(_, activities)| activities),
+        );
+
+    let mut rooms = BTreeMap::<String, Room>::new();
+
+    for activities in cached_activities {
+        for room in activities.iter().flat_map(|activity| &activity.rooms) {
+            let Some(id) = &room.id else {
+                continue;
+            };
+
+            if normalize(&room.building_name).contains(&building) {
+                rooms.entry(id.clone()).or_insert_with(|| room.clone());
+            }
+        }
+    }
+
+    rooms.into_values().collect()
+}
+
+pub async fn find_free_rooms(
+    app_state: &AppState,
+    query: FreeRoomsQuery,
+) -> AppResult<Vec<FreeRoom>> {
+    if query.from >= query.to {
+        return Ok(Vec::new());
+    }
+
+    let min_duration = match query.min_duration_minutes {
+        Some(minutes) => Duration::minutes(minutes.into()),
+        None => query.to - query.from,
+    };
+
+    let rooms = known_rooms_in_building(app_state, &query.semester, &query.building);
+    let room_activities_cache = &app_state.room_activities_cache;
+
+    let mut free_rooms = stream::iter(rooms)
+        .map(|room| async {
+            let identifier = RoomIdentifier {
+                room_id: room.id.clone().unwrap_or_default(),
+                semester: query.semester.clone(),
+            };
+
+            let activities = room_activities_cache.get_or_fetch(identifier).await?;
+            let busy = busy_slots(activities.iter());
+            let free_slots = free_slots(&busy, query.from, query.to, min_duration);
+
+            AppResult::Ok(FreeRoom { room, free_slots })
+        })
+        .buffer_unordered(MAX_CONCURRENT_FETCHES)
+        .try_filter(|free_room| std::future::ready(!free_room.free_slots.is_empty()))
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    free_rooms.sort_by(|a, b| a.room.name.cmp(&b.room.name));
+
+    Ok(free_rooms)
+}
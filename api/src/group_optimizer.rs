@@ -0,0 +1,486 @@
+use crate::calendar::filter_activities::includes_target_group;
+use crate::error::{AppError, AppResult};
+use crate::shared_types::{
+    Activity, CalendarQuery, GroupOptimization, GroupOptimizerQuery, GroupSelection,
+    OptimizerCourse,
+};
+use crate::AppState;
+use chrono::Timelike;
+use chrono_tz::Europe::Oslo;
+use futures_util::future::try_join_all;
+use itertools::Itertools;
+use std::collections::{BTreeSet, HashSet};
+use std::sync::Arc;
+use tracing::error;
+
+const DEFAULT_ALTERNATIVES: u32 = 5;
+const MAX_ALTERNATIVES: u32 = 20;
+
+/// Upper bound on explored search nodes, so large selections can't stall the server
+const MAX_NODES: usize = 100_000;
+
+/// One parallel group to choose for a kind of activity in a course
+struct SlotOption<'a> {
+    group: &'a str,
+    activities: Vec<&'a Activity>,
+}
+
+/// A kind of activity in a course, e.g. "Øving", taught in several parallel groups
+struct Slot<'a> {
+    course_index: usize,
+    options: Vec<SlotOption<'a>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Score {
+    conflicting_minutes: i32,
+    /// Sum of the enabled secondary objectives
+    penalty: i32,
+}
+
+struct Candidate {
+    score: Score,
+    groups_per_course: Vec<BTreeSet<String>>,
+    days_on_campus: i32,
+    early_activities: i32,
+}
+
+/// Splits the activities outside the base groups into slots with one option per parallel group
+fn course_slots<'a>(
+    course_index: usize,
+    course: &OptimizerCourse,
+    activities: &'a [Activity],
+) -> Vec<Slot<'a>> {
+    let remaining = activities
+        .iter()
+        .filter(|activity| !includes_target_group(activity, &course.base_groups))
+        .collect::<Vec<_>>();
+
+    remaining
+        .iter()
+        .into_group_map_by(|activity| activity.title.as_str())
+        .into_iter()
+        .sorted_by_key(|(kind, _)| *kind)
+        .filter_map(|(_, kind_activities)| {
+            let groups = kind_activities
+                .iter()
+                .flat_map(|activity| &activity.student_groups)
+                .map(String::as_str)
+                .collect::<BTreeSet<_>>();
+
+            let mut seen_activity_sets = HashSet::new();
+
+            let options = groups
+                .into_iter()
+                .filter_map(|group| {
+                    let group_activities = remaining
+                        .iter()
+                        .filter(|activity| activity.student_groups.iter().any(|g| g == group))
+                        .copied()
+                        .collect::<Vec<_>>();
+
+                    // Groups sharing exactly the same activities are the same choice
+                    let activity_ids = group_activities
+                        .iter()
+                        .map(|activity| activity.id.as_str())
+                        .collect::<Vec<_>>();
+
+                    seen_activity_sets
+                        .insert(activity_ids)
+                        .then_some(SlotOption {
+                            group,
+                            activities: group_activities,
+                        })
+                })
+                .collect::<Vec<_>>();
+
+            (!options.is_empty()).then_some(Slot {
+                course_index,
+                options,
+            })
+        })
+        .collect()
+}
+
+fn overlap_minutes(activity: &Activity, chosen: &[&Activity]) -> i32 {
+    chosen
+        .iter()
+        .map(|other| {
+            let start = activity.start.max(other.start);
+            let end = activity.end.min(other.end);
+
+            if start < end {
+                (end - start).num_minutes() as i32
+            } else {
+                0
+            }
+        })
+        .sum()
+}
+
+struct Search<'a, 'q> {
+    query: &'q GroupOptimizerQuery,
+    slots: &'a [Slot<'a>],
+    max_alternatives: usize,
+    nodes: usize,
+    chosen_activities: Vec<&'a Activity>,
+    chosen_ids: HashSet<&'a str>,
+    chosen_options: Vec<usize>,
+    best: Vec<Candidate>,
+}
+
+impl<'a, 'q> Search<'a, 'q> {
+    fn worst_best_conflicts(&self) -> Option<i32> {
+        if self.best.len() < self.max_alternatives {
+            return None;
+        }
+
+        self.best
+            .last()
+            .map(|candidate| candidate.score.conflicting_minutes)
+    }
+
+    fn search(&mut self, slot_index: usize, conflicting_minutes: i32) {
+        self.nodes += 1;
+
+        if self.nodes > MAX_NODES {
+            return;
+        }
+
+        if let Some(worst) = self.worst_best_conflicts() {
+            if conflicting_minutes > worst {
+                return;
+            }
+        }
+
+        let Some(slot) = self.slots.get(slot_index) else {
+            self.record(conflicting_minutes);
+            return;
+        };
+
+        for (option_index, option) in slot.options.iter().enumerate() {
+            let mut added = 0;
+            let mut added_minutes = 0;
+
+            for activity in &option.activities {
+                if self.chosen_ids.insert(&activity.id) {
+                    added_minutes += overlap_minutes(activity, &self.chosen_activities);
+                    self.chosen_activities.push(activity);
+                    added += 1;
+                }
+            }
+
+            self.chosen_options.push(option_index);
+            self.search(slot_index + 1, conflicting_minutes + added_minutes);
+            self.chosen_options.pop();
+
+            for activity in self
+                .chosen_activities
+                .drain(self.chosen_activities.len() - added..)
+            {
+                self.chosen_ids.remove(activity.id.as_str());
+            }
+        }
+    }
+
+    fn record(&mut self, conflicting_minutes: i32) {
+        let days_on_campus = self
+            .chosen_activities
+            .iter()
+            .map(|activity| activity.start.with_timezone(&Oslo).date_naive())
+            .unique()
+            .count() as i32;
+
+        let early_activities = match self.query.earliest_start_hour {
+            Some(hour) => self
+                .chosen_activities
+                .iter()
+                .filter(|activity| activity.start.with_timezone(&Oslo).hour() < hour)
+                .count() as i32,
+            None => 0,
+        };
+
+        let mut penalty = early_activities;
+
+        if self.query.minimize_days_on_campus {
+            penalty += days_on_campus;
+        }
+
+        let mut groups_per_course = vec![BTreeSet::new(); self.query.courses.len()];
+
+        for (slot, option_index) in self.slots.iter().zip(&self.chosen_options) {
+            let group = slot.options[*option_index].group;
+            groups_per_course[slot.course_index].insert(group.to_owned());
+        }
+
+        if self
+            .best
+            .iter()
+            .any(|candidate| candidate.groups_per_course == groups_per_course)
+        {
+            return;
+        }
+
+        let candidate = Candidate {
+            score: Score {
+                conflicting_minutes,
+                penalty,
+            },
+            groups_per_course,
+            days_on_campus,
+            early_activities,
+        };
+
+        let position = self
+            .best
+            .partition_point(|best| best.score <= candidate.score);
+
+        if position < self.max_alternatives {
+            self.best.insert(position, candidate);
+            self.best.truncate(self.max_alternatives);
+        }
+    }
+}
+
+/// Ranks choices of one parallel group per activity kind in each course by how few conflicts they give
+pub fn optimize_groups(
+    query: &GroupOptimizerQuery,
+    activities_per_course: &[Arc<Vec<Activity>>],
+) -> GroupOptimization {
+    let max_alternatives = query
+        .max_alternatives
+        .unwrap_or(DEFAULT_ALTERNATIVES)
+        .clamp(1, MAX_ALTERNATIVES) as usize;
+
+    let base_activities = query
+        .courses
+        .iter()
+        .zip(activities_per_course)
+        .flat_map(|(course, activities)| {
+            activities
+                .iter()
+                .filter(|activity| includes_target_group(activity, &course.base_groups))
+        })
+        .collect::<Vec<_>>();
+
+    // Trying the slots with the fewest options first makes pruning kick in earlier
+    let slots = query
+        .courses
+        .iter()
+        .zip(activities_per_course)
+        .enumerate()
+        .flat_map(|(course_index, (course, activities))| {
+            course_slots(course_index, course, activities)
+        })
+        .sorted_by_key(|slot| slot.options.len())
+        .collect::<Vec<_>>();
+
+    let mut chosen_activities = Vec::new();
+    let mut chosen_ids = HashSet::new();
+    let mut conflicting_minutes = 0;
+
+    for activity in base_activities {
+        if chosen_ids.insert(activity.id.as_str()) {
+            conflicting_minutes += overlap_minutes(activity, &chosen_activities);
+            chosen_activities.push(activity);
+        }
+    }
+
+    let mut search = Search {
+        query,
+        slots: &slots,
+        max_alternatives,
+        nodes: 0,
+        chosen_activities,
+        chosen_ids,
+        chosen_options: Vec::new(),
+        best: Vec::new(),
+    };
+
+    search.search(0, conflicting_minutes);
+
+    let exhaustive = search.nodes <= MAX_NODES;
+
+    let selections = search
+        .best
+        .into_iter()
+        .map(|candidate| {
+            let queries = query
+                .courses
+                .iter()
+                .zip(candidate.groups_per_course)
+                .map(|(course, chosen_groups)| {
+                    let mut student_groups = course.base_groups.clone();
+                    student_groups.extend(
+                        chosen_groups
+                            .into_iter()
+                            .filter(|group| !course.base_groups.contains(group)),
+                    );
+
+                    CalendarQuery {
+                        identifier: course.identifier.clone(),
+                        student_groups,
+                        custom_name: course.custom_name.clone(),
+                        include_exams: false,
+                    }
+                })
+                .collect();
+
+            GroupSelection {
+                queries,
+                conflicting_minutes: candidate.score.conflicting_minutes,
+                days_on_campus: candidate.days_on_campus,
+                early_activities: candidate.early_activities,
+            }
+        })
+        .collect();
+
+    GroupOptimization {
+        selections,
+        exhaustive,
+    }
+}
+
+pub async fn find_group_selections(
+    app_state: &AppState,
+    query: GroupOptimizerQuery,
+) -> AppResult<GroupOptimization> {
+    let activities_cache = &app_state.activities_cache;
+
+    let activities = query
+        .courses
+        .iter()
+        .map(|course| activities_cache.get_or_fetch(course.identifier.clone()));
+
+    let activities_per_course = try_join_all(activities).await?;
+
+    // The search is CPU bound, so it shouldn't hold up other requests on the runtime
+    tokio::task::spawn_blocking(move || optimize_groups(&query, &activities_per_course))
+        .await
+        .map_err(|join_error| {
+            error!("Group optimizer failed: {join_error}");
+
+            AppError::InternalError
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared_types::CourseIdentifier;
+    use chrono::{TimeZone, Utc};
+
+    fn activity(id: &str, title: &str, day: u32, hour: u32, groups: &[&str]) -> Activity {
+        let start = Utc.with_ymd_and_hms(2023, 1, day, hour, 0, 0).unwrap();
+
+        Activity {
+            id: id.to_owned(),
+            course_code: String::new(),
+            week: 2,
+            start,
+            end: start + chrono::Duration::hours(2),
+            title: title.to_owned(),
+            summary: String::new(),
+            staff_members: Vec::new(),
+            student_groups: groups.iter().map(|group| group.to_string()).collect(),
+            rooms: Vec::new(),
+        }
+    }
+
+    fn course(course_code: &str, base_groups: &[&str]) -> OptimizerCourse {
+        OptimizerCourse {
+            identifier: CourseIdentifier {
+                course_code: course_code.to_owned(),
+                course_term: 1,
+                semester: "23v".to_owned(),
+            },
+            custom_name: None,
+            base_groups: base_groups.iter().map(|group| group.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_avoids_conflicting_group() {
+        let first = vec![activity("a1", "Forelesning", 9, 8, &["BPROG_1"])];
+        let second = vec![
+            activity("b1", "Forelesning", 10, 8, &["BPROG_1"]),
+            activity("b2", "Øving", 9, 8, &["GR1"]),
+            activity("b3", "Øving", 11, 8, &["GR2"]),
+        ];
+
+        let query = GroupOptimizerQuery {
+            courses: vec![course("A", &["BPROG_1"]), course("B", &["BPROG_1"])],
+            minimize_days_on_campus: false,
+            earliest_start_hour: None,
+            max_alternatives: Some(2),
+        };
+
+        let optimization = optimize_groups(&query, &[Arc::new(first), Arc::new(second)]);
+        assert!(optimization.exhaustive);
+
+        let selections = optimization.selections;
+
+        assert_eq!(selections.len(), 2);
+        assert_eq!(selections[0].conflicting_minutes, 0);
+        assert_eq!(
+            selections[0].queries[1].student_groups,
+            vec!["BPROG_1", "GR2"]
+        );
+        assert_eq!(selections[1].conflicting_minutes, 120);
+        assert_eq!(
+            selections[1].queries[1].student_groups,
+            vec!["BPROG_1", "GR1"]
+        );
+    }
+
+    #[test]
+    fn test_minimizes_days_on_campus() {
+        let first = vec![activity("a1", "Forelesning", 9, 8, &["BPROG_1"])];
+        let second = vec![
+            activity("b1", "Øving", 9, 12, &["GR1"]),
+            activity("b2", "Øving", 10, 12, &["GR2"]),
+        ];
+
+        let query = GroupOptimizerQuery {
+            courses: vec![course("A", &["BPROG_1"]), course("B", &[])],
+            minimize_days_on_campus: true,
+            earliest_start_hour: None,
+            max_alternatives: None,
+        };
+
+        let selections = optimize_groups(&query, &[Arc::new(first), Arc::new(second)]).selections;
+
+        assert_eq!(selections[0].days_on_campus, 1);
+        assert_eq!(selections[0].queries[1].student_groups, vec!["GR1"]);
+    }
+
+    #[test]
+    fn test_reports_when_search_is_cut_short() {
+        // Eight parallel groups for each of six activity kinds is more than the node limit
+        let activities = (0..6)
+            .flat_map(|kind| {
+                (0..8).map(move |group| {
+                    activity(
+                        &format!("k{kind}g{group}"),
+                        &format!("Kind {kind}"),
+                        9 + kind,
+                        8 + group,
+                        &[&format!("K{kind}G{group}")],
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let query = GroupOptimizerQuery {
+            courses: vec![course("A", &[])],
+            minimize_days_on_campus: false,
+            earliest_start_hour: None,
+            max_alternatives: Some(1),
+        };
+
+        let optimization = optimize_groups(&query, &[Arc::new(activities)]);
+
+        assert!(!optimization.exhaustive);
+        assert_eq!(optimization.selections[0].conflicting_minutes, 0);
+    }
+}
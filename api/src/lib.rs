@@ -1,32 +1,84 @@
 use crate::caching::activities_cache::ActivitiesCache;
 use crate::caching::courses_cache::CoursesCache;
+use crate::caching::exams_cache::ExamsCache;
+use crate::caching::prefetch::PrefetchScheduler;
 use crate::caching::semesters_cache::SemestersCache;
+use crate::caldav::caldav_handler::CollectionCache;
+use crate::changes::change_tracker::ChangeTracker;
+use crate::changes::snapshots::SnapshotStore;
+use crate::changes::webhooks::WebhookRegistry;
+use crate::fetch::http_client::HttpClient;
+use crate::notifications::email_notifications::EmailNotifications;
+use crate::shared_types::{ProgrammeIdentifier, RoomIdentifier};
+use crate::staff::StaffIndex;
+use std::net::IpAddr;
 use std::sync::Arc;
 
 pub mod caching;
+pub mod caldav;
 pub mod calendar;
+pub mod changes;
+pub mod common_free_slots;
+pub mod conflicts;
 pub mod error;
 pub mod fetch;
+pub mod free_busy;
+pub mod free_rooms;
+pub mod group_optimizer;
+pub mod notifications;
 pub mod router;
+pub mod search;
 pub mod shared_types;
+pub mod staff;
+pub mod storage;
+pub mod student_groups;
+pub mod time_slots;
+pub mod week_view;
 
 #[derive(Clone)]
 pub struct AppState {
     pub activities_cache: Arc<ActivitiesCache>,
+    pub programme_activities_cache: Arc<ActivitiesCache<ProgrammeIdentifier>>,
+    pub room_activities_cache: Arc<ActivitiesCache<RoomIdentifier>>,
     pub courses_cache: Arc<CoursesCache>,
+    pub exams_cache: Arc<ExamsCache>,
     pub semesters_cache: Arc<SemestersCache>,
+    pub change_tracker: Arc<ChangeTracker>,
+    pub webhook_registry: Arc<WebhookRegistry>,
+    pub email_notifications: Arc<EmailNotifications>,
+    pub snapshot_store: Arc<SnapshotStore>,
+    pub prefetch_scheduler: Arc<PrefetchScheduler>,
+    pub staff_index: Arc<StaffIndex>,
+    pub caldav_collections: Arc<CollectionCache>,
+    /// Who sent the request, set for rspc procedures that limit how often a client may call them
+    pub client_address: Option<IpAddr>,
 }
 
 impl AppState {
-    pub async fn new(reqwest_client: &reqwest::Client) -> anyhow::Result<Self> {
-        let activities_cache: ActivitiesCache = ActivitiesCache::new(reqwest_client.clone());
-        let courses_cache = CoursesCache::new(reqwest_client.clone()).await;
-        let semesters_cache = SemestersCache::new(reqwest_client.clone()).await?;
+    pub async fn new(http_client: &HttpClient) -> anyhow::Result<Self> {
+        let activities_cache: ActivitiesCache = ActivitiesCache::new(http_client.clone());
+        let programme_activities_cache = ActivitiesCache::new(http_client.clone());
+        let room_activities_cache = ActivitiesCache::new(http_client.clone());
+        let courses_cache = CoursesCache::new(http_client.clone()).await;
+        let exams_cache = ExamsCache::new(http_client.clone());
+        let semesters_cache = SemestersCache::new(http_client.clone()).await?;
+        let webhook_registry = WebhookRegistry::from_env()?;
 
         Ok(Self {
             activities_cache: Arc::new(activities_cache),
+            programme_activities_cache: Arc::new(programme_activities_cache),
+            room_activities_cache: Arc::new(room_activities_cache),
             courses_cache: Arc::new(courses_cache),
+            exams_cache: Arc::new(exams_cache),
             semesters_cache: Arc::new(semesters_cache),
+            change_tracker: Arc::new(ChangeTracker::new()),
+            webhook_registry: Arc::new(webhook_registry),
+            email_notifications: Arc::new(EmailNotifications::from_env()),
+            snapshot_store: Arc::new(SnapshotStore::from_env()),
+            prefetch_scheduler: Arc::new(PrefetchScheduler::new()),
+            staff_index: Arc::new(StaffIndex::from_env()),
+            caldav_collections: Arc::new(CollectionCache::new()),
+            client_address: None,
         })
     }
 }
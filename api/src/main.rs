@@ -1,20 +1,62 @@
-use axum::routing::get;
+use axum::extract::ConnectInfo;
+use axum::routing::{any, get};
+use ntnu_timeplan_api::caching::prefetch::spawn_prefetch;
+use ntnu_timeplan_api::caldav::caldav_handler::{
+    caldav_collection_handler, caldav_resource_handler,
+};
 use ntnu_timeplan_api::calendar::calendar_handler::calendar_handler;
+use ntnu_timeplan_api::calendar::export_handler::{
+    csv_handler, html_handler, jcal_handler, json_handler,
+};
+use ntnu_timeplan_api::calendar::free_busy_handler::free_busy_handler;
+use ntnu_timeplan_api::calendar::inspect_handler::inspect_handler;
+use ntnu_timeplan_api::changes::change_feed_handler::change_feed_handler;
+use ntnu_timeplan_api::changes::change_tracker::spawn_change_detection;
+use ntnu_timeplan_api::fetch::http_client::HttpClient;
+use ntnu_timeplan_api::notifications::email_notifications::spawn_email_digests;
+use ntnu_timeplan_api::notifications::notification_handler::{
+    confirm_handler, confirm_page_handler, unsubscribe_handler, unsubscribe_page_handler,
+};
 use ntnu_timeplan_api::router::rspc_router;
+use ntnu_timeplan_api::staff::spawn_staff_indexing;
 use ntnu_timeplan_api::AppState;
+use rspc::integrations::httpz::Request;
 use std::env;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use tower_http::cors::CorsLayer;
 
+/// The address of the client, from the header named by `CLIENT_IP_HEADER` when behind a proxy
+fn client_address(request: &Request, client_ip_header: Option<&str>) -> Option<IpAddr> {
+    match client_ip_header {
+        Some(header) => request
+            .headers()
+            .get(header)?
+            .to_str()
+            .ok()?
+            .trim()
+            .parse()
+            .ok(),
+        None => request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip()),
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
 
-    let reqwest_client = reqwest::Client::new();
+    let http_client = HttpClient::new()?;
     let router = Arc::new(rspc_router());
 
-    let app_state = AppState::new(&reqwest_client).await?;
+    let app_state = AppState::new(&http_client).await?;
+
+    spawn_change_detection(app_state.clone());
+    spawn_email_digests(app_state.clone());
+    spawn_prefetch(app_state.clone());
+    spawn_staff_indexing(app_state.clone());
 
     //     let app = Route::new()
     //         .nest("/", ui)
@@ -23,12 +65,74 @@ async fn main() -> anyhow::Result<()> {
     //         .at("/calendar.ics", get(calendar_handler).with(Tracing))
     //         .data(app_state);
 
+    let client_ip_header = env::var("CLIENT_IP_HEADER").ok();
+
     let app = axum::Router::new()
         .route(
             "/calendar.ics",
             get(calendar_handler).with_state(app_state.clone()),
         )
-        .nest("/rspc", router.endpoint(move || app_state.clone()).axum())
+        .route(
+            "/calendar.json",
+            get(json_handler).with_state(app_state.clone()),
+        )
+        .route(
+            "/calendar.jcal",
+            get(jcal_handler).with_state(app_state.clone()),
+        )
+        .route(
+            "/calendar.csv",
+            get(csv_handler).with_state(app_state.clone()),
+        )
+        .route(
+            "/calendar.html",
+            get(html_handler).with_state(app_state.clone()),
+        )
+        .route(
+            "/freebusy.ics",
+            get(free_busy_handler).with_state(app_state.clone()),
+        )
+        .route(
+            "/calendar/inspect",
+            get(inspect_handler).with_state(app_state.clone()),
+        )
+        .route(
+            "/caldav/:query",
+            any(caldav_collection_handler).with_state(app_state.clone()),
+        )
+        .route(
+            "/caldav/:query/",
+            any(caldav_collection_handler).with_state(app_state.clone()),
+        )
+        .route(
+            "/caldav/:query/:resource",
+            any(caldav_resource_handler).with_state(app_state.clone()),
+        )
+        .route(
+            "/changes.atom",
+            get(change_feed_handler).with_state(app_state.clone()),
+        )
+        .route(
+            "/notifications/confirm",
+            get(confirm_page_handler)
+                .post(confirm_handler)
+                .with_state(app_state.clone()),
+        )
+        .route(
+            "/notifications/unsubscribe",
+            get(unsubscribe_page_handler)
+                .post(unsubscribe_handler)
+                .with_state(app_state.clone()),
+        )
+        .nest(
+            "/rspc",
+            router
+                .endpoint(move |request: Request| AppState {
+                    client_address: client_address(&request, client_ip_header.as_deref()),
+                    ..app_state.clone()
+                })
+                .axum(),
+        )
         .layer(CorsLayer::permissive());
 
     let port = match env::var("PORT") {
@@ -40,7 +144,7 @@ async fn main() -> anyhow::Result<()> {
 
     tracing::info!("listening on http://{}", addr);
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await?;
 
     Ok(())
@@ -0,0 +1,654 @@
+use crate::changes::change_feed::{describe_changes, selection_changes, selection_courses};
+use crate::changes::webhooks::random_hex;
+use crate::error::{AppError, AppResult};
+use crate::notifications::rate_limit::RateLimiter;
+use crate::notifications::smtp::{is_valid_address, EmailMessage, SmtpClient, SmtpConfig};
+use crate::shared_types::{
+    Activity, ActivityChanges, CalendarQuery, CourseChanges, CourseIdentifier,
+    EmailNotificationRequest,
+};
+use crate::storage::JsonFile;
+use crate::AppState;
+use chrono::{DateTime, Duration, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use time::ext::NumericalStdDuration;
+use tracing::{error, warn};
+
+/// Changes to activities this close are sent right away instead of in the weekly digest
+fn alert_window() -> Duration {
+    Duration::hours(48)
+}
+
+fn digest_interval() -> Duration {
+    Duration::weeks(1)
+}
+
+/// Unconfirmed addresses are forgotten after this
+fn confirmation_lifetime() -> Duration {
+    Duration::days(2)
+}
+
+/// Confirmation emails a single address can receive in a day
+const MAX_CONFIRMATIONS_PER_ADDRESS: usize = 3;
+
+/// Sign-ups a single client can make in an hour
+const MAX_REGISTRATIONS_PER_CLIENT: usize = 5;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct EmailSubscription {
+    email: String,
+    /// The course entries of the stored calendar selection, as only courses are tracked
+    courses: Vec<CalendarQuery>,
+    confirm_token: String,
+    unsubscribe_token: String,
+    confirmed: bool,
+    created_at: DateTime<Utc>,
+    last_digest: DateTime<Utc>,
+    pending: Vec<CourseChanges>,
+}
+
+/// Splits the changes into those touching activities within the alert window and the rest
+pub fn split_urgent(
+    changes: &ActivityChanges,
+    now: DateTime<Utc>,
+) -> (ActivityChanges, ActivityChanges) {
+    let is_urgent =
+        |activity: &Activity| activity.end > now && activity.start < now + alert_window();
+
+    let urgent = changes.filter(is_urgent);
+    let later = ActivityChanges {
+        added: changes
+            .added
+            .iter()
+            .filter(|activity| !is_urgent(activity))
+            .cloned()
+            .collect(),
+        removed: changes
+            .removed
+            .iter()
+            .filter(|activity| !is_urgent(activity))
+            .cloned()
+            .collect(),
+        moved: changes
+            .moved
+            .iter()
+            .filter(|moved| !is_urgent(&moved.before) && !is_urgent(&moved.after))
+            .cloned()
+            .collect(),
+    };
+
+    (urgent, later)
+}
+
+/// Emails about timetable changes, only sent to addresses that have confirmed their subscription
+pub struct EmailNotifications {
+    smtp: Option<SmtpClient>,
+    /// Where this API is reachable, for links in emails
+    public_url: String,
+    subscriptions: Mutex<Vec<EmailSubscription>>,
+    file: JsonFile,
+    /// Limits how many emails sign-ups can make us send, as anyone can sign up any address
+    address_limiter: RateLimiter,
+    client_limiter: RateLimiter,
+}
+
+impl EmailNotifications {
+    pub fn new(smtp: Option<SmtpClient>, public_url: String, file: JsonFile) -> Self {
+        Self {
+            smtp,
+            public_url: public_url.trim_end_matches('/').to_owned(),
+            subscriptions: Mutex::new(file.load_or_default()),
+            file,
+            address_limiter: RateLimiter::new(MAX_CONFIRMATIONS_PER_ADDRESS, Duration::days(1)),
+            client_limiter: RateLimiter::new(MAX_REGISTRATIONS_PER_CLIENT, Duration::hours(1)),
+        }
+    }
+
+    /// Configured from `SMTP_*` and `PUBLIC_URL`, disabled without `SMTP_HOST`
+    pub fn from_env() -> Self {
+        let public_url =
+            env::var("PUBLIC_URL").unwrap_or_else(|_| "http://localhost:8080".to_owned());
+
+        // Misconfigured email is logged when the client is created, and disables email like no config
+        let smtp = SmtpConfig::from_env().and_then(|config| SmtpClient::new(config).ok());
+
+        Self::new(
+            smtp,
+            public_url,
+            JsonFile::in_data_directory("email_subscriptions.json"),
+        )
+    }
+
+    fn link(&self, action: &str, token: &str) -> String {
+        format!("{}/notifications/{action}?token={token}", self.public_url)
+    }
+
+    async fn send(&self, message: &EmailMessage) -> AppResult<()> {
+        let Some(smtp) = &self.smtp else {
+            return Err(AppError::EmailError);
+        };
+
+        smtp.send(message).await
+    }
+
+    fn with_subscriptions<T>(&self, action: impl FnOnce(&mut Vec<EmailSubscription>) -> T) -> T {
+        let mut subscriptions = self
+            .subscriptions
+            .lock()
+            .expect("Email subscriptions lock should not be poisoned");
+
+        action(&mut subscriptions)
+    }
+
+    async fn save(&self) -> AppResult<()> {
+        self.file
+            .save(|| self.with_subscriptions(|subscriptions| subscriptions.clone()))
+            .await
+    }
+
+    /// For saves after background work, which has nobody to report the error to
+    async fn save_or_log(&self) {
+        if let Err(storage_error) = self.save().await {
+            error!("Failed to store email subscriptions: {storage_error:?}");
+        }
+    }
+
+    /// Stores the selection and sends a link the address has to be confirmed with
+    ///
+    /// Nothing is sent while an earlier link to the address is still valid, or when the address or
+    /// the client has signed up too often
+    pub async fn register(
+        &self,
+        request: EmailNotificationRequest,
+        client: Option<IpAddr>,
+    ) -> AppResult<()> {
+        if self.smtp.is_none() {
+            return Err(AppError::EmailError);
+        }
+
+        if !is_valid_address(&request.email) {
+            return Err(AppError::InvalidInput("Invalid email address".to_owned()));
+        }
+
+        let courses = selection_courses(&request.query)?;
+
+        if courses.is_empty() {
+            return Err(AppError::InvalidInput(
+                "The selection has no courses".to_owned(),
+            ));
+        }
+
+        let now = Utc::now();
+        let address_key = request.email.to_lowercase();
+        let client_key = client.map(|client| client.to_string()).unwrap_or_default();
+
+        if !self.client_limiter.try_acquire(&client_key, now)
+            || !self.address_limiter.try_acquire(&address_key, now)
+        {
+            return Err(AppError::InvalidInput(
+                "Too many sign-ups, please try again later".to_owned(),
+            ));
+        }
+
+        let confirm_token = random_hex(16)?;
+        let unsubscribe_token = random_hex(16)?;
+
+        let message = EmailMessage {
+            to: request.email.clone(),
+            subject: "Confirm timetable change notifications".to_owned(),
+            body: format!(
+                "Someone asked for changes to these courses to be sent to this address:\n\n{}\n\n\
+                 Confirm by opening {}\n\nIf it wasn't you, just ignore this email.",
+                courses
+                    .iter()
+                    .map(|query| query.identifier.course_code.as_str())
+                    .join(", "),
+                self.link("confirm", &confirm_token)
+            ),
+            unsubscribe_url: None,
+        };
+
+        self.with_subscriptions(|subscriptions| {
+            let awaiting_confirmation = subscriptions.iter().any(|subscription| {
+                !subscription.confirmed
+                    && subscription.email.to_lowercase() == address_key
+                    && now - subscription.created_at < confirmation_lifetime()
+            });
+
+            if awaiting_confirmation {
+                return Err(AppError::InvalidInput(
+                    "A confirmation email was already sent to this address".to_owned(),
+                ));
+            }
+
+            subscriptions.push(EmailSubscription {
+                email: request.email,
+                courses,
+                confirm_token: confirm_token.clone(),
+                unsubscribe_token,
+                confirmed: false,
+                created_at: now,
+                last_digest: now,
+                pending: Vec::new(),
+            });
+
+            Ok(())
+        })?;
+
+        if let Err(send_error) = self.send(&message).await {
+            self.with_subscriptions(|subscriptions| {
+                subscriptions.retain(|subscription| subscription.confirm_token != confirm_token)
+            });
+
+            return Err(send_error);
+        }
+
+        self.save().await
+    }
+
+    /// Confirms the subscription, which replaces any earlier subscription of the address
+    pub async fn confirm(&self, token: &str) -> AppResult<bool> {
+        let confirmed = self.with_subscriptions(|subscriptions| {
+            let Some(index) = subscriptions
+                .iter()
+                .position(|subscription| !token.is_empty() && subscription.confirm_token == token)
+            else {
+                return false;
+            };
+
+            let address = subscriptions[index].email.to_lowercase();
+            let replaced = subscriptions
+                .iter()
+                .enumerate()
+                .find(|(other, subscription)| {
+                    *other != index
+                        && subscription.confirmed
+                        && subscription.email.to_lowercase() == address
+                })
+                .map(|(_, subscription)| subscription.unsubscribe_token.clone());
+
+            let subscription = &mut subscriptions[index];
+            subscription.confirmed = true;
+            subscription.last_digest = Utc::now();
+
+            // Links in emails already sent keep working
+            if let Some(unsubscribe_token) = replaced {
+                subscription.unsubscribe_token = unsubscribe_token;
+            }
+
+            let confirm_token = subscription.confirm_token.clone();
+            subscriptions.retain(|subscription| {
+                subscription.confirm_token == confirm_token
+                    || subscription.email.to_lowercase() != address
+            });
+
+            true
+        });
+
+        if confirmed {
+            self.save().await?;
+        }
+
+        Ok(confirmed)
+    }
+
+    pub async fn unsubscribe(&self, token: &str) -> AppResult<bool> {
+        let removed = self.with_subscriptions(|subscriptions| {
+            let count = subscriptions.len();
+            subscriptions.retain(|subscription| subscription.unsubscribe_token != token);
+
+            subscriptions.len() != count
+        });
+
+        if removed {
+            self.save().await?;
+        }
+
+        Ok(removed)
+    }
+
+    pub fn subscribed_courses(&self) -> Vec<CourseIdentifier> {
+        self.with_subscriptions(|subscriptions| {
+            subscriptions
+                .iter()
+                .filter(|subscription| subscription.confirmed)
+                .flat_map(|subscription| &subscription.courses)
+                .map(|query| query.identifier.clone())
+                .unique()
+                .collect()
+        })
+    }
+
+    fn change_message(
+        &self,
+        email: &str,
+        unsubscribe_token: &str,
+        subject: &str,
+        changes: &[CourseChanges],
+    ) -> EmailMessage {
+        let unsubscribe_url = self.link("unsubscribe", unsubscribe_token);
+
+        EmailMessage {
+            to: email.to_owned(),
+            subject: subject.to_owned(),
+            body: format!(
+                "{}\n\nStop these emails: {unsubscribe_url}",
+                describe_changes(changes)
+            ),
+            unsubscribe_url: Some(unsubscribe_url),
+        }
+    }
+
+    /// Alerts about changes in the next 48 hours right away, and saves the rest for the digest
+    pub async fn handle_changes(&self, course_changes: &CourseChanges) {
+        let now = Utc::now();
+
+        let (alerts, stored) = self.with_subscriptions(|subscriptions| {
+            let mut alerts = Vec::new();
+            let mut stored = false;
+
+            for subscription in subscriptions.iter_mut().filter(|s| s.confirmed) {
+                let Some(relevant) = selection_changes(course_changes, &subscription.courses)
+                else {
+                    continue;
+                };
+
+                let (urgent, later) = split_urgent(&relevant.changes, now);
+
+                if !later.is_empty() {
+                    subscription.pending.push(CourseChanges {
+                        changes: later,
+                        ..course_changes.clone()
+                    });
+                    stored = true;
+                }
+
+                if !urgent.is_empty() {
+                    alerts.push(self.change_message(
+                        &subscription.email,
+                        &subscription.unsubscribe_token,
+                        "Timetable changes in the next 48 hours",
+                        &[CourseChanges {
+                            changes: urgent,
+                            ..course_changes.clone()
+                        }],
+                    ));
+                }
+            }
+
+            (alerts, stored)
+        });
+
+        if stored {
+            self.save_or_log().await;
+        }
+
+        for alert in alerts {
+            if let Err(send_error) = self.send(&alert).await {
+                warn!("Failed to send change alert: {send_error:?}");
+            }
+        }
+    }
+
+    /// Sends the weekly digest to everyone due one, and forgets unconfirmed addresses
+    pub async fn send_due_digests(&self) {
+        let now = Utc::now();
+
+        let digests = self.with_subscriptions(|subscriptions| {
+            subscriptions.retain(|subscription| {
+                subscription.confirmed || now - subscription.created_at < confirmation_lifetime()
+            });
+
+            subscriptions
+                .iter_mut()
+                .filter(|subscription| {
+                    subscription.confirmed
+                        && !subscription.pending.is_empty()
+                        && now - subscription.last_digest >= digest_interval()
+                })
+                .map(|subscription| {
+                    subscription.last_digest = now;
+                    let pending = std::mem::take(&mut subscription.pending);
+
+                    (
+                        subscription.unsubscribe_token.clone(),
+                        self.change_message(
+                            &subscription.email,
+                            &subscription.unsubscribe_token,
+                            "Weekly timetable changes",
+                            &pending,
+                        ),
+                        pending,
+                    )
+                })
+                .collect::<Vec<_>>()
+        });
+
+        for (unsubscribe_token, message, pending) in digests {
+            if let Err(send_error) = self.send(&message).await {
+                warn!("Failed to send weekly digest: {send_error:?}");
+
+                // Kept for the next attempt, unless the address was unsubscribed meanwhile
+                self.with_subscriptions(|subscriptions| {
+                    if let Some(subscription) = subscriptions
+                        .iter_mut()
+                        .find(|subscription| subscription.unsubscribe_token == unsubscribe_token)
+                    {
+                        subscription.pending.splice(0..0, pending);
+                    }
+                });
+            }
+        }
+
+        self.save_or_log().await;
+    }
+}
+
+/// Checks for due weekly digests every hour
+pub fn spawn_email_digests(app_state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(1.std_hours());
+
+        loop {
+            interval.tick().await;
+
+            app_state.email_notifications.send_due_digests().await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calendar::encode_query::encode_calendar_query;
+    use crate::notifications::smtp::tests::spawn_smtp_sink;
+    use crate::storage::tests::temporary_directory;
+
+    fn activity(id: &str, start: DateTime<Utc>, group: &str) -> Activity {
+        Activity {
+            id: id.to_owned(),
+            course_code: "TDT4100".to_owned(),
+            week: 2,
+            start,
+            end: start + Duration::hours(2),
+            title: "Forelesning".to_owned(),
+            summary: String::new(),
+            staff_members: Vec::new(),
+            student_groups: vec![group.to_owned()],
+            rooms: Vec::new(),
+        }
+    }
+
+    fn identifier() -> CourseIdentifier {
+        CourseIdentifier {
+            course_code: "TDT4100".to_owned(),
+            course_term: 1,
+            semester: "23v".to_owned(),
+        }
+    }
+
+    fn token(message: &str, action: &str) -> String {
+        // Undoes the soft line breaks and escaped equals signs of quoted-printable bodies
+        let message = message.replace("=\r\n", "").replace("=3D", "=");
+        let prefix = format!("/notifications/{action}?token=");
+        let start = message.find(&prefix).unwrap() + prefix.len();
+
+        message[start..]
+            .chars()
+            .take_while(char::is_ascii_hexdigit)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_double_opt_in_and_alerts() {
+        let (config, sink) = spawn_smtp_sink().await;
+        let directory = temporary_directory("email-notifications");
+        let file = || JsonFile::new(directory.join("email_subscriptions.json"));
+        let notifications = EmailNotifications::new(
+            Some(SmtpClient::new(config.clone()).unwrap()),
+            "http://localhost".to_owned(),
+            file(),
+        );
+
+        let query = encode_calendar_query(&[CalendarQuery {
+            identifier: identifier(),
+            student_groups: vec!["MTDT_1".to_owned()],
+            custom_name: None,
+            include_exams: false,
+        }])
+        .unwrap();
+
+        let request = |email: &str| EmailNotificationRequest {
+            email: email.to_owned(),
+            query: query.clone(),
+        };
+        notifications
+            .register(request("student@example.com"), None)
+            .await
+            .unwrap();
+
+        // No second email while the first link is still valid
+        assert!(matches!(
+            notifications
+                .register(request("Student@example.com"), None)
+                .await,
+            Err(AppError::InvalidInput(_))
+        ));
+
+        let now = Utc::now();
+        let changes = CourseChanges {
+            identifier: identifier(),
+            detected_at: now,
+            changes: ActivityChanges {
+                added: vec![
+                    activity("soon", now + Duration::hours(3), "MTDT_1"),
+                    activity("later", now + Duration::days(10), "MTDT_1"),
+                    activity("other", now + Duration::hours(3), "BIT_1"),
+                ],
+                ..Default::default()
+            },
+        };
+
+        // Nothing is sent before the address is confirmed
+        notifications.handle_changes(&changes).await;
+        assert_eq!(sink.lock().unwrap().len(), 1);
+
+        let confirm_token = token(&sink.lock().unwrap()[0], "confirm");
+        assert!(notifications.confirm(&confirm_token).await.unwrap());
+        assert_eq!(notifications.subscribed_courses(), vec![identifier()]);
+
+        // Kept across restarts
+        let notifications = EmailNotifications::new(
+            Some(SmtpClient::new(config).unwrap()),
+            "http://localhost".to_owned(),
+            file(),
+        );
+        assert_eq!(notifications.subscribed_courses(), vec![identifier()]);
+
+        notifications.handle_changes(&changes).await;
+
+        let alert = sink.lock().unwrap()[1].clone();
+        assert_eq!(alert.matches("Added: ").count(), 1);
+        assert!(alert.contains("Subject: Timetable changes in the next 48 hours"));
+
+        let unsubscribe_token = token(&alert, "unsubscribe");
+
+        // Signing up again replaces the subscription once confirmed
+        notifications
+            .register(request("student@example.com"), None)
+            .await
+            .unwrap();
+        let confirm_token = token(&sink.lock().unwrap()[2], "confirm");
+        assert!(notifications.confirm(&confirm_token).await.unwrap());
+        assert_eq!(
+            notifications.with_subscriptions(|subscriptions| subscriptions.len()),
+            1
+        );
+
+        assert!(notifications.unsubscribe(&unsubscribe_token).await.unwrap());
+        assert!(notifications.subscribed_courses().is_empty());
+
+        tokio::fs::remove_dir_all(directory).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_limits_sign_ups_per_client() {
+        let (config, sink) = spawn_smtp_sink().await;
+        let directory = temporary_directory("email-limits");
+        let notifications = EmailNotifications::new(
+            Some(SmtpClient::new(config).unwrap()),
+            "http://localhost".to_owned(),
+            JsonFile::new(directory.join("email_subscriptions.json")),
+        );
+
+        let query = encode_calendar_query(&[CalendarQuery {
+            identifier: identifier(),
+            student_groups: Vec::new(),
+            custom_name: None,
+            include_exams: false,
+        }])
+        .unwrap();
+        let client = Some("203.0.113.7".parse().unwrap());
+
+        for number in 0..=MAX_REGISTRATIONS_PER_CLIENT {
+            let registered = notifications
+                .register(
+                    EmailNotificationRequest {
+                        email: format!("student{number}@example.com"),
+                        query: query.clone(),
+                    },
+                    client,
+                )
+                .await;
+
+            assert_eq!(registered.is_ok(), number < MAX_REGISTRATIONS_PER_CLIENT);
+        }
+
+        assert_eq!(sink.lock().unwrap().len(), MAX_REGISTRATIONS_PER_CLIENT);
+
+        tokio::fs::remove_dir_all(directory).await.unwrap();
+    }
+
+    #[test]
+    fn test_split_urgent() {
+        let now = Utc::now();
+        let changes = ActivityChanges {
+            removed: vec![
+                activity("a", now + Duration::hours(47), "MTDT_1"),
+                activity("b", now + Duration::hours(49), "MTDT_1"),
+            ],
+            ..Default::default()
+        };
+
+        let (urgent, later) = split_urgent(&changes, now);
+
+        assert_eq!(urgent.removed[0].id, "a");
+        assert_eq!(later.removed[0].id, "b");
+        assert_eq!(urgent.removed.len() + later.removed.len(), 2);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod email_notifications;
+pub mod notification_handler;
+pub mod rate_limit;
+pub mod smtp;
@@ -0,0 +1,68 @@
+use crate::error::AppResult;
+use crate::AppState;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::Html;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct TokenQuery {
+    token: String,
+}
+
+/// A button that posts back to the same URL, token included
+///
+/// Links in emails are opened by scanners and previews too, so opening one mustn't change anything
+fn form_page(question: &str, button: &str) -> Html<String> {
+    Html(format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{question}</title></head>\n\
+         <body>\n<form method=\"post\">\n<p>{question}</p>\n<button type=\"submit\">{button}</button>\n\
+         </form>\n</body>\n</html>\n"
+    ))
+}
+
+/// Opened from the link in the confirmation email
+pub async fn confirm_page_handler(_query: Query<TokenQuery>) -> Html<String> {
+    form_page(
+        "Email changes to your timetable to this address?",
+        "Confirm",
+    )
+}
+
+pub async fn confirm_handler(
+    query: Query<TokenQuery>,
+    State(app_state): State<AppState>,
+) -> AppResult<(StatusCode, &'static str)> {
+    if app_state.email_notifications.confirm(&query.token).await? {
+        Ok((
+            StatusCode::OK,
+            "Confirmed. Changes to your timetable will now be emailed to you.",
+        ))
+    } else {
+        Ok((
+            StatusCode::NOT_FOUND,
+            "This link has expired. Please sign up for notifications again.",
+        ))
+    }
+}
+
+/// Opened from the link in every notification email
+pub async fn unsubscribe_page_handler(_query: Query<TokenQuery>) -> Html<String> {
+    form_page("Stop emailing changes to your timetable?", "Unsubscribe")
+}
+
+/// Posted from the unsubscribe page, or by mail clients as the one-click unsubscribe of RFC 8058
+pub async fn unsubscribe_handler(
+    query: Query<TokenQuery>,
+    State(app_state): State<AppState>,
+) -> AppResult<(StatusCode, &'static str)> {
+    if app_state
+        .email_notifications
+        .unsubscribe(&query.token)
+        .await?
+    {
+        Ok((StatusCode::OK, "Unsubscribed. No more emails will be sent."))
+    } else {
+        Ok((StatusCode::NOT_FOUND, "Already unsubscribed."))
+    }
+}
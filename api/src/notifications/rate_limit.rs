@@ -0,0 +1,62 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Allows every key a number of attempts within a sliding window
+pub struct RateLimiter {
+    limit: usize,
+    window: Duration,
+    attempts: Mutex<HashMap<String, Vec<DateTime<Utc>>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: usize, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            attempts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records an attempt, unless the key has used up its attempts for the window
+    pub fn try_acquire(&self, key: &str, now: DateTime<Utc>) -> bool {
+        let mut attempts = self
+            .attempts
+            .lock()
+            .expect("Rate limiter lock should not be poisoned");
+
+        // Keys without recent attempts are dropped, so the map doesn't grow forever
+        attempts.retain(|_, times| {
+            times.retain(|time| now - *time < self.window);
+            !times.is_empty()
+        });
+
+        let times = attempts.entry(key.to_owned()).or_default();
+
+        if times.len() >= self.limit {
+            return false;
+        }
+
+        times.push(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_acquire() {
+        let limiter = RateLimiter::new(2, Duration::hours(1));
+        let now = Utc::now();
+
+        assert!(limiter.try_acquire("a", now));
+        assert!(limiter.try_acquire("a", now));
+        assert!(!limiter.try_acquire("a", now));
+        assert!(limiter.try_acquire("b", now));
+
+        // Attempts older than the window no longer count
+        assert!(limiter.try_acquire("a", now + Duration::minutes(61)));
+    }
+}
@@ -0,0 +1,265 @@
+use crate::error::{AppError, AppResult};
+use lettre::message::header::{ContentType, HeaderName, HeaderValue};
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::env;
+use std::fmt::Display;
+use std::time::Duration;
+use time::ext::NumericalStdDuration;
+use tracing::error;
+
+/// Limit for every command sent to the server
+fn command_timeout() -> Duration {
+    30.std_seconds()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpSecurity {
+    /// Plain text, only meant for local relays and test sinks
+    None,
+    StartTls,
+    /// TLS from the start, usually on port 465
+    Tls,
+}
+
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender address, optionally with a name like `Timeplan <noreply@example.com>`
+    pub from: String,
+    pub security: SmtpSecurity,
+}
+
+impl SmtpConfig {
+    /// Read from the `SMTP_*` variables, where email is disabled without `SMTP_HOST`
+    pub fn from_env() -> Option<Self> {
+        let host = env::var("SMTP_HOST").ok()?;
+
+        let security = match env::var("SMTP_SECURITY").as_deref() {
+            Ok("none") => SmtpSecurity::None,
+            Ok("tls") => SmtpSecurity::Tls,
+            _ => SmtpSecurity::StartTls,
+        };
+
+        let default_port = match security {
+            SmtpSecurity::None => 25,
+            SmtpSecurity::StartTls => 587,
+            SmtpSecurity::Tls => 465,
+        };
+
+        Some(Self {
+            port: env::var("SMTP_PORT")
+                .ok()
+                .and_then(|port| port.parse().ok())
+                .unwrap_or(default_port),
+            username: env::var("SMTP_USERNAME").ok(),
+            password: env::var("SMTP_PASSWORD").ok(),
+            from: env::var("SMTP_FROM").unwrap_or_else(|_| format!("noreply@{host}")),
+            host,
+            security,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EmailMessage {
+    pub to: String,
+    pub subject: String,
+    /// Plain text, with lines separated by `\n`
+    pub body: String,
+    pub unsubscribe_url: Option<String>,
+}
+
+fn smtp_error(context: impl Display) -> AppError {
+    error!("SMTP error: {context}");
+
+    AppError::EmailError
+}
+
+/// Sends email over SMTP, reusing connections between messages
+pub struct SmtpClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpClient {
+    pub fn new(config: SmtpConfig) -> AppResult<Self> {
+        let builder = match config.security {
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+            }
+            SmtpSecurity::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+                    .map_err(smtp_error)?
+            }
+            SmtpSecurity::Tls => {
+                AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host).map_err(smtp_error)?
+            }
+        };
+
+        let mut builder = builder.port(config.port).timeout(Some(command_timeout()));
+
+        if let (Some(username), Some(password)) = (config.username, config.password) {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            from: config.from.parse().map_err(smtp_error)?,
+        })
+    }
+
+    fn build_message(&self, message: &EmailMessage) -> AppResult<Message> {
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .to(message.to.parse().map_err(smtp_error)?)
+            .subject(&message.subject)
+            .header(ContentType::TEXT_PLAIN);
+
+        if let Some(unsubscribe_url) = &message.unsubscribe_url {
+            builder = builder.raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str("List-Unsubscribe"),
+                format!("<{unsubscribe_url}>"),
+            ));
+            // Lets mail clients unsubscribe with a POST, which link scanners don't send
+            builder = builder.raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str("List-Unsubscribe-Post"),
+                "List-Unsubscribe=One-Click".to_owned(),
+            ));
+        }
+
+        builder.body(message.body.clone()).map_err(smtp_error)
+    }
+
+    pub async fn send(&self, message: &EmailMessage) -> AppResult<()> {
+        let email = self.build_message(message)?;
+
+        self.transport.send(email).await.map_err(smtp_error)?;
+
+        Ok(())
+    }
+}
+
+/// Whether the address is a bare address that can be sent to
+pub fn is_valid_address(address: &str) -> bool {
+    address
+        .parse::<Address>()
+        .is_ok_and(|parsed| parsed.domain().contains('.'))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+
+    pub type Sink = Arc<Mutex<Vec<String>>>;
+
+    async fn serve_sink(stream: TcpStream, sink: Sink) -> std::io::Result<()> {
+        let mut stream = BufReader::new(stream);
+        stream.get_mut().write_all(b"220 sink ready\r\n").await?;
+
+        loop {
+            let mut line = String::new();
+
+            if stream.read_line(&mut line).await? == 0 {
+                return Ok(());
+            }
+
+            let reply: &[u8] = match line.trim_end() {
+                command if command.starts_with("EHLO") => b"250-sink\r\n250 8BITMIME\r\n",
+                "DATA" => {
+                    stream.get_mut().write_all(b"354 go ahead\r\n").await?;
+                    let mut data = String::new();
+
+                    loop {
+                        let mut data_line = String::new();
+                        stream.read_line(&mut data_line).await?;
+
+                        if data_line == ".\r\n" {
+                            break;
+                        }
+
+                        data += &data_line;
+                    }
+
+                    sink.lock().unwrap().push(data);
+
+                    b"250 queued\r\n"
+                }
+                "QUIT" => {
+                    stream.get_mut().write_all(b"221 bye\r\n").await?;
+                    return Ok(());
+                }
+                _ => b"250 OK\r\n",
+            };
+
+            stream.get_mut().write_all(reply).await?;
+        }
+    }
+
+    /// A local stand-in for an SMTP server, keeping the data of every message it receives
+    pub async fn spawn_smtp_sink() -> (SmtpConfig, Sink) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let sink = Sink::default();
+        let server_sink = sink.clone();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_sink(stream, server_sink.clone()));
+            }
+        });
+
+        let config = SmtpConfig {
+            host: "127.0.0.1".to_owned(),
+            port,
+            username: None,
+            password: None,
+            from: "Timeplan <noreply@example.com>".to_owned(),
+            security: SmtpSecurity::None,
+        };
+
+        (config, sink)
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let (config, sink) = spawn_smtp_sink().await;
+
+        SmtpClient::new(config)
+            .unwrap()
+            .send(&EmailMessage {
+                to: "student@example.com".to_owned(),
+                subject: "Endringer i timeplanen for TDT4100 – Objektorientert programmering"
+                    .to_owned(),
+                body: format!("Moved:\n.Forelesning\n{}", "x".repeat(2000)),
+                unsubscribe_url: Some("http://localhost/unsubscribe".to_owned()),
+            })
+            .await
+            .unwrap();
+
+        let sink = sink.lock().unwrap();
+        assert_eq!(sink.len(), 1);
+        assert!(sink[0].contains("To: student@example.com\r\n"));
+        assert!(sink[0].contains("List-Unsubscribe: <http://localhost/unsubscribe>\r\n"));
+        assert!(sink[0].contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n"));
+
+        // Headers are encoded as RFC 2047 requires, and no line is longer than SMTP allows
+        assert!(sink[0].contains("Subject: Endringer i timeplanen for TDT4100 =?utf-8?"));
+        assert!(sink[0].is_ascii());
+        assert!(sink[0].lines().all(|line| line.len() <= 998));
+    }
+
+    #[test]
+    fn test_valid_address() {
+        assert!(is_valid_address("student@stud.ntnu.no"));
+        assert!(!is_valid_address("student@ntnu"));
+        assert!(!is_valid_address("a@b.no>\r\nBcc: c@d.no"));
+    }
+}
@@ -1,5 +1,25 @@
-use crate::calendar::encode_query::encode_calendar_query;
-use crate::shared_types::{CalendarQuery, CourseIdentifier, CoursesQuery};
+use crate::calendar::encode_query::{
+    decode_calendar_query, encode_calendar_query, encode_calendar_subscriptions,
+};
+use crate::calendar::filter_activities::overlaps_range;
+use crate::changes::change_feed::{selection_change_stream, selection_courses};
+use crate::common_free_slots::find_common_free_slots;
+use crate::conflicts::find_timetable_conflicts;
+use crate::free_busy::free_busy;
+use crate::free_rooms::find_free_rooms;
+use crate::group_optimizer::find_group_selections;
+use crate::shared_types::{
+    CalendarQuery, CalendarSubscription, CommonFreeSlotsQuery, CourseIdentifier, CourseSearchQuery,
+    CoursesQuery, EmailNotificationRequest, ExamIdentifier, FreeBusyQuery, FreeRoomsQuery,
+    GroupOptimizerQuery, ProgrammeActivitiesQuery, ProgrammeIdentifier, RoomActivitiesQuery,
+    SnapshotDiffQuery, StaffIdentifier, StaffSearchQuery, WebhookSubscriptionRequest,
+    WebhookUnsubscribeRequest, WeekViewQuery,
+};
+use crate::staff::{search_staff, staff_activities};
+use crate::student_groups::{
+    activity_matches_programme, student_group_hierarchy, summarize_student_groups,
+};
+use crate::week_view::week_views;
 use crate::AppState;
 use std::ops::Deref;
 
@@ -22,6 +42,19 @@ pub fn rspc_router() -> rspc::Router<AppState> {
                 Ok(courses.deref().clone())
             })
         })
+        .query("search-courses", |t| {
+            t(|app_state: AppState, query: CourseSearchQuery| async move {
+                const DEFAULT_LIMIT: u32 = 20;
+                const MAX_LIMIT: u32 = 100;
+
+                let courses_cache = &app_state.courses_cache;
+                let index = courses_cache.get_or_fetch_index(query.semester).await?;
+
+                let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+
+                Ok(index.search(&query.query, limit as usize))
+            })
+        })
         .query("activities", |t| {
             t(
                 |app_state: AppState, course_identifier: CourseIdentifier| async move {
@@ -33,6 +66,176 @@ pub fn rspc_router() -> rspc::Router<AppState> {
                 },
             )
         })
+        .query("student-groups", |t| {
+            t(
+                |app_state: AppState, course_identifier: CourseIdentifier| async move {
+                    let activities_cache = &app_state.activities_cache;
+
+                    let activities = activities_cache.get_or_fetch(course_identifier).await?;
+
+                    Ok(summarize_student_groups(&activities))
+                },
+            )
+        })
+        .query("student-group-hierarchy", |t| {
+            t(
+                |app_state: AppState, course_identifier: CourseIdentifier| async move {
+                    let activities_cache = &app_state.activities_cache;
+
+                    let activities = activities_cache.get_or_fetch(course_identifier).await?;
+
+                    Ok(student_group_hierarchy(&activities))
+                },
+            )
+        })
+        .query("programme-activities", |t| {
+            t(
+                |app_state: AppState, query: ProgrammeActivitiesQuery| async move {
+                    let activities_cache = &app_state.activities_cache;
+
+                    let activities = activities_cache.get_or_fetch(query.identifier).await?;
+
+                    let programme_activities = activities
+                        .iter()
+                        .filter(|activity| {
+                            activity_matches_programme(activity, &query.programme, query.year)
+                        })
+                        .cloned()
+                        .collect::<Vec<_>>();
+
+                    Ok(programme_activities)
+                },
+            )
+        })
+        .query("programme-timetable", |t| {
+            t(
+                |app_state: AppState, programme_identifier: ProgrammeIdentifier| async move {
+                    let programme_activities_cache = &app_state.programme_activities_cache;
+
+                    let activities = programme_activities_cache
+                        .get_or_fetch(programme_identifier)
+                        .await?;
+
+                    Ok(activities.deref().clone())
+                },
+            )
+        })
+        .query("room-activities", |t| {
+            t(
+                |app_state: AppState, query: RoomActivitiesQuery| async move {
+                    let room_activities_cache = &app_state.room_activities_cache;
+
+                    let activities = room_activities_cache.get_or_fetch(query.identifier).await?;
+
+                    let activities_in_range = activities
+                        .iter()
+                        .filter(|activity| overlaps_range(activity, query.from, query.to))
+                        .cloned()
+                        .collect::<Vec<_>>();
+
+                    Ok(activities_in_range)
+                },
+            )
+        })
+        .query("free-rooms", |t| {
+            t(|app_state: AppState, query: FreeRoomsQuery| async move {
+                let free_rooms = find_free_rooms(&app_state, query).await?;
+
+                Ok(free_rooms)
+            })
+        })
+        .query("free-busy", |t| {
+            t(|app_state: AppState, query: FreeBusyQuery| async move {
+                let free_busy = free_busy(&app_state, query).await?;
+
+                Ok(free_busy)
+            })
+        })
+        .query("common-free-slots", |t| {
+            t(
+                |app_state: AppState, query: CommonFreeSlotsQuery| async move {
+                    let slots = find_common_free_slots(&app_state, query).await?;
+
+                    Ok(slots)
+                },
+            )
+        })
+        .query("search-staff", |t| {
+            t(|app_state: AppState, query: StaffSearchQuery| async move {
+                const DEFAULT_LIMIT: u32 = 20;
+                const MAX_LIMIT: u32 = 100;
+
+                let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+                let staff_members =
+                    search_staff(&app_state, &query.semester, &query.query, limit as usize);
+
+                Ok(staff_members)
+            })
+        })
+        .query("staff-activities", |t| {
+            t(
+                |app_state: AppState, staff_identifier: StaffIdentifier| async move {
+                    let activities = staff_activities(&app_state, &staff_identifier).await?;
+
+                    Ok(activities)
+                },
+            )
+        })
+        .query("exams", |t| {
+            t(
+                |app_state: AppState, exam_identifier: ExamIdentifier| async move {
+                    let exams_cache = &app_state.exams_cache;
+
+                    let exams = exams_cache.get_or_fetch(exam_identifier).await?;
+
+                    Ok(exams.deref().clone())
+                },
+            )
+        })
+        .query("timetable-conflicts", |t| {
+            t(
+                |app_state: AppState, input: Vec<CalendarQuery>| async move {
+                    let conflicts = find_timetable_conflicts(&app_state, input).await?;
+
+                    Ok(conflicts)
+                },
+            )
+        })
+        .query("optimize-groups", |t| {
+            t(
+                |app_state: AppState, query: GroupOptimizerQuery| async move {
+                    let selections = find_group_selections(&app_state, query).await?;
+
+                    Ok(selections)
+                },
+            )
+        })
+        .query("week-view", |t| {
+            t(|app_state: AppState, query: WeekViewQuery| async move {
+                let weeks = week_views(&app_state, query).await?;
+
+                Ok(weeks)
+            })
+        })
+        .query("course-snapshots", |t| {
+            t(
+                |app_state: AppState, identifier: CourseIdentifier| async move {
+                    let snapshots = app_state.snapshot_store.list(&identifier).await?;
+
+                    Ok(snapshots)
+                },
+            )
+        })
+        .query("diff-snapshots", |t| {
+            t(|app_state: AppState, query: SnapshotDiffQuery| async move {
+                let changes = app_state
+                    .snapshot_store
+                    .diff(&query.identifier, query.from, query.to)
+                    .await?;
+
+                Ok(changes)
+            })
+        })
         .query("encode-calendar-query", |t| {
             t(|_, input: Vec<CalendarQuery>| async move {
                 let encoded_query = encode_calendar_query(&input)?;
@@ -40,6 +243,65 @@ pub fn rspc_router() -> rspc::Router<AppState> {
                 Ok(encoded_query)
             })
         })
+        .query("encode-calendar-subscriptions", |t| {
+            t(|_, input: Vec<CalendarSubscription>| async move {
+                let encoded_query = encode_calendar_subscriptions(&input)?;
+
+                Ok(encoded_query)
+            })
+        })
+        .query("decode-calendar-query", |t| {
+            t(|_, input: String| async move {
+                let subscriptions = decode_calendar_query(&input)?;
+
+                Ok(subscriptions)
+            })
+        })
+        .mutation("register-webhook", |t| {
+            t(
+                |app_state: AppState, request: WebhookSubscriptionRequest| async move {
+                    let registration = app_state
+                        .webhook_registry
+                        .register(
+                            request,
+                            app_state.client_address,
+                            &app_state.activities_cache,
+                        )
+                        .await?;
+
+                    Ok(registration)
+                },
+            )
+        })
+        .mutation("unregister-webhook", |t| {
+            t(
+                |app_state: AppState, request: WebhookUnsubscribeRequest| async move {
+                    let removed = app_state.webhook_registry.unregister(request).await?;
+
+                    Ok(removed)
+                },
+            )
+        })
+        .mutation("register-email-notifications", |t| {
+            t(
+                |app_state: AppState, request: EmailNotificationRequest| async move {
+                    app_state
+                        .email_notifications
+                        .register(request, app_state.client_address)
+                        .await?;
+
+                    Ok(())
+                },
+            )
+        })
+        .subscription("selection-changes", |t| {
+            t(|app_state: AppState, query: String| {
+                // An invalid query just never receives anything
+                let courses = selection_courses(&query).unwrap_or_default();
+
+                selection_change_stream(&app_state.change_tracker, courses)
+            })
+        })
         .build();
 
     router
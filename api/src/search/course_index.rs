@@ -0,0 +1,199 @@
+use crate::search::normalize::{edit_distance, normalize};
+use crate::shared_types::{Course, CourseSearchResult};
+use std::collections::HashMap;
+
+#[derive(Debug)]
+struct IndexEntry {
+    code: String,
+    normalized_code: String,
+    name_words: Vec<String>,
+    course: Course,
+}
+
+/// Search index over the courses of one semester, built when the courses are fetched
+#[derive(Debug)]
+pub struct CourseIndex {
+    entries: Vec<IndexEntry>,
+}
+
+impl CourseIndex {
+    pub fn new(courses: &HashMap<String, Course>) -> Self {
+        let entries = courses
+            .iter()
+            .map(|(code, course)| IndexEntry {
+                code: code.clone(),
+                normalized_code: normalize(code),
+                name_words: normalize(&course.name)
+                    .split_whitespace()
+                    .map(str::to_owned)
+                    .collect(),
+                course: course.clone(),
+            })
+            .collect();
+
+        Self { entries }
+    }
+
+    pub fn search(&self, query: &str, limit: usize) -> Vec<CourseSearchResult> {
+        let normalized_query = normalize(query);
+        let query_words = normalized_query.split_whitespace().collect::<Vec<_>>();
+
+        if query_words.is_empty() {
+            return Vec::new();
+        }
+
+        let mut scored = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                let code_score = score_code(&entry.normalized_code, &normalized_query);
+                let name_score = score_name(&entry.name_words, &query_words);
+
+                let score = code_score.max(name_score)?;
+                Some((score, entry))
+            })
+            .collect::<Vec<_>>();
+
+        scored.sort_by(|(score_a, entry_a), (score_b, entry_b)| {
+            score_b
+                .cmp(score_a)
+                .then_with(|| entry_a.code.cmp(&entry_b.code))
+        });
+
+        scored
+            .into_iter()
+            .take(limit)
+            .map(|(_, entry)| CourseSearchResult {
+                code: entry.code.clone(),
+                course: entry.course.clone(),
+            })
+            .collect()
+    }
+}
+
+fn score_code(normalized_code: &str, normalized_query: &str) -> Option<u32> {
+    let query = normalized_query.replace(' ', "");
+
+    if normalized_code == query {
+        Some(1000)
+    } else if normalized_code.starts_with(&query) {
+        // Prefer codes closer in length to the query
+        let remaining = (normalized_code.len() - query.len()) as u32;
+        Some(800u32.saturating_sub(remaining))
+    } else {
+        None
+    }
+}
+
+/// Every query word has to match one of the name words, scored by how close the match is
+fn score_name(name_words: &[String], query_words: &[&str]) -> Option<u32> {
+    query_words
+        .iter()
+        .map(|query_word| {
+            name_words
+                .iter()
+                .enumerate()
+                .filter_map(|(position, name_word)| {
+                    let word_score = score_word(name_word, query_word)?;
+
+                    // Matches early in the name are slightly more relevant
+                    Some(word_score.saturating_sub(position as u32))
+                })
+                .max()
+        })
+        .sum()
+}
+
+fn score_word(name_word: &str, query_word: &str) -> Option<u32> {
+    if name_word == query_word {
+        return Some(100);
+    }
+
+    if name_word.starts_with(query_word) {
+        return Some(80);
+    }
+
+    if query_word.len() >= 3 && name_word.contains(query_word) {
+        return Some(50);
+    }
+
+    let allowed_distance = match query_word.chars().count() {
+        0..=3 => return None,
+        4..=7 => 1,
+        _ => 2,
+    };
+
+    // Compare against the prefix of the same length, so partially typed words can be misspelled
+    let prefix_length = query_word.chars().count().min(name_word.chars().count());
+    let name_prefix = name_word.chars().take(prefix_length).collect::<String>();
+    let distance = edit_distance(&name_prefix, query_word);
+
+    (distance <= allowed_distance).then(|| 40 - 10 * distance as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> CourseIndex {
+        let courses = [
+            ("TDT4100", "Objektorientert programmering"),
+            ("TDT4120", "Algoritmer og datastrukturer"),
+            ("TMA4100", "Matematikk 1"),
+            ("TMA4105", "Matematikk 2"),
+            (
+                "EXPH0300",
+                "Examen philosophicum for naturvitenskap og teknologi",
+            ),
+            ("HMS0002", "HMS-kurs for 1. årsstudenter"),
+            ("FI1001", "Innføring i økologi og miljø"),
+        ];
+
+        let courses = courses
+            .into_iter()
+            .map(|(code, name)| {
+                let course = Course {
+                    name: name.to_owned(),
+                    amount_of_terms: 1,
+                };
+
+                (code.to_owned(), course)
+            })
+            .collect();
+
+        CourseIndex::new(&courses)
+    }
+
+    fn search_codes(query: &str) -> Vec<String> {
+        index()
+            .search(query, 10)
+            .into_iter()
+            .map(|result| result.code)
+            .collect()
+    }
+
+    #[test]
+    fn test_code_prefix() {
+        assert_eq!(search_codes("tdt41"), vec!["TDT4100", "TDT4120"]);
+        assert_eq!(search_codes("TMA4105"), vec!["TMA4105"]);
+    }
+
+    #[test]
+    fn test_diacritic_insensitive() {
+        assert_eq!(search_codes("okologi"), vec!["FI1001"]);
+        assert_eq!(search_codes("arsstudenter"), vec!["HMS0002"]);
+        assert_eq!(search_codes("Økologi miljø"), vec!["FI1001"]);
+    }
+
+    #[test]
+    fn test_fuzzy_name() {
+        assert_eq!(search_codes("algoritmr"), vec!["TDT4120"]);
+        assert_eq!(search_codes("matematik"), vec!["TMA4100", "TMA4105"]);
+    }
+
+    #[test]
+    fn test_limit() {
+        assert_eq!(index().search("matematikk", 1).len(), 1);
+        assert!(index().search("   ", 10).is_empty());
+    }
+}
@@ -0,0 +1,2 @@
+pub mod course_index;
+pub mod normalize;
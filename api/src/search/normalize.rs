@@ -0,0 +1,41 @@
+/// Lowercases and folds Norwegian and other diacritics, so "Økologi" matches "okologi"
+pub fn normalize(input: &str) -> String {
+    let mut normalized = String::with_capacity(input.len());
+
+    for character in input.chars().flat_map(char::to_lowercase) {
+        match character {
+            'æ' => normalized.push_str("ae"),
+            'ø' | 'ö' | 'ó' | 'ò' | 'ô' => normalized.push('o'),
+            'å' | 'ä' | 'á' | 'à' | 'â' => normalized.push('a'),
+            'é' | 'è' | 'ê' | 'ë' => normalized.push('e'),
+            'ü' | 'ú' | 'ù' | 'û' => normalized.push('u'),
+            'í' | 'ì' | 'î' | 'ï' => normalized.push('i'),
+            character if character.is_alphanumeric() => normalized.push(character),
+            _ => normalized.push(' '),
+        }
+    }
+
+    normalized
+}
+
+/// Levenshtein distance between two strings
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous_row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current_row = vec![i + 1; b.len() + 1];
+
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution_cost = usize::from(a_char != *b_char);
+
+            current_row[j + 1] = (previous_row[j] + substitution_cost)
+                .min(previous_row[j + 1] + 1)
+                .min(current_row[j] + 1);
+        }
+
+        previous_row = current_row;
+    }
+
+    previous_row[b.len()]
+}
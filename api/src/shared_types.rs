@@ -1,24 +1,79 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
 use rspc::internal::specta;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(specta::Type, Serialize, Debug, Clone)]
+#[derive(specta::Type, Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Room {
+    /// Upstream room id, used to look up the timetable of the room
+    pub id: Option<String>,
     pub name: String,
     pub building_name: String,
     pub url: String,
 }
 
+#[derive(specta::Type, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeSlot {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
 #[derive(specta::Type, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
+pub struct FreeRoom {
+    pub room: Room,
+    pub free_slots: Vec<TimeSlot>,
+}
+
+#[derive(specta::Type, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ConflictingActivity {
+    pub course: CourseIdentifier,
+    pub activity_id: String,
+    pub title: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+#[derive(specta::Type, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityConflict {
+    pub first: ConflictingActivity,
+    pub second: ConflictingActivity,
+    pub week: i32,
+    pub overlap: TimeSlot,
+}
+
+#[derive(specta::Type, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CourseConflictShare {
+    pub course: CourseIdentifier,
+    pub total_minutes: i32,
+    pub conflicting_minutes: i32,
+    /// Share of the course's hours in conflict, between 0 and 1
+    pub share: f64,
+    pub weeks: Vec<i32>,
+}
+
+#[derive(specta::Type, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TimetableConflicts {
+    pub conflicts: Vec<ActivityConflict>,
+    pub courses: Vec<CourseConflictShare>,
+}
+
+#[derive(specta::Type, Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
 pub struct StaffMember {
+    /// Upstream staff id, when educloud includes it
+    pub id: Option<String>,
     pub first_name: String,
     pub last_name: String,
 }
 
-#[derive(specta::Type, Serialize, Debug, Clone)]
+#[derive(specta::Type, Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Activity {
     pub id: String,
@@ -33,6 +88,61 @@ pub struct Activity {
     pub rooms: Vec<Room>,
 }
 
+/// A student group string like `BPROG_2` split into its parts
+#[derive(specta::Type, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StudentGroup {
+    pub raw: String,
+    pub programme: String,
+    pub year: Option<i32>,
+    pub parallel: Option<String>,
+}
+
+#[derive(specta::Type, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct StudentGroupSummary {
+    pub group: StudentGroup,
+    pub activity_count: u32,
+    /// Distinct activity titles, e.g. "Forelesning" or "Øving"
+    pub activity_kinds: Vec<String>,
+    pub weeks: Vec<i32>,
+}
+
+#[derive(specta::Type, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgrammeYearGroups {
+    pub year: Option<i32>,
+    pub groups: Vec<StudentGroupSummary>,
+}
+
+#[derive(specta::Type, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgrammeGroups {
+    pub programme: String,
+    pub years: Vec<ProgrammeYearGroups>,
+}
+
+#[derive(specta::Type, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Exam {
+    pub id: String,
+    pub course_code: String,
+    /// E.g. "Skriftlig skoleeksamen"
+    pub assessment_form: String,
+    pub date: NaiveDate,
+    /// Missing until the exam has been given a time
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    pub location: Option<String>,
+}
+
+#[derive(specta::Type, Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct ExamIdentifier {
+    pub course_code: String,
+    pub semester: String,
+}
+
 #[derive(specta::Type, Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Course {
@@ -40,6 +150,13 @@ pub struct Course {
     pub amount_of_terms: i32,
 }
 
+#[derive(specta::Type, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CourseSearchResult {
+    pub code: String,
+    pub course: Course,
+}
+
 #[derive(specta::Type, Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(rename_all = "camelCase")]
 pub struct CourseIdentifier {
@@ -67,6 +184,80 @@ pub struct CalendarQuery {
     pub identifier: CourseIdentifier,
     pub student_groups: Vec<String>,
     pub custom_name: Option<String>,
+    /// Missing in queries encoded before exams could be included
+    #[serde(default)]
+    pub include_exams: bool,
+}
+
+/// A study programme and year, e.g. BPROG year 2, which has a timetable across all its courses
+#[derive(specta::Type, Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgrammeIdentifier {
+    pub programme: String,
+    pub year: i32,
+    pub semester: String,
+}
+
+#[derive(specta::Type, Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgrammeCalendarQuery {
+    pub identifier: ProgrammeIdentifier,
+    pub custom_name: Option<String>,
+}
+
+#[derive(specta::Type, Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomIdentifier {
+    pub room_id: String,
+    pub semester: String,
+}
+
+#[derive(specta::Type, Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct StaffIdentifier {
+    pub staff_id: String,
+    pub semester: String,
+}
+
+/// When a custom event takes place, with times in Norwegian local time
+#[derive(specta::Type, Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum CustomEventSchedule {
+    /// Every `interval_weeks` week on the ISO weekday, from 1 for Monday, within the dates
+    Weekly {
+        weekday: u32,
+        first_date: NaiveDate,
+        last_date: NaiveDate,
+        interval_weeks: u32,
+    },
+    Dates {
+        dates: Vec<NaiveDate>,
+    },
+}
+
+/// A user-defined event, like a study group session, kept in the encoded query
+#[derive(specta::Type, Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomEvent {
+    /// Chosen by the client and kept stable, as it is part of the event UIDs
+    pub id: String,
+    pub title: String,
+    pub location: Option<String>,
+    pub description: Option<String>,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+    pub schedule: CustomEventSchedule,
+}
+
+/// One entry in an encoded calendar query
+#[derive(specta::Type, Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum CalendarSubscription {
+    Course(CalendarQuery),
+    Programme(ProgrammeCalendarQuery),
+    Room(RoomIdentifier),
+    Staff(StaffIdentifier),
+    Custom(CustomEvent),
 }
 
 #[derive(specta::Type, Deserialize, Serialize, Debug, Clone, PartialEq)]
@@ -81,3 +272,266 @@ pub struct OldCalendarQuery {
 pub struct CoursesQuery {
     pub semester: String,
 }
+
+#[derive(specta::Type, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CourseSearchQuery {
+    pub semester: String,
+    pub query: String,
+    pub limit: Option<u32>,
+}
+
+#[derive(specta::Type, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgrammeActivitiesQuery {
+    pub identifier: CourseIdentifier,
+    pub programme: String,
+    pub year: Option<i32>,
+}
+
+#[derive(specta::Type, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomActivitiesQuery {
+    pub identifier: RoomIdentifier,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// There is no upstream list of rooms, so only rooms seen in timetables cached for the semester
+/// are considered
+#[derive(specta::Type, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FreeRoomsQuery {
+    pub semester: String,
+    /// Matched against the building name of rooms, ignoring case and diacritics
+    pub building: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Defaults to the whole window having to be free
+    pub min_duration_minutes: Option<u32>,
+}
+
+#[derive(specta::Type, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StaffSearchQuery {
+    pub semester: String,
+    pub query: String,
+    pub limit: Option<u32>,
+}
+
+#[derive(specta::Type, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OptimizerCourse {
+    pub identifier: CourseIdentifier,
+    pub custom_name: Option<String>,
+    /// Groups the student always belongs to, e.g. their programme and year
+    pub base_groups: Vec<String>,
+}
+
+#[derive(specta::Type, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupOptimizerQuery {
+    pub courses: Vec<OptimizerCourse>,
+    pub minimize_days_on_campus: bool,
+    /// Activities starting before this local hour are avoided
+    pub earliest_start_hour: Option<u32>,
+    pub max_alternatives: Option<u32>,
+}
+
+#[derive(specta::Type, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupSelection {
+    /// Ready to be passed to `encode-calendar-query`
+    pub queries: Vec<CalendarQuery>,
+    pub conflicting_minutes: i32,
+    pub days_on_campus: i32,
+    pub early_activities: i32,
+}
+
+#[derive(specta::Type, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupOptimization {
+    /// Best first
+    pub selections: Vec<GroupSelection>,
+    /// False when the search hit its limit, so better selections may have been missed
+    pub exhaustive: bool,
+}
+
+#[derive(specta::Type, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WeekViewQuery {
+    pub queries: Vec<CalendarQuery>,
+    /// ISO week year and number of the first week
+    pub year: i32,
+    pub week: u32,
+    pub week_count: Option<u32>,
+}
+
+#[derive(specta::Type, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PlacedActivity {
+    /// Custom name of the course, or its code
+    pub name: String,
+    pub activity: Activity,
+    /// Column of the activity among the activities it overlaps, starting at 0
+    pub column: u32,
+    pub column_count: u32,
+}
+
+#[derive(specta::Type, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DayView {
+    pub date: NaiveDate,
+    pub activities: Vec<PlacedActivity>,
+    /// Hours with at least one activity
+    pub hours: f64,
+    pub first_start: Option<DateTime<Utc>>,
+    pub last_end: Option<DateTime<Utc>>,
+}
+
+#[derive(specta::Type, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WeekView {
+    pub year: i32,
+    pub week: u32,
+    pub days: Vec<DayView>,
+}
+
+#[derive(specta::Type, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FreeBusyQuery {
+    /// Encoded calendar queries, typically one per person
+    pub queries: Vec<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// Leaves out whose calendar each busy interval comes from
+    #[serde(default)]
+    pub anonymise: bool,
+}
+
+#[derive(specta::Type, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BusyInterval {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Indices of the busy calendars in the query, empty when anonymised
+    pub busy_calendars: Vec<u32>,
+}
+
+#[derive(specta::Type, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FreeBusy {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// Names of the calendars in the query, empty when anonymised
+    pub calendars: Vec<String>,
+    pub busy: Vec<BusyInterval>,
+}
+
+#[derive(specta::Type, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CommonFreeSlotsQuery {
+    /// Encoded calendar queries, one per student
+    pub queries: Vec<String>,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// ISO weekday numbers, from 1 for Monday, defaulting to Monday to Friday
+    #[serde(default)]
+    pub weekdays: Vec<u32>,
+    /// Local hours of the day to search within
+    pub earliest_hour: u32,
+    pub latest_hour: u32,
+    pub min_duration_minutes: u32,
+    pub limit: Option<u32>,
+}
+
+#[derive(specta::Type, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CommonFreeSlot {
+    /// ISO weekday number, from 1 for Monday
+    pub weekday: u32,
+    /// Local time of day
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub duration_minutes: u32,
+    /// Dates in the window where everyone is free for the whole slot
+    pub free_dates: Vec<NaiveDate>,
+    /// How many times the weekday occurs in the window
+    pub total_weeks: u32,
+}
+
+#[derive(specta::Type, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MovedActivity {
+    pub before: Activity,
+    pub after: Activity,
+}
+
+/// Difference between two fetches of the same timetable
+#[derive(specta::Type, Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityChanges {
+    pub added: Vec<Activity>,
+    pub removed: Vec<Activity>,
+    /// Activities given another time or room
+    pub moved: Vec<MovedActivity>,
+}
+
+#[derive(specta::Type, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CourseChanges {
+    pub identifier: CourseIdentifier,
+    pub detected_at: DateTime<Utc>,
+    pub changes: ActivityChanges,
+}
+
+/// A distinct version of a course timetable, kept from when it was first fetched
+#[derive(specta::Type, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotSummary {
+    /// Increasing through the history of the course from 0, and kept when older snapshots are dropped
+    pub id: u32,
+    pub fetched_at: DateTime<Utc>,
+    pub activity_count: u32,
+}
+
+#[derive(specta::Type, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotDiffQuery {
+    pub identifier: CourseIdentifier,
+    pub from: u32,
+    pub to: u32,
+}
+
+#[derive(specta::Type, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookSubscriptionRequest {
+    pub identifier: CourseIdentifier,
+    /// Only changes to activities of these groups are sent, or all changes if empty
+    #[serde(default)]
+    pub student_groups: Vec<String>,
+    pub url: String,
+}
+
+#[derive(specta::Type, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookRegistration {
+    pub id: String,
+    /// Key of the HMAC-SHA256 signature sent with every payload, only shown once
+    pub secret: String,
+}
+
+#[derive(specta::Type, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookUnsubscribeRequest {
+    pub id: String,
+    pub secret: String,
+}
+
+#[derive(specta::Type, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailNotificationRequest {
+    pub email: String,
+    /// Encoded calendar query, of which changes to the courses are sent
+    pub query: String,
+}
@@ -0,0 +1,334 @@
+use crate::error::AppResult;
+use crate::search::normalize::normalize;
+use crate::shared_types::{Activity, CourseIdentifier, StaffIdentifier, StaffMember};
+use crate::storage::JsonFile;
+use crate::AppState;
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, warn};
+
+/// Courses fetched at the same time for a staff calendar
+const MAX_CONCURRENT_FETCHES: usize = 8;
+
+pub fn teaches(activity: &Activity, staff_id: &str) -> bool {
+    activity
+        .staff_members
+        .iter()
+        .any(|staff_member| staff_member.id.as_deref() == Some(staff_id))
+}
+
+/// Whether every word of the query starts a word of the name
+fn name_matches(staff_member: &StaffMember, query_words: &[&str]) -> bool {
+    let name = normalize(&format!(
+        "{} {}",
+        staff_member.first_name, staff_member.last_name
+    ));
+    let name_words = name.split_whitespace().collect::<Vec<_>>();
+
+    query_words.iter().all(|query_word| {
+        name_words
+            .iter()
+            .any(|name_word| name_word.starts_with(query_word))
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct IndexedStaffMember {
+    staff_member: StaffMember,
+    courses: Vec<CourseIdentifier>,
+}
+
+/// Staff members by semester and id
+type StaffBySemester = HashMap<String, HashMap<String, IndexedStaffMember>>;
+
+/// Which courses every staff member teaches, as staff have no timetable of their own upstream
+///
+/// Built from every course timetable fetched, and kept across restarts
+pub struct StaffIndex {
+    semesters: RwLock<StaffBySemester>,
+    file: JsonFile,
+}
+
+impl StaffIndex {
+    pub fn new(file: JsonFile) -> Self {
+        Self {
+            semesters: RwLock::new(file.load_or_default()),
+            file,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(JsonFile::in_data_directory("staff_index.json"))
+    }
+
+    /// Updates who teaches the course, returning whether anything changed
+    fn update_course(&self, identifier: &CourseIdentifier, activities: &[Activity]) -> bool {
+        let teachers = activities
+            .iter()
+            .flat_map(|activity| &activity.staff_members)
+            .filter_map(|staff_member| Some((staff_member.id.clone()?, staff_member)))
+            .collect::<HashMap<_, _>>();
+
+        let mut semesters = self
+            .semesters
+            .write()
+            .expect("Staff index lock should not be poisoned");
+        let staff = semesters.entry(identifier.semester.clone()).or_default();
+
+        let mut changed = false;
+
+        for (id, staff_member) in &teachers {
+            let indexed = staff
+                .entry(id.clone())
+                .or_insert_with(|| IndexedStaffMember {
+                    staff_member: (*staff_member).clone(),
+                    courses: Vec::new(),
+                });
+
+            // Names can be corrected upstream, so the latest fetch wins
+            if indexed.staff_member != **staff_member {
+                indexed.staff_member = (*staff_member).clone();
+                changed = true;
+            }
+
+            if !indexed.courses.contains(identifier) {
+                indexed.courses.push(identifier.clone());
+                changed = true;
+            }
+        }
+
+        // Staff no longer teaching the course
+        for (id, indexed) in staff.iter_mut() {
+            if !teachers.contains_key(id) && indexed.courses.contains(identifier) {
+                indexed.courses.retain(|course| course != identifier);
+                changed = true;
+            }
+        }
+
+        staff.retain(|_, indexed| !indexed.courses.is_empty());
+
+        changed
+    }
+
+    pub async fn record_course(
+        &self,
+        identifier: &CourseIdentifier,
+        activities: &[Activity],
+    ) -> AppResult<()> {
+        if !self.update_course(identifier, activities) {
+            return Ok(());
+        }
+
+        self.file
+            .save(|| {
+                self.semesters
+                    .read()
+                    .expect("Staff index lock should not be poisoned")
+                    .clone()
+            })
+            .await
+    }
+
+    /// Staff members of the semester whose name matches the query, sorted by name
+    pub fn search(&self, semester: &str, query: &str, limit: usize) -> Vec<StaffMember> {
+        let query = normalize(query);
+        let query_words = query.split_whitespace().collect::<Vec<_>>();
+
+        if query_words.is_empty() {
+            return Vec::new();
+        }
+
+        let semesters = self
+            .semesters
+            .read()
+            .expect("Staff index lock should not be poisoned");
+
+        semesters
+            .get(semester)
+            .into_iter()
+            .flat_map(|staff| staff.values())
+            .map(|indexed| &indexed.staff_member)
+            .filter(|staff_member| name_matches(staff_member, &query_words))
+            .sorted_by(|a, b| (&a.last_name, &a.first_name).cmp(&(&b.last_name, &b.first_name)))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    pub fn courses(&self, identifier: &StaffIdentifier) -> Vec<CourseIdentifier> {
+        self.semesters
+            .read()
+            .expect("Staff index lock should not be poisoned")
+            .get(&identifier.semester)
+            .and_then(|staff| staff.get(&identifier.staff_id))
+            .map(|indexed| indexed.courses.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Adds every fetched course timetable to the staff index
+pub fn spawn_staff_indexing(app_state: AppState) {
+    let mut fetches = app_state.activities_cache.subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            let (identifier, activities) = match fetches.recv().await {
+                Ok(fetch) => fetch,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Staff indexing skipped {skipped} timetable fetches");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            if let Err(storage_error) = app_state
+                .staff_index
+                .record_course(&identifier, &activities)
+                .await
+            {
+                error!("Failed to store staff index: {storage_error:?}");
+            }
+        }
+    });
+}
+
+/// Staff members of the semester whose name matches the query
+pub fn search_staff(
+    app_state: &AppState,
+    semester: &str,
+    query: &str,
+    limit: usize,
+) -> Vec<StaffMember> {
+    app_state.staff_index.search(semester, query, limit)
+}
+
+/// Everything taught by the staff member, along with when the newest and the oldest of the courses
+/// were fetched
+pub async fn staff_activities_with_time(
+    app_state: &AppState,
+    identifier: &StaffIdentifier,
+) -> AppResult<(Vec<Activity>, DateTime<Utc>, DateTime<Utc>)> {
+    let courses = app_state.staff_index.courses(identifier);
+
+    let timetables = stream::iter(courses)
+        .map(|course| app_state.activities_cache.get_or_fetch_with_time(course))
+        .buffer_unordered(MAX_CONCURRENT_FETCHES)
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    let now = Utc::now();
+    let fetch_times = timetables.iter().map(|(_, fetched_at)| *fetched_at);
+    let newest_fetched_at = fetch_times.clone().max().unwrap_or(now);
+    let oldest_fetched_at = fetch_times.min().unwrap_or(now);
+
+    let activities = timetables
+        .iter()
+        .flat_map(|(activities, _)| activities.iter())
+        .filter(|activity| teaches(activity, &identifier.staff_id))
+        .unique_by(|activity| activity.id.clone())
+        .sorted_by_key(|activity| activity.start)
+        .cloned()
+        .collect();
+
+    Ok((activities, newest_fetched_at, oldest_fetched_at))
+}
+
+pub async fn staff_activities(
+    app_state: &AppState,
+    identifier: &StaffIdentifier,
+) -> AppResult<Vec<Activity>> {
+    let (activities, ..) = staff_activities_with_time(app_state, identifier).await?;
+
+    Ok(activities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::temporary_directory;
+    use chrono::{Duration, TimeZone};
+
+    fn activity(staff: &[(&str, &str, &str)]) -> Activity {
+        let start = Utc.with_ymd_and_hms(2023, 1, 10, 8, 15, 0).unwrap();
+
+        Activity {
+            id: "a".to_owned(),
+            course_code: "TDT4100".to_owned(),
+            week: 2,
+            start,
+            end: start + Duration::hours(2),
+            title: "Forelesning".to_owned(),
+            summary: String::new(),
+            staff_members: staff
+                .iter()
+                .map(|(id, first_name, last_name)| StaffMember {
+                    id: Some(id.to_string()),
+                    first_name: first_name.to_string(),
+                    last_name: last_name.to_string(),
+                })
+                .collect(),
+            student_groups: Vec::new(),
+            rooms: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_staff_index() {
+        let directory = temporary_directory("staff-index");
+        let file = || JsonFile::new(directory.join("staff_index.json"));
+
+        let course = CourseIdentifier {
+            course_code: "TDT4100".to_owned(),
+            course_term: 1,
+            semester: "23v".to_owned(),
+        };
+        let staff_identifier = |staff_id: &str| StaffIdentifier {
+            staff_id: staff_id.to_owned(),
+            semester: "23v".to_owned(),
+        };
+
+        let index = StaffIndex::new(file());
+        index
+            .record_course(
+                &course,
+                &[activity(&[
+                    ("1", "Hallvard", "Trætteberg"),
+                    ("2", "Børge", "Haugset"),
+                ])],
+            )
+            .await
+            .unwrap();
+
+        let found = index.search("23v", "hall trae", 10);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id.as_deref(), Some("1"));
+        assert!(index.search("22h", "hall", 10).is_empty());
+
+        // Kept across restarts
+        let index = StaffIndex::new(file());
+        assert_eq!(index.courses(&staff_identifier("2")), vec![course.clone()]);
+
+        // Staff who stop teaching the course are dropped
+        index
+            .record_course(&course, &[activity(&[("1", "Hallvard", "Trætteberg")])])
+            .await
+            .unwrap();
+        assert!(index.courses(&staff_identifier("2")).is_empty());
+        assert_eq!(index.courses(&staff_identifier("1")), vec![course.clone()]);
+
+        // Renamed staff are found by their new name
+        index
+            .record_course(&course, &[activity(&[("1", "Hallvard", "Traetteberg")])])
+            .await
+            .unwrap();
+        assert_eq!(index.search("23v", "hall", 10)[0].last_name, "Traetteberg");
+
+        tokio::fs::remove_dir_all(directory).await.unwrap();
+    }
+}
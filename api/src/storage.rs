@@ -0,0 +1,112 @@
+use crate::error::{AppError, AppResult};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::env;
+use std::fmt::Display;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use tokio::fs;
+use tokio::sync::Mutex;
+use tracing::error;
+
+/// Where state that should survive restarts is kept, `DATA_DIR` or `data` in the working directory
+pub fn data_directory() -> PathBuf {
+    env::var("DATA_DIR")
+        .unwrap_or_else(|_| "data".to_owned())
+        .into()
+}
+
+pub fn storage_error(context: impl Display) -> AppError {
+    error!("Storage error: {context}");
+
+    AppError::StorageError
+}
+
+/// A value persisted as a single JSON file, replaced as a whole on every save
+pub struct JsonFile {
+    path: PathBuf,
+    /// Saves are serialized, so an older value never overwrites a newer one
+    write_lock: Mutex<()>,
+}
+
+impl JsonFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn in_data_directory(name: &str) -> Self {
+        Self::new(data_directory().join(name))
+    }
+
+    /// Reads the value, or the default before anything has been saved
+    ///
+    /// Blocks, as it is meant for loading state at startup
+    pub fn load<T: DeserializeOwned + Default>(&self) -> AppResult<T> {
+        match std::fs::read(&self.path) {
+            Ok(contents) => serde_json::from_slice(&contents).map_err(storage_error),
+            Err(read_error) if read_error.kind() == ErrorKind::NotFound => Ok(T::default()),
+            Err(read_error) => Err(storage_error(read_error)),
+        }
+    }
+
+    /// Like [`JsonFile::load`], but logs errors and starts over from the default
+    pub fn load_or_default<T: DeserializeOwned + Default>(&self) -> T {
+        self.load().unwrap_or_else(|_| {
+            error!("Starting without the data in {:?}", self.path);
+
+            T::default()
+        })
+    }
+
+    /// Saves the value `snapshot` returns, which is called once no other save is in progress
+    pub async fn save<T: Serialize>(&self, snapshot: impl FnOnce() -> T) -> AppResult<()> {
+        let _write_guard = self.write_lock.lock().await;
+
+        let contents = serde_json::to_vec(&snapshot()).map_err(storage_error)?;
+
+        if let Some(directory) = self.path.parent() {
+            fs::create_dir_all(directory).await.map_err(storage_error)?;
+        }
+
+        // Renaming is atomic, so a crash while writing never leaves half a file behind
+        let temporary_path = self.path.with_extension("json.tmp");
+        fs::write(&temporary_path, contents)
+            .await
+            .map_err(storage_error)?;
+        fs::rename(&temporary_path, &self.path)
+            .await
+            .map_err(storage_error)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::changes::webhooks::random_hex;
+    use std::collections::HashMap;
+
+    /// A fresh directory for a test, which the test should remove when done
+    pub fn temporary_directory(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("{name}-{}", random_hex(8).unwrap()))
+    }
+
+    #[tokio::test]
+    async fn test_save_and_load() {
+        let directory = temporary_directory("storage");
+        let file = JsonFile::new(directory.join("values.json"));
+
+        assert!(file.load::<HashMap<String, u32>>().unwrap().is_empty());
+
+        let values = HashMap::from([("a".to_owned(), 1)]);
+        file.save(|| &values).await.unwrap();
+
+        assert_eq!(file.load::<HashMap<String, u32>>().unwrap(), values);
+
+        fs::remove_dir_all(directory).await.unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+use crate::shared_types::{
+    Activity, ProgrammeGroups, ProgrammeYearGroups, StudentGroup, StudentGroupSummary,
+};
+use itertools::Itertools;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Parses group strings like `BPROG_2` or `MTDT_1_ALL` into programme, year and parallel
+pub fn parse_student_group(raw: &str) -> StudentGroup {
+    let mut parts = raw.split('_');
+
+    let programme = parts.next().unwrap_or_default().to_owned();
+    let rest = parts.collect::<Vec<_>>();
+
+    let year = rest.first().and_then(|year| year.parse::<i32>().ok());
+
+    let parallel = match year {
+        Some(_) => rest[1..].join("_"),
+        None => rest.join("_"),
+    };
+
+    StudentGroup {
+        raw: raw.to_owned(),
+        programme,
+        year,
+        parallel: (!parallel.is_empty()).then_some(parallel),
+    }
+}
+
+/// Whether the group belongs to the programme, and the year if one is given
+pub fn group_matches_programme(group: &StudentGroup, programme: &str, year: Option<i32>) -> bool {
+    group.programme.eq_ignore_ascii_case(programme) && (year.is_none() || group.year == year)
+}
+
+pub fn activity_matches_programme(activity: &Activity, programme: &str, year: Option<i32>) -> bool {
+    activity
+        .student_groups
+        .iter()
+        .any(|raw| group_matches_programme(&parse_student_group(raw), programme, year))
+}
+
+#[derive(Default)]
+struct GroupAccumulator {
+    activity_count: u32,
+    activity_kinds: BTreeSet<String>,
+    weeks: BTreeSet<i32>,
+}
+
+/// Distinct student groups of a course, sorted by name
+pub fn summarize_student_groups(activities: &[Activity]) -> Vec<StudentGroupSummary> {
+    let mut groups = BTreeMap::<&str, GroupAccumulator>::new();
+
+    for activity in activities {
+        for student_group in &activity.student_groups {
+            let group = groups.entry(student_group).or_default();
+
+            group.activity_count += 1;
+            group.activity_kinds.insert(activity.title.clone());
+            group.weeks.insert(activity.week);
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(name, group)| StudentGroupSummary {
+            group: parse_student_group(name),
+            activity_count: group.activity_count,
+            activity_kinds: group.activity_kinds.into_iter().collect(),
+            weeks: group.weeks.into_iter().collect(),
+        })
+        .collect()
+}
+
+/// Student groups of a course grouped by programme and then year
+pub fn student_group_hierarchy(activities: &[Activity]) -> Vec<ProgrammeGroups> {
+    let summaries = summarize_student_groups(activities);
+
+    summaries
+        .into_iter()
+        .into_group_map_by(|summary| summary.group.programme.clone())
+        .into_iter()
+        .sorted_by(|(programme_a, _), (programme_b, _)| programme_a.cmp(programme_b))
+        .map(|(programme, summaries)| {
+            let years = summaries
+                .into_iter()
+                .into_group_map_by(|summary| summary.group.year)
+                .into_iter()
+                .sorted_by_key(|(year, _)| *year)
+                .map(|(year, groups)| ProgrammeYearGroups { year, groups })
+                .collect();
+
+            ProgrammeGroups { programme, years }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone, Utc};
+
+    fn activity(title: &str, week: i32, student_groups: &[&str]) -> Activity {
+        let start = Utc.with_ymd_and_hms(2023, 1, 10, 8, 15, 0).unwrap();
+
+        Activity {
+            id: format!("{title}-{week}"),
+            course_code: "TDT4100".to_owned(),
+            week,
+            start,
+            end: start + Duration::hours(2),
+            title: title.to_owned(),
+            summary: String::new(),
+            staff_members: Vec::new(),
+            student_groups: student_groups
+                .iter()
+                .map(|group| group.to_string())
+                .collect(),
+            rooms: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_student_group() {
+        assert_eq!(
+            parse_student_group("BPROG_2"),
+            StudentGroup {
+                raw: "BPROG_2".to_owned(),
+                programme: "BPROG".to_owned(),
+                year: Some(2),
+                parallel: None,
+            }
+        );
+
+        assert_eq!(
+            parse_student_group("MTDT_1_ALL"),
+            StudentGroup {
+                raw: "MTDT_1_ALL".to_owned(),
+                programme: "MTDT".to_owned(),
+                year: Some(1),
+                parallel: Some("ALL".to_owned()),
+            }
+        );
+
+        assert_eq!(
+            parse_student_group("EVU"),
+            StudentGroup {
+                raw: "EVU".to_owned(),
+                programme: "EVU".to_owned(),
+                year: None,
+                parallel: None,
+            }
+        );
+
+        assert_eq!(
+            parse_student_group("MLREAL_VIT"),
+            StudentGroup {
+                raw: "MLREAL_VIT".to_owned(),
+                programme: "MLREAL".to_owned(),
+                year: None,
+                parallel: Some("VIT".to_owned()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_group_matches_programme() {
+        let group = parse_student_group("BPROG_2_A");
+
+        assert!(group_matches_programme(&group, "bprog", None));
+        assert!(group_matches_programme(&group, "BPROG", Some(2)));
+        assert!(!group_matches_programme(&group, "BPROG", Some(1)));
+        assert!(!group_matches_programme(&group, "MTDT", None));
+    }
+
+    #[test]
+    fn test_summarize_student_groups() {
+        let activities = vec![
+            activity("Forelesning", 2, &["MTDT_1", "BIT_1"]),
+            activity("Forelesning", 3, &["MTDT_1"]),
+            activity("Øving", 3, &["MTDT_1"]),
+        ];
+
+        let summaries = summarize_student_groups(&activities);
+
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].group.raw, "BIT_1");
+        assert_eq!(summaries[1].activity_count, 3);
+        assert_eq!(summaries[1].activity_kinds, vec!["Forelesning", "Øving"]);
+        assert_eq!(summaries[1].weeks, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_student_group_hierarchy() {
+        let activities = vec![activity(
+            "Forelesning",
+            2,
+            &["MTDT_2", "MTDT_1_A", "MTDT_1_B", "BIT_1", "EVU"],
+        )];
+
+        let hierarchy = student_group_hierarchy(&activities);
+
+        let programmes = hierarchy
+            .iter()
+            .map(|programme| programme.programme.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(programmes, vec!["BIT", "EVU", "MTDT"]);
+
+        let mtdt = &hierarchy[2];
+        let years = mtdt.years.iter().map(|year| year.year).collect::<Vec<_>>();
+        assert_eq!(years, vec![Some(1), Some(2)]);
+        assert_eq!(mtdt.years[0].groups.len(), 2);
+
+        assert_eq!(hierarchy[1].years[0].year, None);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::shared_types::{Activity, TimeSlot};
+use chrono::{DateTime, Duration, Utc};
+use itertools::Itertools;
+
+/// Merges the time of the activities into sorted, non-overlapping busy slots
+pub fn busy_slots<'a>(activities: impl IntoIterator<Item = &'a Activity>) -> Vec<TimeSlot> {
+    let slots = activities.into_iter().map(|activity| TimeSlot {
+        start: activity.start,
+        end: activity.end,
+    });
+
+    merge_slots(slots)
+}
+
+pub fn merge_slots(slots: impl IntoIterator<Item = TimeSlot>) -> Vec<TimeSlot> {
+    let mut merged: Vec<TimeSlot> = Vec::new();
+
+    for slot in slots.into_iter().sorted_by_key(|slot| slot.start) {
+        match merged.last_mut() {
+            Some(last) if slot.start <= last.end => last.end = last.end.max(slot.end),
+            _ => merged.push(slot),
+        }
+    }
+
+    merged
+}
+
+/// Gaps between the busy slots within `from..to` lasting at least `min_duration`
+pub fn free_slots(
+    busy: &[TimeSlot],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    min_duration: Duration,
+) -> Vec<TimeSlot> {
+    let mut free = Vec::new();
+    let mut cursor = from;
+
+    for slot in busy {
+        if slot.end <= cursor {
+            continue;
+        }
+
+        if slot.start >= to {
+            break;
+        }
+
+        if slot.start > cursor && slot.start - cursor >= min_duration {
+            free.push(TimeSlot {
+                start: cursor,
+                end: slot.start,
+            });
+        }
+
+        cursor = cursor.max(slot.end);
+    }
+
+    if cursor < to && to - cursor >= min_duration {
+        free.push(TimeSlot {
+            start: cursor,
+            end: to,
+        });
+    }
+
+    free
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 1, 10, hour, 0, 0).unwrap()
+    }
+
+    fn slot(start: u32, end: u32) -> TimeSlot {
+        TimeSlot {
+            start: at(start),
+            end: at(end),
+        }
+    }
+
+    #[test]
+    fn test_merge_slots() {
+        let merged = merge_slots([slot(10, 12), slot(8, 9), slot(11, 13), slot(13, 14)]);
+
+        assert_eq!(merged, vec![slot(8, 9), slot(10, 14)]);
+    }
+
+    #[test]
+    fn test_free_slots() {
+        let busy = [slot(8, 9), slot(10, 12), slot(15, 16)];
+
+        assert_eq!(
+            free_slots(&busy, at(8), at(18), Duration::hours(1)),
+            vec![slot(9, 10), slot(12, 15), slot(16, 18)]
+        );
+
+        assert_eq!(
+            free_slots(&busy, at(9), at(16), Duration::hours(2)),
+            vec![slot(12, 15)]
+        );
+
+        assert!(free_slots(&busy, at(10), at(12), Duration::zero()).is_empty());
+    }
+}
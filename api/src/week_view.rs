@@ -0,0 +1,221 @@
+use crate::calendar::resolve_subscriptions::resolve_subscriptions;
+use crate::error::AppResult;
+use crate::shared_types::{
+    Activity, CalendarSubscription, DayView, PlacedActivity, WeekView, WeekViewQuery,
+};
+use crate::time_slots::busy_slots;
+use crate::AppState;
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use chrono_tz::Europe::Oslo;
+use itertools::Itertools;
+
+const MAX_WEEKS: u32 = 52;
+
+/// Places overlapping activities side by side, like a calendar app does
+///
+/// Activities that overlap, directly or through each other, form a cluster sharing a column count
+pub fn layout_day(activities: Vec<(String, Activity)>) -> Vec<PlacedActivity> {
+    let activities = activities
+        .into_iter()
+        .sorted_by(|(_, a), (_, b)| a.start.cmp(&b.start).then(b.end.cmp(&a.end)))
+        .collect::<Vec<_>>();
+
+    let mut placed = Vec::<PlacedActivity>::new();
+    let mut cluster_start = 0;
+    let mut cluster_end = None;
+    // End time of the last activity placed in each column of the current cluster
+    let mut column_ends = Vec::new();
+
+    let finish_cluster =
+        |placed: &mut Vec<PlacedActivity>, cluster_start: usize, columns: usize| {
+            for placed_activity in &mut placed[cluster_start..] {
+                placed_activity.column_count = columns as u32;
+            }
+        };
+
+    for (name, activity) in activities {
+        if cluster_end.is_some_and(|end| activity.start >= end) {
+            finish_cluster(&mut placed, cluster_start, column_ends.len());
+            cluster_start = placed.len();
+            column_ends.clear();
+        }
+
+        let column = match column_ends.iter().position(|end| activity.start >= *end) {
+            Some(column) => {
+                column_ends[column] = activity.end;
+                column
+            }
+            None => {
+                column_ends.push(activity.end);
+                column_ends.len() - 1
+            }
+        };
+
+        cluster_end = Some(match cluster_end {
+            Some(end) if cluster_start < placed.len() => activity.end.max(end),
+            _ => activity.end,
+        });
+
+        placed.push(PlacedActivity {
+            name,
+            activity,
+            column: column as u32,
+            column_count: 1,
+        });
+    }
+
+    finish_cluster(&mut placed, cluster_start, column_ends.len());
+
+    placed
+}
+
+fn day_view(date: NaiveDate, activities: Vec<(String, Activity)>) -> DayView {
+    let busy = busy_slots(activities.iter().map(|(_, activity)| activity));
+
+    let hours = busy
+        .iter()
+        .map(|slot| (slot.end - slot.start).num_minutes() as f64 / 60.0)
+        .sum();
+
+    DayView {
+        date,
+        first_start: busy.first().map(|slot| slot.start),
+        last_end: busy.last().map(|slot| slot.end),
+        hours,
+        activities: layout_day(activities),
+    }
+}
+
+/// Groups the activities into the days of the ISO weeks starting at `year`/`week`
+pub fn build_week_views(
+    activities: Vec<(String, Activity)>,
+    year: i32,
+    week: u32,
+    week_count: u32,
+) -> Vec<WeekView> {
+    let Some(first_monday) = NaiveDate::from_isoywd_opt(year, week, Weekday::Mon) else {
+        return Vec::new();
+    };
+
+    let mut activities_by_date = activities
+        .into_iter()
+        .into_group_map_by(|(_, activity)| activity.start.with_timezone(&Oslo).date_naive());
+
+    (0..week_count)
+        .map(|week_index| {
+            let monday = first_monday + Duration::weeks(week_index.into());
+            let iso_week = monday.iso_week();
+
+            let days = (0..7)
+                .map(|day| {
+                    let date = monday + Duration::days(day);
+                    let activities = activities_by_date.remove(&date).unwrap_or_default();
+
+                    day_view(date, activities)
+                })
+                .collect();
+
+            WeekView {
+                year: iso_week.year(),
+                week: iso_week.week(),
+                days,
+            }
+        })
+        .collect()
+}
+
+pub async fn week_views(app_state: &AppState, query: WeekViewQuery) -> AppResult<Vec<WeekView>> {
+    let subscriptions = query
+        .queries
+        .into_iter()
+        .map(CalendarSubscription::Course)
+        .collect();
+
+    let resolved_subscriptions = resolve_subscriptions(app_state, subscriptions).await?;
+
+    let activities = resolved_subscriptions
+        .iter()
+        .flat_map(|resolved_subscription| {
+            resolved_subscription.filtered_activities().map(|activity| {
+                let name = resolved_subscription
+                    .custom_name()
+                    .clone()
+                    .unwrap_or_else(|| activity.course_code.clone());
+
+                (name, activity.clone())
+            })
+        })
+        .unique_by(|(_, activity)| activity.id.clone())
+        .collect();
+
+    let week_count = query.week_count.unwrap_or(1).clamp(1, MAX_WEEKS);
+
+    Ok(build_week_views(
+        activities, query.year, query.week, week_count,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn activity(id: &str, start_hour: u32, end_hour: u32) -> (String, Activity) {
+        let activity = Activity {
+            id: id.to_owned(),
+            course_code: "TDT4100".to_owned(),
+            week: 2,
+            start: Utc.with_ymd_and_hms(2023, 1, 10, start_hour, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, 10, end_hour, 0, 0).unwrap(),
+            title: "Forelesning".to_owned(),
+            summary: String::new(),
+            staff_members: Vec::new(),
+            student_groups: Vec::new(),
+            rooms: Vec::new(),
+        };
+
+        ("TDT4100".to_owned(), activity)
+    }
+
+    fn columns(placed: &[PlacedActivity]) -> Vec<(&str, u32, u32)> {
+        placed
+            .iter()
+            .map(|placed| {
+                (
+                    placed.activity.id.as_str(),
+                    placed.column,
+                    placed.column_count,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_layout_day() {
+        let placed = layout_day(vec![
+            activity("a", 8, 10),
+            activity("b", 9, 11),
+            activity("c", 10, 12),
+            activity("d", 12, 14),
+        ]);
+
+        assert_eq!(
+            columns(&placed),
+            vec![("a", 0, 2), ("b", 1, 2), ("c", 0, 2), ("d", 0, 1)]
+        );
+    }
+
+    #[test]
+    fn test_build_week_views() {
+        let weeks = build_week_views(vec![activity("a", 8, 10), activity("b", 9, 11)], 2023, 2, 1);
+
+        assert_eq!(weeks.len(), 1);
+        assert_eq!(weeks[0].week, 2);
+
+        let tuesday = &weeks[0].days[1];
+        assert_eq!(tuesday.date, NaiveDate::from_ymd_opt(2023, 1, 10).unwrap());
+        assert_eq!(tuesday.activities.len(), 2);
+        assert_eq!(tuesday.hours, 3.0);
+        assert!(weeks[0].days[0].activities.is_empty());
+    }
+}